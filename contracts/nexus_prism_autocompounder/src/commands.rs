@@ -1,34 +1,135 @@
 use crate::{
     replies_id::ReplyId,
     state::{
-        load_config, load_gov_update, load_withdraw_action, remove_gov_update,
-        remove_withdraw_action, store_config, store_gov_update, store_withdraw_action, Config,
-        GovernanceUpdateState, WithdrawAction,
+        load_config, load_gov_update, load_harvest_context, load_state, load_withdraw_action,
+        remove_gov_update, remove_harvest_context, remove_withdraw_action, store_config,
+        store_gov_update, store_harvest, store_harvest_context, store_state, store_withdraw_action,
+        Config, GovernanceUpdateState, Harvest, HarvestContext, WithdrawAction,
     },
 };
+use astroport::{
+    asset::AssetInfo,
+    router::{SimulateSwapOperationsResponse, SwapOperation, MAX_SWAP_OPERATIONS},
+};
 use cosmwasm_bignumber::{Decimal256, Uint256};
 use cosmwasm_std::{
-    to_binary, Addr, CosmosMsg, Deps, DepsMut, Env, MessageInfo, Response, StdError, StdResult,
-    SubMsg, Uint128, WasmMsg,
+    to_binary, Addr, CosmosMsg, Decimal, Deps, DepsMut, Env, MessageInfo, Response, StdError,
+    StdResult, SubMsg, Uint128, WasmMsg,
 };
 use cw20::Cw20ReceiveMsg;
 use nexus_prism_protocol::{
-    cfg_addr,
-    common::{burn, get_time, mint, query_token_balance, query_token_supply, send, transfer},
+    cfg_addr, cfg_var,
+    common::{
+        burn, get_time, mint, query_token_balance, query_token_supply, send, send_wasm_msg,
+        transfer,
+    },
+    events::{AutocompounderDeposited, AutocompounderWithdrawn, TypedEvent},
     staking::StakerResponse,
 };
+use std::cmp::min;
 
+#[allow(clippy::too_many_arguments)]
 pub fn update_config(
     deps: DepsMut,
     mut config: Config,
     reward_compound_pair: Option<String>,
     staking_contract: Option<String>,
+    astroport_router: Option<Option<String>>,
+    reward_swap_operations: Option<Vec<SwapOperation>>,
+    reward_swap_max_spread: Option<Decimal>,
+    min_harvest_amount: Option<Uint128>,
+    max_swap_amount: Option<Option<Uint128>>,
 ) -> StdResult<Response> {
     cfg_addr!(deps, config, reward_compound_pair, staking_contract);
+
+    if let Some(astroport_router) = astroport_router {
+        config.astroport_router = astroport_router
+            .map(|addr| deps.api.addr_validate(&addr))
+            .transpose()?;
+    }
+
+    cfg_var!(
+        config,
+        reward_swap_operations,
+        reward_swap_max_spread,
+        min_harvest_amount
+    );
+
+    if let Some(max_swap_amount) = max_swap_amount {
+        if max_swap_amount == Some(Uint128::zero()) {
+            return Err(StdError::generic_err(
+                "max swap amount must be greater than 0",
+            ));
+        }
+        config.max_swap_amount = max_swap_amount;
+    }
+
+    validate_reward_swap_route(&config)?;
+
     store_config(deps.storage, &config)?;
     Ok(Response::new().add_attribute("action", "update_config"))
 }
 
+pub fn validate_reward_swap_route(config: &Config) -> StdResult<()> {
+    if config.reward_swap_max_spread.is_zero() || config.reward_swap_max_spread >= Decimal::one() {
+        return Err(StdError::generic_err(
+            "reward swap max spread must be greater than 0 and less than 1",
+        ));
+    }
+
+    let operations = &config.reward_swap_operations;
+    if operations.is_empty() {
+        return Ok(());
+    }
+
+    if config.astroport_router.is_none() {
+        return Err(StdError::generic_err(
+            "astroport router is required for reward swap operations",
+        ));
+    }
+
+    if operations.len() > MAX_SWAP_OPERATIONS {
+        return Err(StdError::generic_err("too many reward swap operations"));
+    }
+
+    let mut offer_asset_info = AssetInfo::Token {
+        contract_addr: config.reward_token.clone(),
+    };
+    for operation in operations {
+        match operation {
+            SwapOperation::AstroSwap {
+                offer_asset_info: operation_offer_asset_info,
+                ask_asset_info,
+            } => {
+                if *operation_offer_asset_info != offer_asset_info {
+                    return Err(StdError::generic_err(format!(
+                        "reward swap route is broken: expected {} to be offered, but got {}",
+                        offer_asset_info, operation_offer_asset_info,
+                    )));
+                }
+                offer_asset_info = ask_asset_info.clone();
+            }
+            SwapOperation::NativeSwap { .. } => {
+                return Err(StdError::generic_err(
+                    "native swaps are not supported in reward swap route",
+                ));
+            }
+        }
+    }
+
+    let compounding_asset_info = AssetInfo::Token {
+        contract_addr: config.compounding_token.clone(),
+    };
+    if offer_asset_info != compounding_asset_info {
+        return Err(StdError::generic_err(format!(
+            "reward swap route must end in {}, but ends in {}",
+            compounding_asset_info, offer_asset_info,
+        )));
+    }
+
+    Ok(())
+}
+
 pub fn update_governance(
     deps: DepsMut,
     env: Env,
@@ -76,19 +177,19 @@ pub fn receive_cw20_deposit(
     env: Env,
     info: MessageInfo,
     cw20_msg: Cw20ReceiveMsg,
+    recipient: Option<String>,
 ) -> StdResult<Response> {
     let config: Config = load_config(deps.storage)?;
     if info.sender != config.compounding_token {
         return Err(StdError::generic_err("unauthorized"));
     }
 
-    deposit_compounding_token(
-        deps,
-        env,
-        config,
-        Addr::unchecked(cw20_msg.sender),
-        cw20_msg.amount.into(),
-    )
+    let farmer = match recipient {
+        Some(recipient) => deps.api.addr_validate(&recipient)?,
+        None => Addr::unchecked(cw20_msg.sender),
+    };
+
+    deposit_compounding_token(deps, env, config, farmer, cw20_msg.amount.into())
 }
 
 pub fn deposit_compounding_token(
@@ -122,11 +223,19 @@ pub fn deposit_compounding_token(
             &config.compounding_token,
             &config.staking_contract,
             amount.into(),
-            &nexus_prism_protocol::staking::Cw20HookMsg::Bond {},
+            &nexus_prism_protocol::staking::Cw20HookMsg::Bond {
+                recipient: None,
+                lock_duration: None,
+            },
         )?)
         .add_attribute("action", "deposit_compounding_token")
-        .add_attribute("farmer", farmer)
-        .add_attribute("amount", amount))
+        .add_event(
+            AutocompounderDeposited {
+                farmer: farmer.to_string(),
+                compounding_token_amount: amount.into(),
+            }
+            .into_event(),
+        ))
 }
 
 pub fn receive_cw20_withdraw(
@@ -134,19 +243,20 @@ pub fn receive_cw20_withdraw(
     env: Env,
     info: MessageInfo,
     cw20_msg: Cw20ReceiveMsg,
+    recipient: Option<String>,
 ) -> StdResult<Response> {
     let config: Config = load_config(deps.storage)?;
     if info.sender != config.auto_compounding_token {
         return Err(StdError::generic_err("unauthorized"));
     }
 
-    withdraw_compounding_token(
-        deps,
-        env,
-        config,
-        Addr::unchecked(cw20_msg.sender),
-        cw20_msg.amount,
-    )
+    // compounding tokens are sent to farmer on withdraw
+    let farmer = match recipient {
+        Some(recipient) => deps.api.addr_validate(&recipient)?,
+        None => Addr::unchecked(cw20_msg.sender),
+    };
+
+    withdraw_compounding_token(deps, env, config, farmer, cw20_msg.amount)
 }
 
 pub fn withdraw_compounding_token(
@@ -159,14 +269,15 @@ pub fn withdraw_compounding_token(
     store_withdraw_action(
         deps.storage,
         WithdrawAction {
-            farmer,
+            farmer: farmer.clone(),
             auto_compounding_token_amount: amount,
         },
     )?;
 
     Ok(Response::new()
         .add_submessage(claim_rewards(&config.staking_contract)?)
-        .add_attributes(vec![("action", "claim_rewards")]))
+        .add_attributes(vec![("action", "claim_rewards")])
+        .add_attribute("farmer", farmer))
 }
 
 pub fn compound(deps: DepsMut, _env: Env, _info: MessageInfo) -> StdResult<Response> {
@@ -184,6 +295,7 @@ fn claim_rewards(staking_contract: &Addr) -> StdResult<SubMsg> {
             msg: to_binary(&nexus_prism_protocol::staking::ExecuteMsg::Anyone {
                 anyone_msg: nexus_prism_protocol::staking::AnyoneMsg::ClaimRewards {
                     recipient: None,
+                    msg: None,
                 },
             })?,
             funds: vec![],
@@ -192,6 +304,87 @@ fn claim_rewards(staking_contract: &Addr) -> StdResult<SubMsg> {
     ))
 }
 
+pub fn harvest_rewards(deps: DepsMut, env: Env, config: &Config) -> StdResult<Response> {
+    let reward_token_balance =
+        query_token_balance(deps.as_ref(), &config.reward_token, &env.contract.address);
+
+    // hold rewards until there is enough of them to be worth selling
+    if reward_token_balance.is_zero() || reward_token_balance < config.min_harvest_amount {
+        return withdraw(deps, env);
+    }
+
+    let rewards_to_sell = match config.max_swap_amount {
+        Some(max_swap_amount) => min(reward_token_balance, max_swap_amount),
+        None => reward_token_balance,
+    };
+
+    let state = load_state(deps.storage)?;
+    let harvest_context = HarvestContext {
+        rewards_claimed: reward_token_balance.saturating_sub(state.rewards_carried_over),
+        rewards_sold: rewards_to_sell,
+        rewards_carried_over: reward_token_balance - rewards_to_sell,
+        compounding_token_balance: query_token_balance(
+            deps.as_ref(),
+            &config.compounding_token,
+            &env.contract.address,
+        ),
+        compounding_token_staked: get_compounding_token_balance(
+            deps.as_ref(),
+            env,
+            &config.staking_contract,
+        )?,
+        auto_compounding_token_supply: query_token_supply(
+            deps.as_ref(),
+            &config.auto_compounding_token,
+        )?,
+    };
+    store_harvest_context(deps.storage, &harvest_context)?;
+
+    Ok(Response::new().add_submessage(sell_rewards(deps.as_ref(), config, rewards_to_sell)?))
+}
+
+pub fn sell_rewards(deps: Deps, config: &Config, amount: Uint128) -> StdResult<SubMsg> {
+    let swap_msg = match &config.astroport_router {
+        Some(astroport_router) if !config.reward_swap_operations.is_empty() => {
+            let simulation: SimulateSwapOperationsResponse = deps.querier.query_wasm_smart(
+                astroport_router,
+                &astroport::router::QueryMsg::SimulateSwapOperations {
+                    offer_amount: amount,
+                    operations: config.reward_swap_operations.clone(),
+                },
+            )?;
+            let minimum_receive =
+                simulation.amount * (Decimal::one() - config.reward_swap_max_spread);
+
+            send_wasm_msg(
+                &config.reward_token,
+                astroport_router,
+                amount,
+                &astroport::router::Cw20HookMsg::ExecuteSwapOperations {
+                    operations: config.reward_swap_operations.clone(),
+                    minimum_receive: Some(minimum_receive),
+                    to: None,
+                },
+            )?
+        }
+        _ => send_wasm_msg(
+            &config.reward_token,
+            &config.reward_compound_pair,
+            amount,
+            &astroport::pair::Cw20HookMsg::Swap {
+                belief_price: None,
+                max_spread: Some(config.reward_swap_max_spread),
+                to: None,
+            },
+        )?,
+    };
+
+    Ok(SubMsg::reply_on_success(
+        swap_msg,
+        ReplyId::RewardsSold.into(),
+    ))
+}
+
 pub fn withdraw(deps: DepsMut, env: Env) -> StdResult<Response> {
     let config = load_config(deps.storage)?;
 
@@ -208,7 +401,10 @@ pub fn withdraw(deps: DepsMut, env: Env) -> StdResult<Response> {
             &config.compounding_token,
             &config.staking_contract,
             compounding_token_balance,
-            &nexus_prism_protocol::staking::Cw20HookMsg::Bond {},
+            &nexus_prism_protocol::staking::Cw20HookMsg::Bond {
+                recipient: None,
+                lock_duration: None,
+            },
         )?)
     };
 
@@ -230,7 +426,9 @@ pub fn withdraw(deps: DepsMut, env: Env) -> StdResult<Response> {
                 contract_addr: config.staking_contract.to_string(),
                 msg: to_binary(&nexus_prism_protocol::staking::ExecuteMsg::Anyone {
                     anyone_msg: nexus_prism_protocol::staking::AnyoneMsg::Unbond {
-                        amount: compounding_token_to_withdraw.into(),
+                        amount: Some(compounding_token_to_withdraw.into()),
+                        recipient: None,
+                        claim_rewards: false,
                     },
                 })?,
                 funds: vec![],
@@ -245,18 +443,70 @@ pub fn withdraw(deps: DepsMut, env: Env) -> StdResult<Response> {
                 withdraw_action.auto_compounding_token_amount,
             )?)
             .add_attribute("action", "withdraw")
-            .add_attribute(
-                "compounding_token_amount_withdrawn",
-                compounding_token_to_withdraw,
-            )
-            .add_attribute(
-                "auto_compounding_token_amount_burned",
-                withdraw_action.auto_compounding_token_amount,
+            .add_event(
+                AutocompounderWithdrawn {
+                    farmer: withdraw_action.farmer.to_string(),
+                    compounding_token_amount: compounding_token_to_withdraw.into(),
+                    auto_compounding_token_burned: withdraw_action.auto_compounding_token_amount,
+                }
+                .into_event(),
             ))
     } else {
         Ok(resp)
     }
 }
+pub fn register_harvest(deps: DepsMut, env: &Env, config: &Config) -> StdResult<Harvest> {
+    let context = load_harvest_context(deps.storage)?;
+    remove_harvest_context(deps.storage);
+
+    let compounding_token_balance = query_token_balance(
+        deps.as_ref(),
+        &config.compounding_token,
+        &env.contract.address,
+    );
+    let compounding_token_received =
+        compounding_token_balance.saturating_sub(context.compounding_token_balance);
+
+    let harvest = Harvest {
+        time: get_time(&env.block),
+        rewards_claimed: context.rewards_claimed,
+        rewards_sold: context.rewards_sold,
+        compounding_token_received,
+        share_price_before: share_price(
+            context.compounding_token_staked,
+            context.auto_compounding_token_supply,
+        ),
+        share_price_after: share_price(
+            context.compounding_token_staked + compounding_token_received,
+            context.auto_compounding_token_supply,
+        ),
+    };
+
+    let mut state = load_state(deps.storage)?;
+    store_harvest(deps.storage, state.harvests_count, &harvest)?;
+
+    state.last_harvest_time = harvest.time;
+    state.harvests_count += 1;
+    state.total_rewards_claimed += harvest.rewards_claimed;
+    state.total_rewards_sold += harvest.rewards_sold;
+    state.total_compounding_token_received += harvest.compounding_token_received;
+    state.rewards_carried_over = context.rewards_carried_over;
+    store_state(deps.storage, &state)?;
+
+    Ok(harvest)
+}
+
+// compounding token amount per one auto compounding token
+pub fn share_price(
+    compounding_token_amount: Uint128,
+    auto_compounding_token_supply: Uint128,
+) -> Decimal {
+    if auto_compounding_token_supply.is_zero() {
+        return Decimal::one();
+    }
+    Decimal::from_ratio(compounding_token_amount, auto_compounding_token_supply)
+}
+
 pub fn get_compounding_token_balance(deps: Deps, env: Env, addr: &Addr) -> StdResult<Uint128> {
     let staker: StakerResponse = deps.querier.query_wasm_smart(
         addr,
@@ -266,3 +516,452 @@ pub fn get_compounding_token_balance(deps: Deps, env: Env, addr: &Addr) -> StdRe
     )?;
     Ok(staker.balance)
 }
+
+#[cfg(test)]
+mod tests {
+    use astroport::{asset::AssetInfo, router::SwapOperation};
+    use cosmwasm_std::{
+        from_binary,
+        testing::{mock_env, mock_info, MOCK_CONTRACT_ADDR},
+        to_binary, Addr, Binary, ContractResult, CosmosMsg, Decimal, DepsMut, Reply,
+        SubMsgExecutionResponse, Uint128,
+    };
+    use cw20::Cw20ReceiveMsg;
+    use nexus_prism_protocol::{
+        autocompounder::GovernanceMsg,
+        common::{mint, send_wasm_msg},
+        events::{parse_events, AutocompounderDeposited, AutocompounderHarvested},
+        staking::StakerResponse,
+        testing::{mock_dependencies, WasmMockQuerier},
+    };
+
+    use crate::{
+        commands::{
+            harvest_rewards, receive_cw20_deposit, receive_cw20_withdraw, register_harvest,
+            sell_rewards, update_config, validate_reward_swap_route,
+        },
+        replies_id::ReplyId,
+        state::{
+            load_config, load_harvest_context, load_harvests, load_state, load_withdraw_action,
+            remove_withdraw_action, store_config, store_harvest_context, store_state, Config,
+            HarvestContext, State,
+        },
+    };
+
+    fn token(addr: &str) -> AssetInfo {
+        AssetInfo::Token {
+            contract_addr: Addr::unchecked(addr),
+        }
+    }
+
+    fn hop(offer: &str, ask: &str) -> SwapOperation {
+        SwapOperation::AstroSwap {
+            offer_asset_info: token(offer),
+            ask_asset_info: token(ask),
+        }
+    }
+
+    fn config(reward_swap_operations: Vec<SwapOperation>) -> Config {
+        Config {
+            compounding_token: Addr::unchecked("nexprism"),
+            auto_compounding_token: Addr::unchecked("cnexprism"),
+            reward_token: Addr::unchecked("prism"),
+            reward_compound_pair: Addr::unchecked("pair"),
+            governance: Addr::unchecked("gov"),
+            staking_contract: Addr::unchecked("staking"),
+            astroport_router: Some(Addr::unchecked("router")),
+            reward_swap_operations,
+            reward_swap_max_spread: Decimal::percent(1),
+            min_harvest_amount: Uint128::zero(),
+            max_swap_amount: None,
+        }
+    }
+
+    #[test]
+    fn empty_route_is_valid() {
+        assert!(validate_reward_swap_route(&config(vec![])).is_ok());
+    }
+
+    #[test]
+    fn route_to_compounding_token_is_valid() {
+        let route = vec![hop("prism", "xprism"), hop("xprism", "nexprism")];
+        assert!(validate_reward_swap_route(&config(route)).is_ok());
+    }
+
+    #[test]
+    fn route_must_end_in_compounding_token() {
+        let route = vec![hop("prism", "xprism")];
+        assert!(validate_reward_swap_route(&config(route)).is_err());
+    }
+
+    #[test]
+    fn route_must_start_from_reward_token() {
+        let route = vec![hop("xprism", "nexprism")];
+        assert!(validate_reward_swap_route(&config(route)).is_err());
+    }
+
+    #[test]
+    fn route_hops_must_be_connected() {
+        let route = vec![hop("prism", "xprism"), hop("yluna", "nexprism")];
+        assert!(validate_reward_swap_route(&config(route)).is_err());
+    }
+
+    #[test]
+    fn max_spread_must_be_set() {
+        let mut config = config(vec![]);
+        config.reward_swap_max_spread = Decimal::zero();
+        assert!(validate_reward_swap_route(&config).is_err());
+        config.reward_swap_max_spread = Decimal::one();
+        assert!(validate_reward_swap_route(&config).is_err());
+    }
+
+    #[test]
+    fn pair_swap_is_protected() {
+        let deps = mock_dependencies(&[]);
+        let msg = sell_rewards(deps.as_ref(), &config(vec![]), Uint128::new(100)).unwrap();
+        assert_eq!(
+            msg.msg,
+            CosmosMsg::Wasm(
+                send_wasm_msg(
+                    &Addr::unchecked("prism"),
+                    &Addr::unchecked("pair"),
+                    Uint128::new(100),
+                    &astroport::pair::Cw20HookMsg::Swap {
+                        belief_price: None,
+                        max_spread: Some(Decimal::percent(1)),
+                        to: None,
+                    },
+                )
+                .unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn clear_astroport_router() {
+        let keep: GovernanceMsg = from_binary(&Binary::from(
+            br#"{"update_config":{"min_harvest_amount":"10"}}"#.as_ref(),
+        ))
+        .unwrap();
+        let clear: GovernanceMsg = from_binary(&Binary::from(
+            br#"{"update_config":{"astroport_router":null}}"#.as_ref(),
+        ))
+        .unwrap();
+        let router = |msg: GovernanceMsg| match msg {
+            GovernanceMsg::UpdateConfig {
+                astroport_router, ..
+            } => astroport_router,
+            _ => unreachable!(),
+        };
+        assert_eq!(router(keep), None);
+        assert_eq!(router(clear), Some(None));
+
+        let mut deps = mock_dependencies(&[]);
+        let update = |deps: DepsMut, config: Config, astroport_router: Option<Option<String>>| {
+            update_config(
+                deps,
+                config,
+                None,
+                None,
+                astroport_router,
+                None,
+                None,
+                None,
+                None,
+            )
+        };
+
+        // router is still used by the swap route
+        let route = vec![hop("prism", "nexprism")];
+        assert!(update(deps.as_mut(), config(route), Some(None)).is_err());
+
+        update(deps.as_mut(), config(vec![]), Some(None)).unwrap();
+        assert_eq!(load_config(&deps.storage).unwrap().astroport_router, None);
+
+        update(deps.as_mut(), config(vec![]), None).unwrap();
+        assert_eq!(
+            load_config(&deps.storage).unwrap().astroport_router,
+            Some(Addr::unchecked("router"))
+        );
+    }
+
+    #[test]
+    fn update_max_swap_amount() {
+        let mut deps = mock_dependencies(&[]);
+        let update = |deps: DepsMut, max_swap_amount: Option<Option<Uint128>>| {
+            let config = load_config(deps.storage).unwrap_or_else(|_| config(vec![]));
+            update_config(
+                deps,
+                config,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                max_swap_amount,
+            )
+        };
+
+        update(deps.as_mut(), Some(Some(Uint128::new(80)))).unwrap();
+        assert_eq!(
+            load_config(&deps.storage).unwrap().max_swap_amount,
+            Some(Uint128::new(80))
+        );
+
+        update(deps.as_mut(), None).unwrap();
+        assert_eq!(
+            load_config(&deps.storage).unwrap().max_swap_amount,
+            Some(Uint128::new(80))
+        );
+
+        assert!(update(deps.as_mut(), Some(Some(Uint128::zero()))).is_err());
+
+        update(deps.as_mut(), Some(None)).unwrap();
+        assert_eq!(load_config(&deps.storage).unwrap().max_swap_amount, None);
+    }
+
+    #[test]
+    fn hold_rewards_below_min_harvest_amount() {
+        let mut deps = mock_dependencies(&[]);
+        let mut config = config(vec![]);
+        config.min_harvest_amount = Uint128::new(100);
+        store_config(&mut deps.storage, &config).unwrap();
+        remove_withdraw_action(&mut deps.storage).unwrap();
+        deps.querier
+            .set_token_balance("prism", MOCK_CONTRACT_ADDR, 99);
+
+        let res = harvest_rewards(deps.as_mut(), mock_env(), &config).unwrap();
+        assert!(res.messages.is_empty());
+        assert!(load_harvest_context(&deps.storage).is_err());
+    }
+
+    #[test]
+    fn carry_rewards_over_max_swap_amount() {
+        let mut deps = mock_dependencies(&[]);
+        let mut config = config(vec![]);
+        config.min_harvest_amount = Uint128::new(100);
+        config.max_swap_amount = Some(Uint128::new(80));
+        store_config(&mut deps.storage, &config).unwrap();
+        store_state(
+            &mut deps.storage,
+            &State {
+                rewards_carried_over: Uint128::new(30),
+                ..State::default()
+            },
+        )
+        .unwrap();
+        deps.querier
+            .set_token_balance("prism", MOCK_CONTRACT_ADDR, 130);
+        deps.querier
+            .set_token_balance("nexprism", MOCK_CONTRACT_ADDR, 5);
+        deps.querier.set_token_supply("cnexprism", 500);
+        mock_staker(&mut deps.querier, 1_000);
+
+        let res = harvest_rewards(deps.as_mut(), mock_env(), &config).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![sell_rewards(deps.as_ref(), &config, Uint128::new(80)).unwrap()]
+        );
+        assert_eq!(
+            load_harvest_context(&deps.storage).unwrap(),
+            HarvestContext {
+                rewards_claimed: Uint128::new(100),
+                rewards_sold: Uint128::new(80),
+                rewards_carried_over: Uint128::new(50),
+                compounding_token_balance: Uint128::new(5),
+                compounding_token_staked: Uint128::new(1_000),
+                auto_compounding_token_supply: Uint128::new(500),
+            }
+        );
+
+        // carried over rewards are not claimed again
+        deps.querier
+            .set_token_balance("nexprism", MOCK_CONTRACT_ADDR, 45);
+        register_harvest(deps.as_mut(), &mock_env(), &config).unwrap();
+        let state = load_state(&deps.storage).unwrap();
+        assert_eq!(state.rewards_carried_over, Uint128::new(50));
+        assert_eq!(state.total_rewards_claimed, Uint128::new(100));
+        assert_eq!(state.total_rewards_sold, Uint128::new(80));
+    }
+
+    #[test]
+    fn route_requires_router() {
+        let mut config = config(vec![hop("prism", "nexprism")]);
+        config.astroport_router = None;
+        assert!(validate_reward_swap_route(&config).is_err());
+    }
+
+    fn mock_staker(querier: &mut WasmMockQuerier, balance: u128) {
+        querier.set_handler("staking", move |_| {
+            to_binary(&StakerResponse {
+                address: MOCK_CONTRACT_ADDR.to_string(),
+                balance: Uint128::new(balance),
+                virtual_pending_rewards: Decimal::zero(),
+                real_pending_rewards: Decimal::zero(),
+                payout_preference: None,
+            })
+        });
+    }
+
+    fn harvest_context() -> HarvestContext {
+        HarvestContext {
+            rewards_claimed: Uint128::new(100),
+            rewards_sold: Uint128::new(80),
+            rewards_carried_over: Uint128::new(20),
+            compounding_token_balance: Uint128::new(5),
+            compounding_token_staked: Uint128::new(1_000),
+            auto_compounding_token_supply: Uint128::new(500),
+        }
+    }
+
+    #[test]
+    fn register_harvest_share_price() {
+        let mut deps = mock_dependencies(&[]);
+        let config = config(vec![]);
+        store_harvest_context(&mut deps.storage, &harvest_context()).unwrap();
+        // 5 tokens were left from before the swap
+        deps.querier
+            .set_token_balance("nexprism", MOCK_CONTRACT_ADDR, 205);
+
+        let env = mock_env();
+        let harvest = register_harvest(deps.as_mut(), &env, &config).unwrap();
+        assert_eq!(harvest.compounding_token_received, Uint128::new(200));
+        assert_eq!(
+            harvest.share_price_before,
+            Decimal::from_ratio(2u128, 1u128)
+        );
+        assert_eq!(
+            harvest.share_price_after,
+            Decimal::from_ratio(12u128, 5u128)
+        );
+
+        let state = load_state(&deps.storage).unwrap();
+        assert_eq!(state.harvests_count, 1);
+        assert_eq!(state.last_harvest_time, env.block.time.seconds());
+        assert_eq!(state.total_rewards_claimed, Uint128::new(100));
+        assert_eq!(state.total_rewards_sold, Uint128::new(80));
+        assert_eq!(state.total_compounding_token_received, Uint128::new(200));
+        assert_eq!(state.rewards_carried_over, Uint128::new(20));
+        assert_eq!(
+            load_harvests(&deps.storage, None, 10).unwrap(),
+            vec![(0, harvest)]
+        );
+
+        // context is consumed
+        assert!(register_harvest(deps.as_mut(), &env, &config).is_err());
+    }
+
+    #[test]
+    fn share_price_without_supply() {
+        let mut deps = mock_dependencies(&[]);
+        let mut context = harvest_context();
+        context.compounding_token_staked = Uint128::zero();
+        context.auto_compounding_token_supply = Uint128::zero();
+        store_harvest_context(&mut deps.storage, &context).unwrap();
+
+        let harvest = register_harvest(deps.as_mut(), &mock_env(), &config(vec![])).unwrap();
+        assert_eq!(harvest.compounding_token_received, Uint128::zero());
+        assert_eq!(harvest.share_price_before, Decimal::one());
+        assert_eq!(harvest.share_price_after, Decimal::one());
+    }
+
+    #[test]
+    fn harvest_reply_emits_single_event() {
+        let mut deps = mock_dependencies(&[]);
+        store_config(&mut deps.storage, &config(vec![])).unwrap();
+        remove_withdraw_action(&mut deps.storage).unwrap();
+        store_harvest_context(&mut deps.storage, &harvest_context()).unwrap();
+        deps.querier
+            .set_token_balance("nexprism", MOCK_CONTRACT_ADDR, 205);
+        mock_staker(&mut deps.querier, 1_000);
+
+        let res = crate::contract::reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: ReplyId::RewardsSold.into(),
+                result: ContractResult::Ok(SubMsgExecutionResponse {
+                    events: vec![],
+                    data: None,
+                }),
+            },
+        )
+        .unwrap();
+
+        // received tokens are bonded back
+        assert_eq!(res.messages.len(), 1);
+        assert!(res.attributes.iter().all(|attr| attr.key != "action"));
+        assert_eq!(
+            parse_events::<AutocompounderHarvested>(&res.events).unwrap(),
+            vec![AutocompounderHarvested {
+                rewards_claimed: Uint128::new(100),
+                rewards_sold: Uint128::new(80),
+                compounding_token_received: Uint128::new(200),
+                share_price_before: Decimal::from_ratio(2u128, 1u128),
+                share_price_after: Decimal::from_ratio(12u128, 5u128),
+            }]
+        );
+    }
+
+    #[test]
+    fn deposit_for_recipient() {
+        let mut deps = mock_dependencies(&[]);
+        store_config(&mut deps.storage, &config(vec![])).unwrap();
+        deps.querier.set_token_supply("cnexprism", 500);
+        mock_staker(&mut deps.querier, 1_000);
+
+        let resp = receive_cw20_deposit(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("nexprism", &[]),
+            Cw20ReceiveMsg {
+                sender: "router".to_string(),
+                amount: Uint128::new(100),
+                msg: Binary::default(),
+            },
+            Some("alice".to_string()),
+        )
+        .unwrap();
+
+        // shares are minted to the recipient, tokens are bonded by the autocompounder itself
+        assert_eq!(
+            resp.messages[0],
+            mint(
+                &Addr::unchecked("cnexprism"),
+                &Addr::unchecked("alice"),
+                Uint128::new(50)
+            )
+            .unwrap()
+        );
+        let deposited: Vec<AutocompounderDeposited> = parse_events(&resp.events).unwrap();
+        assert_eq!(deposited[0].farmer, "alice");
+    }
+
+    #[test]
+    fn withdraw_for_recipient() {
+        let mut deps = mock_dependencies(&[]);
+        store_config(&mut deps.storage, &config(vec![])).unwrap();
+        remove_withdraw_action(&mut deps.storage).unwrap();
+
+        let withdraw = |deps: DepsMut, sender: &str| {
+            receive_cw20_withdraw(
+                deps,
+                mock_env(),
+                mock_info(sender, &[]),
+                Cw20ReceiveMsg {
+                    sender: "router".to_string(),
+                    amount: Uint128::new(100),
+                    msg: Binary::default(),
+                },
+                Some("alice".to_string()),
+            )
+        };
+        assert!(withdraw(deps.as_mut(), "nexprism").is_err());
+
+        withdraw(deps.as_mut(), "cnexprism").unwrap();
+        let action = load_withdraw_action(&deps.storage).unwrap().unwrap();
+        assert_eq!(action.farmer, Addr::unchecked("alice"));
+        assert_eq!(action.auto_compounding_token_amount, Uint128::new(100));
+    }
+}
@@ -1,20 +1,22 @@
 use crate::queries::{
     query_auto_compounding_token_value, query_compounding_token_value, query_config,
+    query_harvests, query_state,
 };
 use crate::replies_id::ReplyId;
 use crate::reply_response::MsgInstantiateContractResponse;
 use cosmwasm_std::{
     entry_point, from_binary, to_binary, Addr, Binary, Deps, DepsMut, Env, MessageInfo, Reply,
-    Response, StdError, StdResult, SubMsg,
+    Response, StdError, StdResult, SubMsg, Uint128,
 };
 use cw0::nonpayable;
 use cw2::{get_contract_version, set_contract_version};
-use nexus_prism_protocol::common::{instantiate_token, query_token_balance, send_wasm_msg};
+use nexus_prism_protocol::common::instantiate_token;
+use nexus_prism_protocol::events::{AutocompounderHarvested, TypedEvent};
 use protobuf::Message;
 
 use crate::commands::{
-    accept_governance, compound, receive_cw20_deposit, receive_cw20_withdraw, update_config,
-    withdraw,
+    accept_governance, compound, harvest_rewards, receive_cw20_deposit, receive_cw20_withdraw,
+    register_harvest, update_config, validate_reward_swap_route, withdraw,
 };
 use crate::state::Config;
 use crate::{
@@ -46,7 +48,13 @@ pub fn instantiate(
         reward_compound_pair: deps.api.addr_validate(&msg.reward_compound_pair)?,
         governance: deps.api.addr_validate(&msg.governance)?,
         staking_contract: deps.api.addr_validate(&msg.staking_contract)?,
+        astroport_router: None,
+        reward_swap_operations: vec![],
+        reward_swap_max_spread: msg.reward_swap_max_spread,
+        min_harvest_amount: Uint128::zero(),
+        max_swap_amount: None,
     };
+    validate_reward_swap_route(&config)?;
     store_config(deps.storage, &config)?;
     remove_withdraw_action(deps.storage)?;
 
@@ -80,8 +88,12 @@ fn receive_cw20(
     cw20_msg: Cw20ReceiveMsg,
 ) -> StdResult<Response> {
     match from_binary(&cw20_msg.msg)? {
-        Cw20HookMsg::Deposit {} => receive_cw20_deposit(deps, env, info, cw20_msg),
-        Cw20HookMsg::Withdraw {} => receive_cw20_withdraw(deps, env, info, cw20_msg),
+        Cw20HookMsg::Deposit { recipient } => {
+            receive_cw20_deposit(deps, env, info, cw20_msg, recipient)
+        }
+        Cw20HookMsg::Withdraw { recipient } => {
+            receive_cw20_withdraw(deps, env, info, cw20_msg, recipient)
+        }
     }
 }
 
@@ -105,7 +117,22 @@ pub fn execute(deps: DepsMut, env: Env, info: MessageInfo, msg: ExecuteMsg) -> S
                 GovernanceMsg::UpdateConfig {
                     reward_compound_pair,
                     staking_contract,
-                } => update_config(deps, config, reward_compound_pair, staking_contract),
+                    astroport_router,
+                    reward_swap_operations,
+                    reward_swap_max_spread,
+                    min_harvest_amount,
+                    max_swap_amount,
+                } => update_config(
+                    deps,
+                    config,
+                    reward_compound_pair,
+                    staking_contract,
+                    astroport_router,
+                    reward_swap_operations,
+                    reward_swap_max_spread,
+                    min_harvest_amount,
+                    max_swap_amount,
+                ),
 
                 GovernanceMsg::UpdateGovernanceContract {
                     gov_addr,
@@ -122,7 +149,7 @@ pub fn execute(deps: DepsMut, env: Env, info: MessageInfo, msg: ExecuteMsg) -> S
 }
 
 #[entry_point]
-pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> StdResult<Response> {
+pub fn reply(mut deps: DepsMut, env: Env, msg: Reply) -> StdResult<Response> {
     let reply_id =
         ReplyId::try_from(msg.id).map_err(|_| StdError::generic_err("unknown reply id"))?;
 
@@ -144,30 +171,22 @@ pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> StdResult<Response> {
                 .add_attribute("auto_compounding_token", config.auto_compounding_token))
         }
 
-        ReplyId::RewardsClaimed => {
-            let reward_token_balance =
-                query_token_balance(deps.as_ref(), &config.reward_token, &env.contract.address);
-
-            if reward_token_balance.is_zero() {
-                return withdraw(deps, env);
-            }
-
-            Ok(Response::new().add_submessage(SubMsg::reply_on_success(
-                send_wasm_msg(
-                    &config.reward_token,
-                    &config.reward_compound_pair,
-                    reward_token_balance,
-                    &astroport::pair::Cw20HookMsg::Swap {
-                        belief_price: None,
-                        max_spread: None,
-                        to: None,
-                    },
-                )?,
-                ReplyId::RewardsSold.into(),
-            )))
+        ReplyId::RewardsClaimed => harvest_rewards(deps, env, &config),
+
+        ReplyId::RewardsSold => {
+            let harvest = register_harvest(deps.branch(), &env, &config)?;
+
+            Ok(withdraw(deps, env)?.add_event(
+                AutocompounderHarvested {
+                    rewards_claimed: harvest.rewards_claimed,
+                    rewards_sold: harvest.rewards_sold,
+                    compounding_token_received: harvest.compounding_token_received,
+                    share_price_before: harvest.share_price_before,
+                    share_price_after: harvest.share_price_after,
+                }
+                .into_event(),
+            ))
         }
-
-        ReplyId::RewardsSold => withdraw(deps, env),
     }
 }
 
@@ -181,11 +200,15 @@ pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
         QueryMsg::CompoundingTokenValue { amount } => {
             to_binary(&query_compounding_token_value(deps, env, amount)?)
         }
+        QueryMsg::State {} => to_binary(&query_state(deps)?),
+        QueryMsg::Harvests { start_after, limit } => {
+            to_binary(&query_harvests(deps, start_after, limit)?)
+        }
     }
 }
 
 #[entry_point]
-pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> StdResult<Response> {
+pub fn migrate(deps: DepsMut, _env: Env, msg: MigrateMsg) -> StdResult<Response> {
     let ver = get_contract_version(deps.storage)?;
 
     if ver.contract != CONTRACT_NAME {
@@ -196,6 +219,14 @@ pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> StdResult<Response
         return Err(StdError::generic_err("Cannot upgrade from a newer version"));
     }
 
+    let mut config = load_config(deps.storage)?;
+    if let Some(reward_swap_max_spread) = msg.reward_swap_max_spread {
+        config.reward_swap_max_spread = reward_swap_max_spread;
+    }
+    // harvests must not run without swap protection
+    validate_reward_swap_route(&config)?;
+    store_config(deps.storage, &config)?;
+
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
 
     Ok(Response::new())
@@ -3,11 +3,18 @@ use cosmwasm_std::{Deps, Env, StdResult, Uint128};
 use nexus_prism_protocol::{
     autocompounder::{
         AutoCompoundingTokenValueResponse, CompoundingTokenValueResponse, ConfigResponse,
+        HarvestResponse, HarvestsResponse, StateResponse,
     },
     common::query_token_supply,
 };
 
-use crate::{commands::get_compounding_token_balance, state::load_config};
+use crate::{
+    commands::get_compounding_token_balance,
+    state::{load_config, load_harvests, load_state},
+};
+
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
 
 pub fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
     let config = load_config(deps.storage)?;
@@ -18,6 +25,11 @@ pub fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
         reward_compound_pair: config.reward_compound_pair.to_string(),
         governance: config.governance.to_string(),
         staking_contract: config.staking_contract.to_string(),
+        astroport_router: config.astroport_router.map(|addr| addr.to_string()),
+        reward_swap_operations: config.reward_swap_operations,
+        reward_swap_max_spread: config.reward_swap_max_spread,
+        min_harvest_amount: config.min_harvest_amount,
+        max_swap_amount: config.max_swap_amount,
     })
 }
 
@@ -75,3 +87,85 @@ pub fn query_compounding_token_value(
         auto_compounding_token_amount: auto_compounding_token_amount.into(),
     })
 }
+
+pub fn query_state(deps: Deps) -> StdResult<StateResponse> {
+    let state = load_state(deps.storage)?;
+    Ok(StateResponse {
+        last_harvest_time: state.last_harvest_time,
+        harvests_count: state.harvests_count,
+        total_rewards_claimed: state.total_rewards_claimed,
+        total_rewards_sold: state.total_rewards_sold,
+        total_compounding_token_received: state.total_compounding_token_received,
+        rewards_carried_over: state.rewards_carried_over,
+    })
+}
+
+pub fn query_harvests(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<HarvestsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+
+    let harvests = load_harvests(deps.storage, start_after, limit)?
+        .into_iter()
+        .map(|(id, harvest)| HarvestResponse {
+            id,
+            time: harvest.time,
+            rewards_claimed: harvest.rewards_claimed,
+            rewards_sold: harvest.rewards_sold,
+            compounding_token_received: harvest.compounding_token_received,
+            share_price_before: harvest.share_price_before,
+            share_price_after: harvest.share_price_after,
+        })
+        .collect();
+
+    Ok(HarvestsResponse { harvests })
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::{testing::mock_dependencies, Decimal, Uint128};
+
+    use crate::state::{store_harvest, Harvest};
+
+    use super::{query_harvests, DEFAULT_LIMIT, MAX_LIMIT};
+
+    fn ids(harvests: Vec<nexus_prism_protocol::autocompounder::HarvestResponse>) -> Vec<u64> {
+        harvests.into_iter().map(|harvest| harvest.id).collect()
+    }
+
+    #[test]
+    fn harvests_pagination() {
+        let mut deps = mock_dependencies(&[]);
+        for id in 0..35u64 {
+            let harvest = Harvest {
+                time: id * 100,
+                rewards_claimed: Uint128::new(10),
+                rewards_sold: Uint128::new(10),
+                compounding_token_received: Uint128::new(5),
+                share_price_before: Decimal::one(),
+                share_price_after: Decimal::one(),
+            };
+            store_harvest(&mut deps.storage, id, &harvest).unwrap();
+        }
+
+        let harvests = query_harvests(deps.as_ref(), None, None).unwrap().harvests;
+        assert_eq!(ids(harvests), (0..DEFAULT_LIMIT as u64).collect::<Vec<_>>());
+
+        let harvests = query_harvests(deps.as_ref(), None, Some(100))
+            .unwrap()
+            .harvests;
+        assert_eq!(ids(harvests), (0..MAX_LIMIT as u64).collect::<Vec<_>>());
+
+        let harvests = query_harvests(deps.as_ref(), Some(29), Some(100))
+            .unwrap()
+            .harvests;
+        assert_eq!(ids(harvests), (30..35).collect::<Vec<_>>());
+
+        let harvests = query_harvests(deps.as_ref(), Some(34), None)
+            .unwrap()
+            .harvests;
+        assert!(harvests.is_empty());
+    }
+}
@@ -1,7 +1,8 @@
-use cw_storage_plus::Item;
+use astroport::router::SwapOperation;
+use cw_storage_plus::{Bound, Item, Map, U64Key};
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{Addr, StdError, StdResult, Storage, Uint128};
+use cosmwasm_std::{Addr, Decimal, Order, StdError, StdResult, Storage, Uint128};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Config {
@@ -11,6 +12,16 @@ pub struct Config {
     pub reward_compound_pair: Addr,
     pub governance: Addr,
     pub staking_contract: Addr,
+    #[serde(default)]
+    pub astroport_router: Option<Addr>,
+    #[serde(default)]
+    pub reward_swap_operations: Vec<SwapOperation>,
+    #[serde(default)]
+    pub reward_swap_max_spread: Decimal,
+    #[serde(default)]
+    pub min_harvest_amount: Uint128,
+    #[serde(default)]
+    pub max_swap_amount: Option<Uint128>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -25,9 +36,44 @@ pub struct WithdrawAction {
     pub auto_compounding_token_amount: Uint128,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct State {
+    pub last_harvest_time: u64,
+    pub harvests_count: u64,
+    pub total_rewards_claimed: Uint128,
+    pub total_rewards_sold: Uint128,
+    pub total_compounding_token_received: Uint128,
+    // reward tokens left unsold after the last harvest
+    pub rewards_carried_over: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Harvest {
+    pub time: u64,
+    pub rewards_claimed: Uint128,
+    pub rewards_sold: Uint128,
+    pub compounding_token_received: Uint128,
+    pub share_price_before: Decimal,
+    pub share_price_after: Decimal,
+}
+
+// Balances taken between rewards claiming and selling, used to build `Harvest`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HarvestContext {
+    pub rewards_claimed: Uint128,
+    pub rewards_sold: Uint128,
+    pub rewards_carried_over: Uint128,
+    pub compounding_token_balance: Uint128,
+    pub compounding_token_staked: Uint128,
+    pub auto_compounding_token_supply: Uint128,
+}
+
 const KEY_CONFIG: Item<Config> = Item::new("config");
+const KEY_STATE: Item<State> = Item::new("state");
 const KEY_WITHDRAW_ACTION: Item<Option<WithdrawAction>> = Item::new("withdraw_action");
 const KEY_GOVERNANCE_UPDATE: Item<GovernanceUpdateState> = Item::new("gov_update");
+const KEY_HARVEST_CONTEXT: Item<HarvestContext> = Item::new("harvest_context");
+const HARVESTS: Map<U64Key, Harvest> = Map::new("harvests");
 
 pub fn load_config(storage: &dyn Storage) -> StdResult<Config> {
     KEY_CONFIG.load(storage)
@@ -37,6 +83,57 @@ pub fn store_config(storage: &mut dyn Storage, config: &Config) -> StdResult<()>
     KEY_CONFIG.save(storage, config)
 }
 
+pub fn load_state(storage: &dyn Storage) -> StdResult<State> {
+    KEY_STATE
+        .may_load(storage)
+        .map(|res| res.unwrap_or_default())
+}
+
+pub fn store_state(storage: &mut dyn Storage, state: &State) -> StdResult<()> {
+    KEY_STATE.save(storage, state)
+}
+
+pub fn load_harvest_context(storage: &dyn Storage) -> StdResult<HarvestContext> {
+    KEY_HARVEST_CONTEXT.load(storage)
+}
+
+pub fn store_harvest_context(
+    storage: &mut dyn Storage,
+    harvest_context: &HarvestContext,
+) -> StdResult<()> {
+    KEY_HARVEST_CONTEXT.save(storage, harvest_context)
+}
+
+pub fn remove_harvest_context(storage: &mut dyn Storage) {
+    KEY_HARVEST_CONTEXT.remove(storage)
+}
+
+pub fn store_harvest(storage: &mut dyn Storage, id: u64, harvest: &Harvest) -> StdResult<()> {
+    HARVESTS.save(storage, U64Key::new(id), harvest)
+}
+
+pub fn load_harvests(
+    storage: &dyn Storage,
+    start_after: Option<u64>,
+    limit: usize,
+) -> StdResult<Vec<(u64, Harvest)>> {
+    HARVESTS
+        .range(
+            storage,
+            start_after.map(Bound::exclusive_int),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| {
+            let (key, harvest) = item?;
+            let mut id = [0u8; 8];
+            id.copy_from_slice(&key);
+            Ok((u64::from_be_bytes(id), harvest))
+        })
+        .collect()
+}
+
 pub fn load_withdraw_action(storage: &dyn Storage) -> StdResult<Option<WithdrawAction>> {
     KEY_WITHDRAW_ACTION.load(storage)
 }
@@ -181,7 +181,10 @@ impl Sdk {
                 CosmosMsg::Wasm(WasmMsg::Execute {
                     contract_addr: NASSET_TOKEN_REWARDS_ADDR.to_string(),
                     msg: to_binary(&NAssetTokenRewardsExecuteMsg::Anyone {
-                        anyone_msg: NAssetTokenRewardsAnyoneMsg::ClaimRewards { recipient: None },
+                        anyone_msg: NAssetTokenRewardsAnyoneMsg::ClaimRewards {
+                            recipient: None,
+                            msg: None,
+                        },
                     })
                     .unwrap(),
                     funds: vec![],
@@ -266,7 +269,10 @@ impl Sdk {
                 CosmosMsg::Wasm(WasmMsg::Execute {
                     contract_addr: NASSET_TOKEN_REWARDS_ADDR.to_string(),
                     msg: to_binary(&NAssetTokenRewardsExecuteMsg::Anyone {
-                        anyone_msg: NAssetTokenRewardsAnyoneMsg::ClaimRewards { recipient: None },
+                        anyone_msg: NAssetTokenRewardsAnyoneMsg::ClaimRewards {
+                            recipient: None,
+                            msg: None,
+                        },
                     })
                     .unwrap(),
                     funds: vec![],
@@ -304,7 +310,10 @@ impl Sdk {
                 CosmosMsg::Wasm(WasmMsg::Execute {
                     contract_addr: NASSET_TOKEN_REWARDS_ADDR.to_string(),
                     msg: to_binary(&NAssetTokenRewardsExecuteMsg::Anyone {
-                        anyone_msg: NAssetTokenRewardsAnyoneMsg::ClaimRewards { recipient: None },
+                        anyone_msg: NAssetTokenRewardsAnyoneMsg::ClaimRewards {
+                            recipient: None,
+                            msg: None,
+                        },
                     })
                     .unwrap(),
                     funds: vec![],
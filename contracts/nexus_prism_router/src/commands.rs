@@ -0,0 +1,731 @@
+use cosmwasm_std::{
+    to_binary, Addr, Deps, DepsMut, Env, Response, StdError, StdResult, SubMsg, Uint128, WasmMsg,
+};
+use nexus_prism_protocol::{
+    common::{query_token_balance, send, transfer},
+    router::{CallbackMsg, ExecuteMsg, Operation},
+};
+
+use crate::{
+    error::ContractError,
+    state::{
+        load_config, load_route_context, remove_route_context, save_route_context, Autocompounder,
+        Contracts, RouteContext,
+    },
+};
+
+pub fn load_contracts(deps: Deps) -> StdResult<Contracts> {
+    let config = load_config(deps.storage)?;
+    let vault_config: nexus_prism_protocol::vault::ConfigResponse = deps.querier.query_wasm_smart(
+        &config.vault,
+        &nexus_prism_protocol::vault::QueryMsg::Config {},
+    )?;
+
+    Ok(Contracts {
+        vault: config.vault,
+        prism_governance: config.prism_governance,
+        nexprism_xprism_pair: config.nexprism_xprism_pair,
+
+        prism_token: Addr::unchecked(vault_config.prism_token),
+        xprism_token: Addr::unchecked(vault_config.xprism_token),
+        nexprism_token: Addr::unchecked(vault_config.nexprism_token),
+        yluna_token: Addr::unchecked(vault_config.yluna_token),
+        nyluna_token: Addr::unchecked(vault_config.nyluna_token),
+
+        nexprism_staking: Addr::unchecked(vault_config.nexprism_staking),
+        nyluna_staking: Addr::unchecked(vault_config.nyluna_staking),
+
+        nexprism_autocompounder: load_autocompounder(deps, vault_config.nexprism_autocompounder)?,
+        nyluna_autocompounder: load_autocompounder(deps, vault_config.nyluna_autocompounder)?,
+    })
+}
+
+fn load_autocompounder(deps: Deps, contract: Option<String>) -> StdResult<Option<Autocompounder>> {
+    match contract {
+        Some(contract) => {
+            let config: nexus_prism_protocol::autocompounder::ConfigResponse =
+                deps.querier.query_wasm_smart(
+                    &contract,
+                    &nexus_prism_protocol::autocompounder::QueryMsg::Config {},
+                )?;
+            Ok(Some(Autocompounder {
+                contract: Addr::unchecked(contract),
+                token: Addr::unchecked(config.auto_compounding_token),
+            }))
+        }
+        None => Ok(None),
+    }
+}
+
+impl Contracts {
+    fn autocompounder(&self, token: &Addr) -> Option<&Autocompounder> {
+        if *token == self.nexprism_token {
+            self.nexprism_autocompounder.as_ref()
+        } else if *token == self.nyluna_token {
+            self.nyluna_autocompounder.as_ref()
+        } else {
+            None
+        }
+    }
+
+    fn staking(&self, token: &Addr) -> Option<&Addr> {
+        if *token == self.nexprism_token {
+            Some(&self.nexprism_staking)
+        } else if *token == self.nyluna_token {
+            Some(&self.nyluna_staking)
+        } else {
+            None
+        }
+    }
+
+    // Operations converting `token` to nexPRISM and bonding it.
+    fn restake_operations(&self, token: &Addr) -> Option<Vec<Operation>> {
+        if *token == self.prism_token {
+            Some(vec![
+                Operation::MintXprism {},
+                Operation::VaultDeposit {},
+                Operation::Stake {},
+            ])
+        } else if *token == self.xprism_token {
+            Some(vec![Operation::VaultDeposit {}, Operation::Stake {}])
+        } else if *token == self.nexprism_token {
+            Some(vec![Operation::Stake {}])
+        } else {
+            None
+        }
+    }
+}
+
+pub enum Output {
+    Token(Addr),
+    Staked,
+}
+
+// Token that the operation gives for `offer_token`.
+pub fn operation_output(
+    contracts: &Contracts,
+    offer_token: &Addr,
+    operation: &Operation,
+) -> Option<Output> {
+    match operation {
+        Operation::MintXprism {} if *offer_token == contracts.prism_token => {
+            Some(Output::Token(contracts.xprism_token.clone()))
+        }
+        Operation::VaultDeposit {} if *offer_token == contracts.xprism_token => {
+            Some(Output::Token(contracts.nexprism_token.clone()))
+        }
+        Operation::VaultDeposit {} if *offer_token == contracts.yluna_token => {
+            Some(Output::Token(contracts.nyluna_token.clone()))
+        }
+        Operation::SwapNexprismXprism { .. } if *offer_token == contracts.xprism_token => {
+            Some(Output::Token(contracts.nexprism_token.clone()))
+        }
+        Operation::SwapNexprismXprism { .. } if *offer_token == contracts.nexprism_token => {
+            Some(Output::Token(contracts.xprism_token.clone()))
+        }
+        Operation::Autocompound {} => contracts
+            .autocompounder(offer_token)
+            .map(|autocompounder| Output::Token(autocompounder.token.clone())),
+        Operation::Stake {} => contracts.staking(offer_token).map(|_| Output::Staked),
+        _ => None,
+    }
+}
+
+// Operations to execute, `ClaimAndRestake` is expanded into the ones it consists of.
+pub struct Route {
+    pub operations: Vec<Operation>,
+    pub offer_tokens: Vec<Addr>,
+    pub final_token: Addr,
+    pub staked: bool,
+}
+
+pub fn build_route(
+    contracts: &Contracts,
+    offer_token: Addr,
+    operations: &[Operation],
+) -> Result<Route, ContractError> {
+    if operations.is_empty() {
+        return Err(ContractError::NoOperations {});
+    }
+
+    let mut route_operations = vec![];
+    let mut offer_tokens = vec![];
+    let mut token = offer_token;
+    for (index, operation) in operations.iter().enumerate() {
+        let is_last = index == operations.len() - 1;
+        if *operation == (Operation::ClaimAndRestake {}) {
+            let restake = match contracts.restake_operations(&token) {
+                Some(restake) if is_last => restake,
+                _ => {
+                    return Err(ContractError::InvalidOperation {
+                        index,
+                        token: token.to_string(),
+                    })
+                }
+            };
+            let restake_route = build_route(contracts, token, &restake)?;
+            route_operations.extend(restake_route.operations);
+            offer_tokens.extend(restake_route.offer_tokens);
+            return Ok(Route {
+                operations: route_operations,
+                offer_tokens,
+                final_token: restake_route.final_token,
+                staked: true,
+            });
+        }
+
+        route_operations.push(operation.clone());
+        offer_tokens.push(token.clone());
+        match operation_output(contracts, &token, operation) {
+            Some(Output::Token(ask_token)) => token = ask_token,
+            Some(Output::Staked) if is_last => {
+                return Ok(Route {
+                    operations: route_operations,
+                    offer_tokens,
+                    final_token: token,
+                    staked: true,
+                })
+            }
+            _ => {
+                return Err(ContractError::InvalidOperation {
+                    index,
+                    token: token.to_string(),
+                })
+            }
+        }
+    }
+
+    Ok(Route {
+        operations: route_operations,
+        offer_tokens,
+        final_token: token,
+        staked: false,
+    })
+}
+
+fn callback(env: &Env, msg: CallbackMsg) -> StdResult<SubMsg> {
+    Ok(SubMsg::new(WasmMsg::Execute {
+        contract_addr: env.contract.address.to_string(),
+        msg: to_binary(&ExecuteMsg::Callback(msg))?,
+        funds: vec![],
+    }))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn execute_operations(
+    deps: DepsMut,
+    env: Env,
+    contracts: &Contracts,
+    offer_token: Addr,
+    amount: Uint128,
+    sender: String,
+    operations: Vec<Operation>,
+    minimum_receive: Option<Uint128>,
+    to: Option<String>,
+) -> Result<Response, ContractError> {
+    let sender = deps.api.addr_validate(&sender)?;
+    let to = match to {
+        Some(to) => deps.api.addr_validate(&to)?,
+        // claimed rewards would be bonded for staking contract itself
+        None if sender == contracts.nexprism_staking || sender == contracts.nyluna_staking => {
+            return Err(ContractError::RecipientRequired {})
+        }
+        None => sender,
+    };
+    let route = build_route(contracts, offer_token.clone(), &operations)?;
+
+    // router balance could have the same token before
+    let balance = query_token_balance(deps.as_ref(), &offer_token, &env.contract.address);
+    save_route_context(
+        deps.storage,
+        &RouteContext {
+            token: offer_token,
+            balance_before: balance.checked_sub(amount).map_err(StdError::from)?,
+            contracts: contracts.clone(),
+        },
+    )?;
+
+    let mut msgs = route
+        .operations
+        .into_iter()
+        .map(|operation| {
+            callback(
+                &env,
+                CallbackMsg::ExecuteOperation {
+                    operation,
+                    to: to.to_string(),
+                },
+            )
+        })
+        .collect::<StdResult<Vec<_>>>()?;
+
+    if let Some(minimum_receive) = minimum_receive {
+        let assertion = callback(&env, CallbackMsg::AssertMinimumReceive { minimum_receive })?;
+        // staked amount is checked right before staking
+        if route.staked {
+            msgs.insert(msgs.len() - 1, assertion);
+        } else {
+            msgs.push(assertion);
+        }
+    }
+
+    if !route.staked {
+        msgs.push(callback(
+            &env,
+            CallbackMsg::SendReceived { to: to.to_string() },
+        )?);
+    }
+
+    Ok(Response::new()
+        .add_submessages(msgs)
+        .add_attribute("action", "execute_operations")
+        .add_attribute("to", to))
+}
+
+// Route context and amount of its token received by the last operation.
+fn received(deps: Deps, env: &Env) -> StdResult<(RouteContext, Uint128)> {
+    let context = load_route_context(deps.storage)?;
+    let balance = query_token_balance(deps, &context.token, &env.contract.address);
+    let amount = balance.checked_sub(context.balance_before)?;
+    Ok((context, amount))
+}
+
+pub fn execute_operation(
+    deps: DepsMut,
+    env: Env,
+    operation: Operation,
+    to: String,
+) -> Result<Response, ContractError> {
+    let (context, amount) = received(deps.as_ref(), &env)?;
+    let contracts = &context.contracts;
+    let offer_token = context.token.clone();
+    if amount.is_zero() {
+        return Err(ContractError::NothingToOperate {
+            token: offer_token.to_string(),
+        });
+    }
+
+    // the next operation spends only what this one gives
+    match operation_output(contracts, &offer_token, &operation) {
+        Some(Output::Token(ask_token)) => {
+            let balance_before =
+                query_token_balance(deps.as_ref(), &ask_token, &env.contract.address);
+            save_route_context(
+                deps.storage,
+                &RouteContext {
+                    token: ask_token,
+                    balance_before,
+                    contracts: contracts.clone(),
+                },
+            )?;
+        }
+        _ => remove_route_context(deps.storage),
+    }
+
+    let msg = match operation {
+        Operation::MintXprism {} => send(
+            &offer_token,
+            &contracts.prism_governance,
+            amount,
+            &prism_protocol::gov::Cw20HookMsg::MintXprism { receiver: None },
+        )?,
+        Operation::VaultDeposit {} => send(
+            &offer_token,
+            &contracts.vault,
+            amount,
+            &nexus_prism_protocol::vault::Cw20HookMsg::Deposit {
+                recipient: None,
+                then: None,
+                best_route: false,
+            },
+        )?,
+        Operation::SwapNexprismXprism { max_spread } => send(
+            &offer_token,
+            &contracts.nexprism_xprism_pair,
+            amount,
+            &astroport::pair::Cw20HookMsg::Swap {
+                belief_price: None,
+                max_spread,
+                to: None,
+            },
+        )?,
+        Operation::Autocompound {} => {
+            let autocompounder = contracts.autocompounder(&offer_token).ok_or_else(|| {
+                ContractError::NothingToOperate {
+                    token: offer_token.to_string(),
+                }
+            })?;
+            send(
+                &offer_token,
+                &autocompounder.contract,
+                amount,
+                &nexus_prism_protocol::autocompounder::Cw20HookMsg::Deposit { recipient: None },
+            )?
+        }
+        Operation::Stake {} => {
+            let staking =
+                contracts
+                    .staking(&offer_token)
+                    .ok_or_else(|| ContractError::NothingToOperate {
+                        token: offer_token.to_string(),
+                    })?;
+            send(
+                &offer_token,
+                staking,
+                amount,
+                &nexus_prism_protocol::staking::Cw20HookMsg::Bond {
+                    recipient: Some(to),
+                    lock_duration: None,
+                },
+            )?
+        }
+        // expanded by `build_route`
+        Operation::ClaimAndRestake {} => {
+            return Err(ContractError::NothingToOperate {
+                token: offer_token.to_string(),
+            })
+        }
+    };
+
+    Ok(Response::new()
+        .add_submessage(msg)
+        .add_attribute("action", "execute_operation")
+        .add_attribute("offer_token", offer_token)
+        .add_attribute("amount", amount))
+}
+
+pub fn assert_minimum_receive(
+    deps: DepsMut,
+    env: Env,
+    minimum_receive: Uint128,
+) -> Result<Response, ContractError> {
+    let (_, received) = received(deps.as_ref(), &env)?;
+    if received < minimum_receive {
+        return Err(ContractError::MinimumReceiveAssertion {
+            minimum_receive,
+            received,
+        });
+    }
+
+    Ok(Response::new())
+}
+
+// Operations spend everything they receive, so only the last output is left.
+pub fn send_received(deps: DepsMut, env: Env, to: String) -> Result<Response, ContractError> {
+    let to = Addr::unchecked(to);
+    let (context, amount) = received(deps.as_ref(), &env)?;
+    let token = context.token;
+    remove_route_context(deps.storage);
+
+    let mut resp = Response::new().add_attribute("action", "send_received");
+    if !amount.is_zero() {
+        resp = resp
+            .add_submessage(transfer(&token, &to, amount)?)
+            .add_attribute(token.to_string(), amount);
+    }
+
+    Ok(resp.add_attribute("to", to))
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::{
+        testing::{mock_env, MOCK_CONTRACT_ADDR},
+        Addr, Uint128,
+    };
+    use nexus_prism_protocol::{
+        common::{send, transfer},
+        router::Operation,
+        testing::mock_dependencies,
+    };
+
+    use super::{build_route, execute_operation, execute_operations, send_received};
+    use crate::{
+        error::ContractError,
+        state::{load_route_context, save_route_context, Autocompounder, Contracts, RouteContext},
+    };
+
+    fn contracts() -> Contracts {
+        Contracts {
+            vault: Addr::unchecked("vault"),
+            prism_governance: Addr::unchecked("prism_gov"),
+            nexprism_xprism_pair: Addr::unchecked("pair"),
+            prism_token: Addr::unchecked("prism"),
+            xprism_token: Addr::unchecked("xprism"),
+            nexprism_token: Addr::unchecked("nexprism"),
+            yluna_token: Addr::unchecked("yluna"),
+            nyluna_token: Addr::unchecked("nyluna"),
+            nexprism_staking: Addr::unchecked("nexprism_staking"),
+            nyluna_staking: Addr::unchecked("nyluna_staking"),
+            nexprism_autocompounder: Some(Autocompounder {
+                contract: Addr::unchecked("nexprism_autocompounder"),
+                token: Addr::unchecked("cnexprism"),
+            }),
+            nyluna_autocompounder: None,
+        }
+    }
+
+    #[test]
+    fn prism_to_staked_nexprism() {
+        let route = build_route(
+            &contracts(),
+            Addr::unchecked("prism"),
+            &[
+                Operation::MintXprism {},
+                Operation::VaultDeposit {},
+                Operation::Stake {},
+            ],
+        )
+        .unwrap();
+
+        assert!(route.staked);
+        assert_eq!(route.final_token, Addr::unchecked("nexprism"));
+        assert_eq!(
+            route.offer_tokens,
+            vec![
+                Addr::unchecked("prism"),
+                Addr::unchecked("xprism"),
+                Addr::unchecked("nexprism")
+            ]
+        );
+    }
+
+    #[test]
+    fn xprism_swap_to_autocompounder() {
+        let route = build_route(
+            &contracts(),
+            Addr::unchecked("xprism"),
+            &[
+                Operation::SwapNexprismXprism { max_spread: None },
+                Operation::Autocompound {},
+            ],
+        )
+        .unwrap();
+
+        assert!(!route.staked);
+        assert_eq!(route.final_token, Addr::unchecked("cnexprism"));
+    }
+
+    #[test]
+    fn stake_must_be_last() {
+        let err = build_route(
+            &contracts(),
+            Addr::unchecked("nexprism"),
+            &[Operation::Stake {}, Operation::Autocompound {}],
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(
+            err,
+            ContractError::InvalidOperation {
+                index: 0,
+                token: "nexprism".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn operation_must_accept_offer_token() {
+        let err = build_route(
+            &contracts(),
+            Addr::unchecked("prism"),
+            &[Operation::VaultDeposit {}],
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(
+            err,
+            ContractError::InvalidOperation {
+                index: 0,
+                token: "prism".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn missing_autocompounder() {
+        assert!(build_route(
+            &contracts(),
+            Addr::unchecked("nyluna"),
+            &[Operation::Autocompound {}],
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn claim_and_restake_is_expanded() {
+        let route = build_route(
+            &contracts(),
+            Addr::unchecked("prism"),
+            &[Operation::ClaimAndRestake {}],
+        )
+        .unwrap();
+        assert!(route.staked);
+        assert_eq!(
+            route.operations,
+            vec![
+                Operation::MintXprism {},
+                Operation::VaultDeposit {},
+                Operation::Stake {}
+            ]
+        );
+        assert_eq!(route.final_token, Addr::unchecked("nexprism"));
+
+        let route = build_route(
+            &contracts(),
+            Addr::unchecked("xprism"),
+            &[Operation::ClaimAndRestake {}],
+        )
+        .unwrap();
+        assert_eq!(
+            route.operations,
+            vec![Operation::VaultDeposit {}, Operation::Stake {}]
+        );
+
+        // nothing to restake nyLUNA rewards into
+        assert!(build_route(
+            &contracts(),
+            Addr::unchecked("nyluna"),
+            &[Operation::ClaimAndRestake {}],
+        )
+        .is_err());
+        assert_eq!(
+            build_route(
+                &contracts(),
+                Addr::unchecked("prism"),
+                &[Operation::ClaimAndRestake {}, Operation::MintXprism {}],
+            )
+            .err()
+            .unwrap(),
+            ContractError::InvalidOperation {
+                index: 0,
+                token: "prism".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn route_starts_from_received_amount() {
+        let mut deps = mock_dependencies(&[]);
+        deps.querier
+            .set_token_balance("prism", MOCK_CONTRACT_ADDR, 150);
+
+        let err = execute_operations(
+            deps.as_mut(),
+            mock_env(),
+            &contracts(),
+            Addr::unchecked("prism"),
+            Uint128::new(100),
+            "nexprism_staking".to_string(),
+            vec![Operation::ClaimAndRestake {}],
+            None,
+            None,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err, ContractError::RecipientRequired {});
+
+        let resp = execute_operations(
+            deps.as_mut(),
+            mock_env(),
+            &contracts(),
+            Addr::unchecked("prism"),
+            Uint128::new(100),
+            "nexprism_staking".to_string(),
+            vec![Operation::ClaimAndRestake {}],
+            Some(Uint128::new(90)),
+            Some("alice".to_string()),
+        )
+        .unwrap();
+        // three operations with assertion before staking, nothing is sent back
+        assert_eq!(resp.messages.len(), 4);
+        assert_eq!(
+            load_route_context(&deps.storage).unwrap(),
+            RouteContext {
+                token: Addr::unchecked("prism"),
+                balance_before: Uint128::new(50),
+                contracts: contracts(),
+            }
+        );
+    }
+
+    #[test]
+    fn operation_spends_received_amount() {
+        let mut deps = mock_dependencies(&[]);
+        // 1000 xPRISM were on router balance before the route
+        deps.querier
+            .set_token_balance("xprism", MOCK_CONTRACT_ADDR, 1_100);
+        deps.querier
+            .set_token_balance("nexprism", MOCK_CONTRACT_ADDR, 50);
+        save_route_context(
+            &mut deps.storage,
+            &RouteContext {
+                token: Addr::unchecked("xprism"),
+                balance_before: Uint128::new(1_000),
+                contracts: contracts(),
+            },
+        )
+        .unwrap();
+
+        // vault isn't mocked, contracts are taken from the route context
+        let resp = execute_operation(
+            deps.as_mut(),
+            mock_env(),
+            Operation::VaultDeposit {},
+            "alice".to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            resp.messages,
+            vec![send(
+                &Addr::unchecked("xprism"),
+                &Addr::unchecked("vault"),
+                Uint128::new(100),
+                &nexus_prism_protocol::vault::Cw20HookMsg::Deposit {
+                    recipient: None,
+                    then: None,
+                    best_route: false,
+                },
+            )
+            .unwrap()]
+        );
+        assert_eq!(
+            load_route_context(&deps.storage).unwrap(),
+            RouteContext {
+                token: Addr::unchecked("nexprism"),
+                balance_before: Uint128::new(50),
+                contracts: contracts(),
+            }
+        );
+    }
+
+    #[test]
+    fn send_only_received() {
+        let mut deps = mock_dependencies(&[]);
+        deps.querier
+            .set_token_balance("nexprism", MOCK_CONTRACT_ADDR, 80);
+        save_route_context(
+            &mut deps.storage,
+            &RouteContext {
+                token: Addr::unchecked("nexprism"),
+                balance_before: Uint128::new(50),
+                contracts: contracts(),
+            },
+        )
+        .unwrap();
+
+        let resp = send_received(deps.as_mut(), mock_env(), "alice".to_string()).unwrap();
+        assert_eq!(
+            resp.messages,
+            vec![transfer(
+                &Addr::unchecked("nexprism"),
+                &Addr::unchecked("alice"),
+                Uint128::new(30)
+            )
+            .unwrap()]
+        );
+        assert!(load_route_context(&deps.storage).is_err());
+    }
+}
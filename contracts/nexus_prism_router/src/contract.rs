@@ -0,0 +1,122 @@
+use cosmwasm_std::{
+    entry_point, from_binary, to_binary, Binary, Deps, DepsMut, Env, MessageInfo, Response,
+    StdError, StdResult,
+};
+use cw0::nonpayable;
+use cw2::{get_contract_version, set_contract_version};
+use cw20::Cw20ReceiveMsg;
+use nexus_prism_protocol::router::{
+    CallbackMsg, Cw20HookMsg, ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg,
+};
+
+use crate::commands::{
+    assert_minimum_receive, execute_operation, execute_operations, load_contracts, send_received,
+};
+use crate::error::ContractError;
+use crate::queries::query_config;
+use crate::state::{save_config, Config};
+
+const CONTRACT_NAME: &str = "nexus.protocol:nex-prism-router";
+const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[entry_point]
+pub fn instantiate(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    msg: InstantiateMsg,
+) -> Result<Response, ContractError> {
+    nonpayable(&info)?;
+
+    let config = Config {
+        vault: deps.api.addr_validate(&msg.vault)?,
+        prism_governance: deps.api.addr_validate(&msg.prism_governance)?,
+        nexprism_xprism_pair: deps.api.addr_validate(&msg.nexprism_xprism_pair)?,
+    };
+    save_config(deps.storage, &config)?;
+
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    Ok(Response::new().add_attribute("action", "instantiate"))
+}
+
+#[entry_point]
+pub fn execute(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: ExecuteMsg,
+) -> Result<Response, ContractError> {
+    nonpayable(&info)?;
+
+    match msg {
+        ExecuteMsg::Receive(msg) => receive_cw20(deps, env, info, msg),
+
+        ExecuteMsg::Callback(msg) => {
+            if info.sender != env.contract.address {
+                return Err(ContractError::Unauthorized {});
+            }
+            match msg {
+                CallbackMsg::ExecuteOperation { operation, to } => {
+                    execute_operation(deps, env, operation, to)
+                }
+                CallbackMsg::AssertMinimumReceive { minimum_receive } => {
+                    assert_minimum_receive(deps, env, minimum_receive)
+                }
+                CallbackMsg::SendReceived { to } => send_received(deps, env, to),
+            }
+        }
+    }
+}
+
+fn receive_cw20(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    cw20_msg: Cw20ReceiveMsg,
+) -> Result<Response, ContractError> {
+    match from_binary(&cw20_msg.msg)? {
+        Cw20HookMsg::ExecuteOperations {
+            operations,
+            minimum_receive,
+            to,
+        } => {
+            let contracts = load_contracts(deps.as_ref())?;
+            execute_operations(
+                deps,
+                env,
+                &contracts,
+                info.sender,
+                cw20_msg.amount,
+                cw20_msg.sender,
+                operations,
+                minimum_receive,
+                to,
+            )
+        }
+    }
+}
+
+#[entry_point]
+pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::Config {} => to_binary(&query_config(deps)?),
+    }
+}
+
+#[entry_point]
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    let ver = get_contract_version(deps.storage)?;
+
+    if ver.contract != CONTRACT_NAME {
+        return Err(StdError::generic_err("Can only upgrade from same type").into());
+    }
+
+    if ver.version.as_str() >= CONTRACT_VERSION {
+        return Err(StdError::generic_err("Cannot upgrade from a newer version").into());
+    }
+
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    Ok(Response::new())
+}
@@ -0,0 +1,39 @@
+use cosmwasm_std::{StdError, Uint128};
+use cw0::PaymentError;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ContractError {
+    #[error("{0}")]
+    Std(#[from] StdError),
+
+    #[error("{0}")]
+    Payment(#[from] PaymentError),
+
+    #[error("unauthorized")]
+    Unauthorized {},
+
+    #[error("no operations")]
+    NoOperations {},
+
+    #[error("operation #{index} can't be applied to {token}")]
+    InvalidOperation { index: usize, token: String },
+
+    #[error("recipient is required")]
+    RecipientRequired {},
+
+    #[error("nothing to operate with {token}")]
+    NothingToOperate { token: String },
+
+    #[error("assertion failed; minimum receive amount: {minimum_receive}, received: {received}")]
+    MinimumReceiveAssertion {
+        minimum_receive: Uint128,
+        received: Uint128,
+    },
+}
+
+impl From<ContractError> for StdError {
+    fn from(e: ContractError) -> Self {
+        StdError::generic_err(e.to_string())
+    }
+}
@@ -0,0 +1,5 @@
+mod commands;
+pub mod contract;
+mod error;
+mod queries;
+pub mod state;
@@ -0,0 +1,14 @@
+use cosmwasm_std::{Deps, StdResult};
+use nexus_prism_protocol::router::ConfigResponse;
+
+use crate::state::load_config;
+
+pub fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
+    let config = load_config(deps.storage)?;
+
+    Ok(ConfigResponse {
+        vault: config.vault.to_string(),
+        prism_governance: config.prism_governance.to_string(),
+        nexprism_xprism_pair: config.nexprism_xprism_pair.to_string(),
+    })
+}
@@ -0,0 +1,68 @@
+use cosmwasm_std::{Addr, StdResult, Storage, Uint128};
+use cw_storage_plus::Item;
+use serde::{Deserialize, Serialize};
+
+const CONFIG: Item<Config> = Item::new("config");
+const ROUTE_CONTEXT: Item<RouteContext> = Item::new("route");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Config {
+    pub vault: Addr,
+    pub prism_governance: Addr,
+    pub nexprism_xprism_pair: Addr,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Autocompounder {
+    pub contract: Addr,
+    pub token: Addr,
+}
+
+// Everything operations need, most of it is taken from the vault config.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Contracts {
+    pub vault: Addr,
+    pub prism_governance: Addr,
+    pub nexprism_xprism_pair: Addr,
+
+    pub prism_token: Addr,
+    pub xprism_token: Addr,
+    pub nexprism_token: Addr,
+    pub yluna_token: Addr,
+    pub nyluna_token: Addr,
+
+    pub nexprism_staking: Addr,
+    pub nyluna_staking: Addr,
+
+    pub nexprism_autocompounder: Option<Autocompounder>,
+    pub nyluna_autocompounder: Option<Autocompounder>,
+}
+
+// Token received by the last operation and router balance of it before the operation.
+// Contracts are loaded once per route, operation callbacks don't query them again.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RouteContext {
+    pub token: Addr,
+    pub balance_before: Uint128,
+    pub contracts: Contracts,
+}
+
+pub fn load_config(store: &dyn Storage) -> StdResult<Config> {
+    CONFIG.load(store)
+}
+
+pub fn save_config(store: &mut dyn Storage, config: &Config) -> StdResult<()> {
+    CONFIG.save(store, config)
+}
+
+pub fn load_route_context(store: &dyn Storage) -> StdResult<RouteContext> {
+    ROUTE_CONTEXT.load(store)
+}
+
+pub fn save_route_context(store: &mut dyn Storage, context: &RouteContext) -> StdResult<()> {
+    ROUTE_CONTEXT.save(store, context)
+}
+
+pub fn remove_route_context(store: &mut dyn Storage) {
+    ROUTE_CONTEXT.remove(store)
+}
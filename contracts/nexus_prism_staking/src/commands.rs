@@ -1,13 +1,21 @@
 use std::cmp::min;
 
 use cosmwasm_std::{
-    from_binary, Addr, BlockInfo, Decimal, Deps, DepsMut, Env, MessageInfo, Response, StdError,
-    StdResult, SubMsg, Uint128,
+    from_binary, to_binary, Addr, Binary, BlockInfo, Decimal, Deps, DepsMut, Env, MessageInfo,
+    Response, StdError, StdResult, Storage, SubMsg, Uint128, WasmMsg,
 };
 use nexus_prism_protocol::{
-    common::{query_token_balance, send, send_wasm_msg, sum, transfer},
+    common::{div, query_token_balance, send, send_wasm_msg, sub, sum, transfer},
+    events::{
+        StakingBalanceChanged, StakingBalancesBatchUpdated, StakingDonated, StakingLocked,
+        StakingLocksReleased, StakingPayoutPreferenceSet, StakingRewarded, StakingRewardsClaimed,
+        StakingStakeTransferred, StakingSurplusCollected, StakingVestedRewardsWithdrawn,
+        TypedEvent,
+    },
     staking::{
-        Cw20HookMsg, StakeOperatorQueryMsg, StakeOperatorStakerResponse, StakeOperatorStateResponse,
+        BalanceDelta, Cw20HookMsg, LockupTier, PayoutMode, PayoutPreference, RewardVesting,
+        StakeChangedHookMsg, StakeOperatorQueryMsg, StakeOperatorStakerResponse,
+        StakeOperatorStateResponse, SurplusPolicy, SurplusRecipient,
     },
 };
 
@@ -15,14 +23,29 @@ use crate::{
     error::ContractError,
     replies_id::ReplyId,
     state::{
-        load_config, load_gov_update, load_staker, load_state, remove_gov_update, save_config,
-        save_gov_update, save_state, Config, GovernanceUpdateState, ReplyContext, RewardState,
-        Staker, State, REPLY_CONTEXT,
+        load_config, load_donated, load_gov_update, load_hooks, load_lock_expiration,
+        load_lock_expirations, load_staker, load_stakers, load_stakers_migration, load_state,
+        load_vesting_entries, remove_gov_update, remove_staker, remove_stakers_migration,
+        save_config, save_donated, save_gov_update, save_hooks, save_lock_expiration,
+        save_staker_balance_snapshot, save_stakers_migration, save_state,
+        save_total_staked_snapshot, save_vesting_entries, Config, GovernanceUpdateState, Lock,
+        LockExpiration, Payout, ReplyContext, RewardState, Staker, State, VestingEntry,
+        REPLY_CONTEXT,
     },
     utils::{substract_into_decimal, sum_decimals_and_split_result_to_uint_and_decimal},
 };
 use crate::{state::save_staker, utils::calculate_decimal_rewards};
-use cw20::Cw20ReceiveMsg;
+use astroport::{
+    asset::{Asset, AssetInfo},
+    pair::SimulationResponse,
+};
+use cw20::{Cw20ExecuteMsg, Cw20QueryMsg, Cw20ReceiveMsg, MinterResponse};
+
+const MAX_LOCKS_PER_STAKER: usize = 16;
+const MAX_HOOKS: usize = 10;
+const MAX_VESTING_ENTRIES: usize = 32;
+const DEFAULT_SWEEP_LIMIT: u32 = 10;
+const MAX_SWEEP_LIMIT: u32 = 30;
 
 pub fn receive_cw20(
     deps: DepsMut,
@@ -32,53 +55,279 @@ pub fn receive_cw20(
 ) -> Result<Response, ContractError> {
     let config = load_config(deps.storage)?;
 
-    if config.with_stake_operator() || info.sender != config.staking_token {
-        return Err(ContractError::Unauthorized);
-    }
-
     match from_binary(&cw20_msg.msg) {
-        Ok(Cw20HookMsg::Bond {}) => {
-            increase_balance(deps, env, &config, cw20_msg.sender, cw20_msg.amount)
+        Ok(Cw20HookMsg::Bond { .. })
+            if !config.direct_bonding || info.sender != config.staking_token =>
+        {
+            Err(ContractError::Unauthorized)
+        }
+        Ok(Cw20HookMsg::Bond {
+            recipient,
+            lock_duration,
+        }) => {
+            let recipient = match recipient {
+                Some(recipient) => deps.api.addr_validate(&recipient)?,
+                None => deps.api.addr_validate(&cw20_msg.sender)?,
+            };
+            // locks can't be opened on someone else
+            if lock_duration.is_some() && recipient != cw20_msg.sender {
+                return Err(ContractError::LockForRecipient {});
+            }
+            increase_balance(
+                deps,
+                env,
+                &config,
+                recipient.to_string(),
+                cw20_msg.amount,
+                lock_duration,
+                false,
+            )
+        }
+        Ok(Cw20HookMsg::Donate {}) if info.sender != config.reward_token => {
+            Err(ContractError::Unauthorized)
         }
+        Ok(Cw20HookMsg::Donate {}) => donate(deps, cw20_msg.sender, cw20_msg.amount),
         Err(err) => Err(ContractError::Std(err)),
     }
 }
 
 pub fn unbond(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Option<Uint128>,
+    recipient: Option<String>,
+    claim_rewards: bool,
+) -> Result<Response, ContractError> {
+    let config = load_config(deps.storage)?;
+
+    let recipient = match recipient {
+        Some(recipient) => deps.api.addr_validate(&recipient)?,
+        None => info.sender.clone(),
+    };
+
+    let amount = match amount {
+        Some(amount) => amount,
+        None => {
+            let staker = load_staker(deps.storage, &info.sender)?;
+            staker.bonded - staker.locked_amount(get_time(&env.block))
+        }
+    };
+    if amount.is_zero() {
+        return Err(ContractError::NothingToUnbond {});
+    }
+
+    let mut resp = decrease_balance(
+        deps.branch(),
+        env.clone(),
+        &config,
+        info.sender.to_string(),
+        amount,
+        false,
+    )?
+    .add_submessage(transfer(&config.staking_token, &recipient, amount)?)
+    .add_attribute("recipient", recipient.to_string());
+
+    if claim_rewards {
+        // unbonding is still allowed when there is nothing to claim or rewards can't be
+        // sent to the recipient, these errors come before any state change of the claim
+        match claim_rewards_logic(deps, env, &info.sender, &recipient, None) {
+            Ok(claim_resp) => {
+                resp = resp
+                    .add_submessages(claim_resp.messages)
+                    .add_attributes(claim_resp.attributes)
+                    .add_events(claim_resp.events);
+            }
+            Err(ContractError::NoRewards {} | ContractError::RewardsEscrowed {}) => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(resp)
+}
+
+pub fn transfer_stake(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
+    recipient: String,
     amount: Uint128,
 ) -> Result<Response, ContractError> {
     let config = load_config(deps.storage)?;
 
-    if config.with_stake_operator() {
-        return Err(ContractError::Unauthorized);
+    let recipient = deps.api.addr_validate(&recipient)?;
+    if recipient == info.sender {
+        return Err(StdError::generic_err("can't transfer stake to yourself").into());
     }
 
-    Ok(
-        decrease_balance(deps, env, &config, info.sender.to_string(), amount)?
-            .add_submessage(transfer(&config.staking_token, &info.sender, amount)?),
-    )
+    let mut state: State = load_state(deps.storage)?;
+    state.staking_total_balance =
+        get_staking_total_balance(deps.as_ref(), &config.stake_operators, &state)?;
+    let now = get_time(&env.block);
+    expire_locks(deps.storage, &mut state, now)?;
+    calculate_global_index(
+        state.virtual_reward_balance,
+        state.total_weight(),
+        &mut state.virtual_rewards,
+    )?;
+    calculate_global_index(
+        query_token_balance(deps.as_ref(), &config.reward_token, &env.contract.address),
+        state.total_weight(),
+        &mut state.real_rewards,
+    )?;
+
+    let mut sender: Staker = load_staker(deps.storage, &info.sender)?;
+    sender.balance = get_staker_balance(
+        deps.as_ref(),
+        &config.stake_operators,
+        &sender,
+        &info.sender,
+    )?;
+    release_expired_locks(deps.storage, &mut state, &mut sender, now)?;
+    accrue_rewards(&state, &mut sender)?;
+
+    // only directly bonded tokens can be transferred, locked ones stay with sender
+    let unlocked_balance = sender.bonded - sender.locked_amount(now);
+    if unlocked_balance < amount {
+        // balances of stake operators can be moved only on their side
+        if sender.balance > sender.bonded {
+            return Err(ContractError::OperatorStakeNotTransferable {
+                transferable: unlocked_balance,
+            });
+        }
+        return Err(ContractError::NotEnoughTokens {
+            name: config.staking_token.to_string(),
+            value: unlocked_balance,
+            required: amount,
+        });
+    }
+
+    let mut receiver: Staker = load_staker(deps.storage, &recipient)?;
+    receiver.balance = get_staker_balance(
+        deps.as_ref(),
+        &config.stake_operators,
+        &receiver,
+        &recipient,
+    )?;
+    release_expired_locks(deps.storage, &mut state, &mut receiver, now)?;
+    accrue_rewards(&state, &mut receiver)?;
+
+    let hooks = [
+        stake_changed_hooks(
+            deps.storage,
+            &info.sender,
+            sender.balance,
+            sender.balance - amount,
+        )?,
+        stake_changed_hooks(
+            deps.storage,
+            &recipient,
+            receiver.balance,
+            receiver.balance + amount,
+        )?,
+    ]
+    .concat();
+
+    sender.balance -= amount;
+    sender.bonded -= amount;
+    receiver.balance += amount;
+    receiver.bonded += amount;
+
+    track_exit(&mut state, &mut sender, now);
+    track_exit(&mut state, &mut receiver, now);
+    save_or_prune_staker(deps.storage, &mut state, &info.sender, &sender)?;
+    save_staker(deps.storage, &recipient, &receiver)?;
+    save_state(deps.storage, &state)?;
+    save_staker_balance_snapshot(deps.storage, &info.sender, sender.balance, env.block.height)?;
+    save_staker_balance_snapshot(deps.storage, &recipient, receiver.balance, env.block.height)?;
+
+    Ok(Response::new()
+        .add_submessages(hooks)
+        .add_attribute("action", "transfer_stake")
+        .add_event(
+            StakingStakeTransferred {
+                staker: info.sender.to_string(),
+                recipient: recipient.to_string(),
+                amount,
+            }
+            .into_event(),
+        ))
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn update_config(
     deps: DepsMut,
     mut config: Config,
-    stake_operator: Option<String>,
+    stake_operators: Option<Vec<String>>,
+    direct_bonding: Option<bool>,
     reward_operator: Option<String>,
-    nexprism_xprism_pair: Option<String>,
+    reward_operators: Option<Vec<String>>,
+    payout_mode: Option<PayoutMode>,
+    lockup_tiers: Option<Vec<LockupTier>>,
+    surplus_policy: Option<Option<SurplusPolicy>>,
+    reward_vesting: Option<RewardVesting>,
+    escrow_exempt: Option<Vec<String>>,
 ) -> Result<Response, ContractError> {
-    if let Some(stake_operator) = stake_operator {
-        config.stake_operator = Some(deps.api.addr_validate(&stake_operator)?);
+    if let Some(stake_operators) = stake_operators {
+        config.stake_operators = validate_stake_operators(deps.as_ref(), stake_operators)?;
+    }
+
+    if let Some(direct_bonding) = direct_bonding {
+        config.direct_bonding = direct_bonding;
     }
 
     if let Some(reward_operator) = reward_operator {
         config.reward_operator = deps.api.addr_validate(&reward_operator)?;
     }
 
-    if let Some(nexprism_xprism_pair) = nexprism_xprism_pair {
-        config.nexprism_xprism_pair = Some(deps.api.addr_validate(&nexprism_xprism_pair)?);
+    if let Some(reward_operators) = reward_operators {
+        config.reward_operators = reward_operators
+            .iter()
+            .map(|addr| deps.api.addr_validate(addr))
+            .collect::<StdResult<_>>()?;
+    }
+
+    if let Some(payout_mode) = payout_mode {
+        config.payout = validate_payout_mode(deps.as_ref(), &config.reward_token, payout_mode)?;
+    }
+
+    // existing locks keep their multipliers
+    if let Some(lockup_tiers) = lockup_tiers {
+        for (i, tier) in lockup_tiers.iter().enumerate() {
+            if tier.duration == 0
+                || tier.multiplier < Decimal::one()
+                || lockup_tiers[..i]
+                    .iter()
+                    .any(|t| t.duration == tier.duration)
+            {
+                return Err(ContractError::InvalidConfig {});
+            }
+        }
+        config.lockup_tiers = lockup_tiers;
+    }
+
+    if let Some(surplus_policy) = surplus_policy {
+        config.surplus_policy = surplus_policy
+            .map(|policy| validate_surplus_policy(deps.as_ref(), policy))
+            .transpose()?;
+    }
+
+    if let Some(reward_vesting) = reward_vesting {
+        if reward_vesting.early_exit_penalty > Decimal::one() {
+            return Err(ContractError::InvalidConfig {});
+        }
+        config.reward_vesting = if reward_vesting.duration != 0 {
+            Some(reward_vesting)
+        } else {
+            None
+        };
+    }
+
+    if let Some(escrow_exempt) = escrow_exempt {
+        config.escrow_exempt = escrow_exempt
+            .iter()
+            .map(|addr| deps.api.addr_validate(addr))
+            .collect::<StdResult<_>>()?;
     }
 
     save_config(deps.storage, &config)?;
@@ -86,11 +335,100 @@ pub fn update_config(
     Ok(Response::new().add_attribute("action", "update_config"))
 }
 
+fn validate_surplus_policy(
+    deps: Deps,
+    policy: SurplusPolicy,
+) -> Result<SurplusPolicy, ContractError> {
+    if policy.grace_period == 0 {
+        return Err(ContractError::InvalidConfig {});
+    }
+    let recipient = match policy.recipient {
+        SurplusRecipient::Stakers => SurplusRecipient::Stakers,
+        SurplusRecipient::Address { address } => SurplusRecipient::Address {
+            address: deps.api.addr_validate(&address)?.to_string(),
+        },
+    };
+    Ok(SurplusPolicy {
+        grace_period: policy.grace_period,
+        recipient,
+    })
+}
+
+pub fn validate_stake_operators(
+    deps: Deps,
+    stake_operators: Vec<String>,
+) -> Result<Vec<Addr>, ContractError> {
+    let mut validated: Vec<Addr> = vec![];
+    for stake_operator in stake_operators {
+        let addr = deps.api.addr_validate(&stake_operator)?;
+        if validated.contains(&addr) {
+            return Err(ContractError::InvalidConfig {});
+        }
+        validated.push(addr);
+    }
+    Ok(validated)
+}
+
+pub fn validate_payout_mode(
+    deps: Deps,
+    reward_token: &Addr,
+    payout_mode: PayoutMode,
+) -> Result<Payout, ContractError> {
+    match payout_mode {
+        PayoutMode::Prism {} => Ok(Payout::Prism),
+        PayoutMode::Xprism {
+            prism_governance,
+            xprism_token,
+        } => {
+            let prism_governance = deps.api.addr_validate(&prism_governance)?;
+            let xprism_token = deps.api.addr_validate(&xprism_token)?;
+            if xprism_token == *reward_token || xprism_token == prism_governance {
+                return Err(ContractError::InvalidConfig {});
+            }
+            Ok(Payout::Xprism {
+                prism_governance,
+                xprism_token,
+            })
+        }
+        PayoutMode::Nexprism {
+            prism_governance,
+            xprism_token,
+            nexprism_xprism_pair,
+        } => {
+            let prism_governance = deps.api.addr_validate(&prism_governance)?;
+            let xprism_token = deps.api.addr_validate(&xprism_token)?;
+            let nexprism_xprism_pair = deps.api.addr_validate(&nexprism_xprism_pair)?;
+            if xprism_token == *reward_token || xprism_token == prism_governance {
+                return Err(ContractError::InvalidConfig {});
+            }
+
+            // pair has to trade xPRISM for another token
+            let pair_info: astroport::asset::PairInfo = deps
+                .querier
+                .query_wasm_smart(&nexprism_xprism_pair, &astroport::pair::QueryMsg::Pair {})?;
+            let has_xprism = pair_info.asset_infos.iter().any(|info| {
+                matches!(info, astroport::asset::AssetInfo::Token { contract_addr } if *contract_addr == xprism_token)
+            });
+            if !has_xprism
+                || query_nexprism_token(deps, &nexprism_xprism_pair, &xprism_token).is_err()
+            {
+                return Err(ContractError::InvalidConfig {});
+            }
+
+            Ok(Payout::Nexprism {
+                prism_governance,
+                xprism_token,
+                nexprism_xprism_pair,
+            })
+        }
+    }
+}
+
 pub fn update_global_index(deps: DepsMut, env: Env) -> Result<Response, ContractError> {
     let mut state = load_state(deps.storage)?;
     let config = load_config(deps.storage)?;
     state.staking_total_balance =
-        get_staking_total_balance(deps.as_ref(), config.stake_operator.clone(), &state)?;
+        get_staking_total_balance(deps.as_ref(), &config.stake_operators, &state)?;
 
     let resp = Response::new().add_attribute("action", "update_global_index");
 
@@ -98,23 +436,24 @@ pub fn update_global_index(deps: DepsMut, env: Env) -> Result<Response, Contract
         return Ok(resp);
     }
 
+    expire_locks(deps.storage, &mut state, get_time(&env.block))?;
     let virtual_claimed_rewards = calculate_global_index(
         state.virtual_reward_balance,
-        state.staking_total_balance,
+        state.total_weight(),
         &mut state.virtual_rewards,
     )?;
     let real_claimed_rewards = calculate_global_index(
         query_token_balance(deps.as_ref(), &config.reward_token, &env.contract.address),
-        state.staking_total_balance,
+        state.total_weight(),
         &mut state.real_rewards,
     )?;
 
+    save_state(deps.storage, &state)?;
+
     if virtual_claimed_rewards.is_zero() && real_claimed_rewards.is_zero() {
         return Ok(resp);
     }
 
-    save_state(deps.storage, &state)?;
-
     Ok(resp
         .add_attribute("real_claimed_rewards", real_claimed_rewards)
         .add_attribute("virtual_claimed_rewards", virtual_claimed_rewards))
@@ -135,6 +474,70 @@ pub fn update_governance(
     Ok(Response::new().add_attribute("action", "update_governance_addr"))
 }
 
+pub fn add_hook(deps: DepsMut, addr: String) -> Result<Response, ContractError> {
+    let addr = deps.api.addr_validate(&addr)?;
+    let mut hooks = load_hooks(deps.storage)?;
+
+    if hooks.contains(&addr) {
+        return Err(ContractError::HookAlreadyRegistered {
+            addr: addr.to_string(),
+        });
+    }
+    // every hook is called on each balance change
+    if hooks.len() >= MAX_HOOKS {
+        return Err(ContractError::TooManyHooks {});
+    }
+
+    hooks.push(addr.clone());
+    save_hooks(deps.storage, &hooks)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "add_hook")
+        .add_attribute("hook", addr))
+}
+
+pub fn remove_hook(deps: DepsMut, addr: String) -> Result<Response, ContractError> {
+    let addr = deps.api.addr_validate(&addr)?;
+    let mut hooks = load_hooks(deps.storage)?;
+
+    if !hooks.contains(&addr) {
+        return Err(ContractError::HookNotRegistered {
+            addr: addr.to_string(),
+        });
+    }
+
+    hooks.retain(|hook| *hook != addr);
+    save_hooks(deps.storage, &hooks)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "remove_hook")
+        .add_attribute("hook", addr))
+}
+
+fn stake_changed_hooks(
+    storage: &dyn Storage,
+    staker: &Addr,
+    old_balance: Uint128,
+    new_balance: Uint128,
+) -> StdResult<Vec<SubMsg>> {
+    let msg = to_binary(&StakeChangedHookMsg::StakeChangedHook {
+        staker: staker.to_string(),
+        old_balance,
+        new_balance,
+    })?;
+
+    Ok(load_hooks(storage)?
+        .into_iter()
+        .map(|hook| {
+            SubMsg::new(WasmMsg::Execute {
+                contract_addr: hook.to_string(),
+                msg: msg.clone(),
+                funds: vec![],
+            })
+        })
+        .collect())
+}
+
 pub fn accept_governance(
     deps: DepsMut,
     env: Env,
@@ -189,14 +592,15 @@ pub fn claim_rewards(
     env: Env,
     info: MessageInfo,
     recipient: Option<String>,
+    msg: Option<Binary>,
 ) -> Result<Response, ContractError> {
     let staker = &info.sender;
     match recipient {
         Some(recipient) => {
             let recipient_addr = deps.api.addr_validate(&recipient)?;
-            claim_rewards_logic(deps, env, staker, &recipient_addr)
+            claim_rewards_logic(deps, env, staker, &recipient_addr, msg)
         }
-        None => claim_rewards_logic(deps, env, staker, staker),
+        None => claim_rewards_logic(deps, env, staker, staker, msg),
     }
 }
 
@@ -206,7 +610,7 @@ pub fn claim_rewards_for_someone(
     recipient: String,
 ) -> Result<Response, ContractError> {
     let addr = deps.api.addr_validate(&recipient)?;
-    claim_rewards_logic(deps, env, &addr, &addr)
+    claim_rewards_logic(deps, env, &addr, &addr, None)
 }
 
 fn claim_rewards_logic(
@@ -214,36 +618,40 @@ fn claim_rewards_logic(
     env: Env,
     staker_addr: &Addr,
     recipient: &Addr,
+    msg: Option<Binary>,
 ) -> Result<Response, ContractError> {
     let mut staker: Staker = load_staker(deps.storage, staker_addr)?;
     let mut state: State = load_state(deps.storage)?;
     let config: Config = load_config(deps.storage)?;
 
     state.staking_total_balance =
-        get_staking_total_balance(deps.as_ref(), config.stake_operator.clone(), &state)?;
+        get_staking_total_balance(deps.as_ref(), &config.stake_operators, &state)?;
     staker.balance =
-        get_staker_balance(deps.as_ref(), config.stake_operator, &staker, staker_addr)?;
+        get_staker_balance(deps.as_ref(), &config.stake_operators, &staker, staker_addr)?;
 
+    expire_locks(deps.storage, &mut state, get_time(&env.block))?;
     calculate_global_index(
         state.virtual_reward_balance,
-        state.staking_total_balance,
+        state.total_weight(),
         &mut state.virtual_rewards,
     )?;
     calculate_global_index(
         query_token_balance(deps.as_ref(), &config.reward_token, &env.contract.address),
-        state.staking_total_balance,
+        state.total_weight(),
         &mut state.real_rewards,
     )?;
 
+    release_expired_locks(deps.storage, &mut state, &mut staker, get_time(&env.block))?;
+
     let real_reward_with_decimals = calculate_decimal_rewards(
         state.real_rewards.global_index,
         staker.real_index,
-        staker.balance,
+        staker.weight(),
     )?;
     let virtual_reward_with_decimals = calculate_decimal_rewards(
         state.virtual_rewards.global_index,
         staker.virtual_index,
-        staker.balance,
+        staker.weight(),
     )?;
 
     let (real_rewards, real_decimals) = sum_decimals_and_split_result_to_uint_and_decimal(
@@ -261,10 +669,20 @@ fn claim_rewards_logic(
         return Err(ContractError::NoRewards {});
     }
 
-    state.real_rewards.prev_balance -= rewards;
+    let vesting = config
+        .reward_vesting
+        .clone()
+        .filter(|_| !config.is_escrow_exempt(staker_addr));
+    // escrow is kept by staker, recipient is chosen on withdrawal
+    if vesting.is_some() && (msg.is_some() || recipient != staker_addr) {
+        return Err(ContractError::RewardsEscrowed {});
+    }
+    // escrowed rewards stay on contract balance until withdrawn
+    if vesting.is_none() {
+        state.real_rewards.prev_balance -= rewards;
+    }
     state.virtual_rewards.prev_balance -= rewards;
     state.virtual_reward_balance -= rewards;
-    save_state(deps.storage, &state)?;
 
     staker.real_pending_rewards = substract_into_decimal(real_rewards, rewards) + real_decimals;
     staker.virtual_pending_rewards =
@@ -273,43 +691,308 @@ fn claim_rewards_logic(
     staker.real_index = state.real_rewards.global_index;
     staker.virtual_index = state.virtual_rewards.global_index;
 
-    save_staker(deps.storage, staker_addr, &staker)?;
+    let payout_preference = staker.payout_preference;
+    save_or_prune_staker(deps.storage, &mut state, staker_addr, &staker)?;
+    save_state(deps.storage, &state)?;
 
     let resp = Response::new()
         .add_attribute("action", "claim_reward")
-        .add_attribute("staker", staker_addr)
-        .add_attribute("recipient", recipient)
-        .add_attribute("rewards", rewards);
-
-    match (
-        config.prism_governance,
-        config.xprism_token,
-        config.nexprism_xprism_pair,
-    ) {
-        (None, None, None) => {
-            Ok(resp.add_submessage(transfer(&config.reward_token, recipient, rewards)?))
+        .add_event(
+            StakingRewardsClaimed {
+                staker: staker_addr.to_string(),
+                recipient: recipient.to_string(),
+                rewards,
+            }
+            .into_event(),
+        );
+
+    if let Some(vesting) = vesting {
+        let now = get_time(&env.block);
+        let mut entries = load_vesting_entries(deps.storage, staker_addr)?;
+        entries.retain(|entry| entry.withdrawn < entry.amount);
+        add_vesting_entry(&mut entries, rewards, now, vesting.duration);
+        save_vesting_entries(deps.storage, staker_addr, &entries)?;
+        return Ok(resp.add_attribute("escrowed_until", (now + vesting.duration).to_string()));
+    }
+
+    let payout = staker_payout(deps.as_ref(), &config, payout_preference);
+    Ok(resp.add_submessage(payout_rewards(
+        deps, &env, config, payout, recipient, rewards, msg,
+    )?))
+}
+
+// Over the cap rewards join the latest entry and vest till the new end,
+// its start is moved back so that already vested part stays withdrawable.
+fn add_vesting_entry(entries: &mut Vec<VestingEntry>, amount: Uint128, now: u64, duration: u64) {
+    let end_time = now + duration;
+    let is_full = entries.len() >= MAX_VESTING_ENTRIES;
+    match entries.last_mut() {
+        Some(latest) if is_full => {
+            let vested = latest.vested(now) - latest.withdrawn;
+            let unvested = latest.amount - latest.vested(now) + amount;
+            let shift = vested.multiply_ratio(duration, unvested).u128();
+            *latest = VestingEntry {
+                amount: vested + unvested,
+                withdrawn: Uint128::zero(),
+                start_time: now - shift.min(now as u128) as u64,
+                end_time,
+            };
+        }
+        _ => entries.push(VestingEntry {
+            amount,
+            withdrawn: Uint128::zero(),
+            start_time: now,
+            end_time,
+        }),
+    }
+}
+
+pub fn withdraw_vested_rewards(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    recipient: Option<String>,
+    msg: Option<Binary>,
+    exit: bool,
+) -> Result<Response, ContractError> {
+    let config = load_config(deps.storage)?;
+    let recipient = match recipient {
+        Some(recipient) => deps.api.addr_validate(&recipient)?,
+        None => info.sender.clone(),
+    };
+    let now = get_time(&env.block);
+    // escrow could be disabled after rewards were claimed
+    let early_exit_penalty = config
+        .reward_vesting
+        .as_ref()
+        .map(|vesting| vesting.early_exit_penalty)
+        .unwrap_or_else(Decimal::zero);
+
+    let mut entries = load_vesting_entries(deps.storage, &info.sender)?;
+    let mut amount = Uint128::zero();
+    let mut penalty = Uint128::zero();
+    for entry in entries.iter_mut() {
+        let vested = entry.vested(now);
+        amount += vested - entry.withdrawn;
+        entry.withdrawn = vested;
+        if exit {
+            let unvested = entry.amount - vested;
+            let entry_penalty = unvested * early_exit_penalty;
+            amount += unvested - entry_penalty;
+            penalty += entry_penalty;
+            entry.withdrawn = entry.amount;
+        }
+    }
+    entries.retain(|entry| entry.withdrawn < entry.amount);
+
+    if amount.is_zero() && penalty.is_zero() {
+        return Err(ContractError::NoRewards {});
+    }
+
+    // penalty stays on contract balance and is distributed as new real rewards
+    let mut state = load_state(deps.storage)?;
+    state.real_rewards.prev_balance -= amount + penalty;
+    save_state(deps.storage, &state)?;
+    save_vesting_entries(deps.storage, &info.sender, &entries)?;
+
+    let resp = Response::new()
+        .add_attribute(
+            "action",
+            if exit {
+                "exit_vesting"
+            } else {
+                "withdraw_vested_rewards"
+            },
+        )
+        .add_event(
+            StakingVestedRewardsWithdrawn {
+                staker: info.sender.to_string(),
+                recipient: recipient.to_string(),
+                rewards: amount,
+                penalty,
+            }
+            .into_event(),
+        );
+
+    if amount.is_zero() {
+        return Ok(resp);
+    }
+
+    let payout_preference = load_staker(deps.storage, &info.sender)?.payout_preference;
+    let payout = staker_payout(deps.as_ref(), &config, payout_preference);
+    Ok(resp.add_submessage(payout_rewards(
+        deps, &env, config, payout, &recipient, amount, msg,
+    )?))
+}
+
+// Preference could become unsupported after payout mode change.
+fn staker_payout(
+    deps: Deps,
+    config: &Config,
+    preference: Option<PayoutPreference>,
+) -> PayoutPreference {
+    match preference {
+        Some(payout) if is_payout_supported(deps, config, payout) => payout,
+        _ => default_payout(config),
+    }
+}
+
+fn default_payout(config: &Config) -> PayoutPreference {
+    match config.payout {
+        Payout::Prism => PayoutPreference::Prism,
+        Payout::Xprism { .. } => PayoutPreference::Xprism,
+        Payout::Nexprism { .. } => PayoutPreference::Nexprism,
+    }
+}
+
+fn is_payout_supported(deps: Deps, config: &Config, payout: PayoutPreference) -> bool {
+    match payout {
+        PayoutPreference::Prism => true,
+        PayoutPreference::Xprism => config.payout.xprism_token().is_some(),
+        PayoutPreference::Nexprism => config.payout.nexprism_xprism_pair().is_some(),
+        PayoutPreference::Restake => restake_payout(deps, config).is_ok(),
+    }
+}
+
+// Payout that gives staking token to restake rewards.
+fn restake_payout(deps: Deps, config: &Config) -> Result<PayoutPreference, ContractError> {
+    if !config.direct_bonding {
+        return Err(ContractError::InvalidPayoutPreference {});
+    }
+
+    match &config.payout {
+        Payout::Xprism { xprism_token, .. } | Payout::Nexprism { xprism_token, .. }
+            if *xprism_token == config.staking_token =>
+        {
+            Ok(PayoutPreference::Xprism)
+        }
+        Payout::Nexprism {
+            xprism_token,
+            nexprism_xprism_pair,
+            ..
+        } if query_nexprism_token(deps, nexprism_xprism_pair, xprism_token)?
+            == config.staking_token =>
+        {
+            Ok(PayoutPreference::Nexprism)
         }
-        (Some(prism_gov), None, None) => Ok(resp.add_submessage(prism_xprism_swap(
+        _ => Err(ContractError::InvalidPayoutPreference {}),
+    }
+}
+
+fn payout_rewards(
+    deps: DepsMut,
+    env: &Env,
+    config: Config,
+    payout: PayoutPreference,
+    recipient: &Addr,
+    rewards: Uint128,
+    msg: Option<Binary>,
+) -> Result<SubMsg, ContractError> {
+    if payout == PayoutPreference::Restake {
+        if msg.is_some() {
+            return Err(ContractError::InvalidPayoutPreference {});
+        }
+        let payout = restake_payout(deps.as_ref(), &config)?;
+        // staking tokens are sent back to this contract and bonded for the recipient
+        let bond_msg = to_binary(&Cw20HookMsg::Bond {
+            recipient: Some(recipient.to_string()),
+            lock_duration: None,
+        })?;
+        return payout_rewards(
+            deps,
+            env,
+            config,
+            payout,
+            &env.contract.address,
+            rewards,
+            Some(bond_msg),
+        );
+    }
+
+    match (payout, &config.payout) {
+        (PayoutPreference::Prism, _) => Ok(transfer_or_send(
             &config.reward_token,
-            &prism_gov,
+            recipient,
+            rewards,
+            msg,
+        )?),
+        (
+            PayoutPreference::Xprism,
+            Payout::Xprism {
+                prism_governance, ..
+            }
+            | Payout::Nexprism {
+                prism_governance, ..
+            },
+        ) if msg.is_none() => Ok(prism_xprism_swap(
+            &config.reward_token,
+            prism_governance,
             rewards,
             recipient,
-        )?)),
-        (Some(prism_gov), Some(_), Some(_)) => {
-            REPLY_CONTEXT.save(
-                deps.storage,
-                &ReplyContext {
-                    rewards_recipient: recipient.clone(),
-                },
-            )?;
-            Ok(resp.add_submessage(prism_xprism_swap_and_reply(
+        )?),
+        // xPRISM is minted to this contract and delivered in reply
+        (
+            PayoutPreference::Xprism,
+            Payout::Xprism {
+                prism_governance,
+                xprism_token,
+            },
+        )
+        | (
+            PayoutPreference::Xprism | PayoutPreference::Nexprism,
+            Payout::Nexprism {
+                prism_governance,
+                xprism_token,
+                ..
+            },
+        ) => {
+            let context = ReplyContext {
+                rewards_recipient: recipient.clone(),
+                rewards_msg: msg,
+                balance_before: query_token_balance(
+                    deps.as_ref(),
+                    xprism_token,
+                    &env.contract.address,
+                ),
+                buy_nexprism: payout == PayoutPreference::Nexprism,
+            };
+            REPLY_CONTEXT.save(deps.storage, &context)?;
+            Ok(prism_xprism_swap_and_reply(
                 &config.reward_token,
-                &prism_gov,
+                prism_governance,
                 rewards,
-            )?))
+            )?)
+        }
+        _ => Err(ContractError::InvalidPayoutPreference {}),
+    }
+}
+
+pub fn set_payout_preference(
+    deps: DepsMut,
+    info: MessageInfo,
+    preference: Option<PayoutPreference>,
+) -> Result<Response, ContractError> {
+    let config = load_config(deps.storage)?;
+
+    if let Some(preference) = preference {
+        if !is_payout_supported(deps.as_ref(), &config, preference) {
+            return Err(ContractError::InvalidPayoutPreference {});
         }
-        _ => Err(ContractError::InvalidConfig {}),
     }
+
+    let mut staker = load_staker(deps.storage, &info.sender)?;
+    staker.payout_preference = preference;
+    save_staker(deps.storage, &info.sender, &staker)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_payout_preference")
+        .add_event(
+            StakingPayoutPreferenceSet {
+                staker: info.sender.to_string(),
+                preference,
+            }
+            .into_event(),
+        ))
 }
 
 pub fn increase_balance(
@@ -318,65 +1001,104 @@ pub fn increase_balance(
     config: &Config,
     address: String,
     amount: Uint128,
+    lock_duration: Option<u64>,
+    by_operator: bool,
 ) -> Result<Response, ContractError> {
     let address = deps.api.addr_validate(&address)?;
 
     let mut state: State = load_state(deps.storage)?;
     let mut staker: Staker = load_staker(deps.storage, &address)?;
+    let now = get_time(&env.block);
+
+    let lock = match lock_duration {
+        Some(duration) => {
+            let multiplier = config
+                .lockup_multiplier(duration)
+                .ok_or(ContractError::InvalidLockDuration { duration })?;
+            Some(Lock {
+                amount,
+                unlock_time: now + duration,
+                multiplier,
+                scheduled: true,
+            })
+        }
+        None => None,
+    };
+    let locked = lock.as_ref().map(|lock| {
+        StakingLocked {
+            staker: address.to_string(),
+            amount: lock.amount,
+            unlock_time: lock.unlock_time,
+            multiplier: lock.multiplier,
+        }
+        .into_event()
+    });
 
     state.staking_total_balance =
-        get_staking_total_balance(deps.as_ref(), config.stake_operator.clone(), &state)?;
-    staker.balance = get_staker_balance(
-        deps.as_ref(),
-        config.stake_operator.clone(),
-        &staker,
-        &address,
-    )?;
+        get_staking_total_balance(deps.as_ref(), &config.stake_operators, &state)?;
+    staker.balance = get_staker_balance(deps.as_ref(), &config.stake_operators, &staker, &address)?;
 
     //cause if so - we already have updated balance from StakeOperator
-    if config.with_stake_operator() {
+    if by_operator {
         staker.balance -= amount;
         state.staking_total_balance -= amount;
     }
 
-    let real_rewards = calculate_decimal_rewards(
-        state.real_rewards.global_index,
-        staker.real_index,
-        staker.balance,
-    )?;
+    expire_locks(deps.storage, &mut state, now)?;
+    release_expired_locks(deps.storage, &mut state, &mut staker, now)?;
+    accrue_rewards(&state, &mut staker)?;
 
-    staker.real_index = state.real_rewards.global_index;
-    staker.real_pending_rewards = sum(real_rewards, staker.real_pending_rewards);
+    if let Some(lock) = lock {
+        if staker.locks.len() >= MAX_LOCKS_PER_STAKER {
+            return Err(ContractError::TooManyLocks {});
+        }
+        schedule_lock(deps.storage, &lock)?;
+        staker.lock_boost += lock.boost();
+        state.total_lock_boost += lock.boost();
+        staker.locks.push(lock);
+    }
 
-    let virtual_rewards = calculate_decimal_rewards(
-        state.virtual_rewards.global_index,
-        staker.virtual_index,
+    let hooks = stake_changed_hooks(
+        deps.storage,
+        &address,
         staker.balance,
+        staker.balance + amount,
     )?;
-
-    staker.virtual_index = state.virtual_rewards.global_index;
-    staker.virtual_pending_rewards = sum(virtual_rewards, staker.virtual_pending_rewards);
-
     staker.balance += amount;
     state.staking_total_balance += amount;
+    if !by_operator {
+        staker.bonded += amount;
+        state.total_bonded += amount;
+    }
 
     calculate_global_index(
         state.virtual_reward_balance,
-        state.staking_total_balance,
+        state.total_weight(),
         &mut state.virtual_rewards,
     )?;
     calculate_global_index(
         query_token_balance(deps.as_ref(), &config.reward_token, &env.contract.address),
-        state.staking_total_balance,
+        state.total_weight(),
         &mut state.real_rewards,
     )?;
+    track_exit(&mut state, &mut staker, now);
     save_staker(deps.storage, &address, &staker)?;
     save_state(deps.storage, &state)?;
+    save_staker_balance_snapshot(deps.storage, &address, staker.balance, env.block.height)?;
+    save_total_staked_snapshot(deps.storage, state.staking_total_balance, env.block.height)?;
 
     Ok(Response::new()
+        .add_submessages(hooks)
         .add_attribute("action", "increase_balance")
-        .add_attribute("staker", address)
-        .add_attribute("amount", amount))
+        .add_event(
+            StakingBalanceChanged {
+                staker: address.to_string(),
+                old_balance: staker.balance - amount,
+                new_balance: staker.balance,
+            }
+            .into_event(),
+        )
+        .add_events(locked))
 }
 
 pub fn decrease_balance(
@@ -385,6 +1107,7 @@ pub fn decrease_balance(
     config: &Config,
     address: String,
     amount: Uint128,
+    by_operator: bool,
 ) -> Result<Response, ContractError> {
     let address = deps.api.addr_validate(&address)?;
 
@@ -392,65 +1115,562 @@ pub fn decrease_balance(
     let mut staker: Staker = load_staker(deps.storage, &address)?;
 
     state.staking_total_balance =
-        get_staking_total_balance(deps.as_ref(), config.stake_operator.clone(), &state)?;
-    staker.balance = get_staker_balance(
-        deps.as_ref(),
-        config.stake_operator.clone(),
-        &staker,
-        &address,
-    )?;
+        get_staking_total_balance(deps.as_ref(), &config.stake_operators, &state)?;
+    staker.balance = get_staker_balance(deps.as_ref(), &config.stake_operators, &staker, &address)?;
 
     //cause if so - we already have updated balance from StakeOperator
-    if config.with_stake_operator() {
+    if by_operator {
         staker.balance += amount;
         state.staking_total_balance += amount;
     }
 
-    if !config.with_stake_operator() && staker.balance < amount {
+    let now = get_time(&env.block);
+    let unlocked_balance = staker.bonded - staker.locked_amount(now);
+    if !by_operator && unlocked_balance < amount {
         return Err(ContractError::NotEnoughTokens {
             name: config.staking_token.to_string(),
-            value: staker.balance,
+            value: unlocked_balance,
             required: amount,
         });
     }
 
+    expire_locks(deps.storage, &mut state, now)?;
     calculate_global_index(
         state.virtual_reward_balance,
-        state.staking_total_balance,
+        state.total_weight(),
         &mut state.virtual_rewards,
     )?;
     calculate_global_index(
         query_token_balance(deps.as_ref(), &config.reward_token, &env.contract.address),
-        state.staking_total_balance,
+        state.total_weight(),
         &mut state.real_rewards,
     )?;
 
-    let real_rewards = calculate_decimal_rewards(
-        state.real_rewards.global_index,
-        staker.real_index,
-        staker.balance,
-    )?;
-    staker.real_index = state.real_rewards.global_index;
-    staker.real_pending_rewards = sum(real_rewards, staker.real_pending_rewards);
+    release_expired_locks(deps.storage, &mut state, &mut staker, now)?;
+    accrue_rewards(&state, &mut staker)?;
 
-    let virtual_rewards = calculate_decimal_rewards(
-        state.virtual_rewards.global_index,
-        staker.virtual_index,
+    let hooks = stake_changed_hooks(
+        deps.storage,
+        &address,
         staker.balance,
+        staker.balance - amount,
     )?;
-    staker.virtual_index = state.virtual_rewards.global_index;
-    staker.virtual_pending_rewards = sum(virtual_rewards, staker.virtual_pending_rewards);
-
     staker.balance -= amount;
     state.staking_total_balance -= amount;
+    if !by_operator {
+        staker.bonded -= amount;
+        state.total_bonded -= amount;
+    }
 
-    save_staker(deps.storage, &address, &staker)?;
+    track_exit(&mut state, &mut staker, now);
+    save_or_prune_staker(deps.storage, &mut state, &address, &staker)?;
     save_state(deps.storage, &state)?;
+    save_staker_balance_snapshot(deps.storage, &address, staker.balance, env.block.height)?;
+    save_total_staked_snapshot(deps.storage, state.staking_total_balance, env.block.height)?;
 
     Ok(Response::new()
+        .add_submessages(hooks)
         .add_attribute("action", "decrease_balance")
-        .add_attribute("staker", address)
-        .add_attribute("amount", amount))
+        .add_event(
+            StakingBalanceChanged {
+                staker: address.to_string(),
+                old_balance: staker.balance + amount,
+                new_balance: staker.balance,
+            }
+            .into_event(),
+        ))
+}
+
+// Settles stakers like `increase_balance`/`decrease_balance` with a single total query.
+pub fn batch_update_balances(
+    deps: DepsMut,
+    env: Env,
+    config: &Config,
+    updates: Vec<(String, BalanceDelta)>,
+) -> Result<Response, ContractError> {
+    let mut state: State = load_state(deps.storage)?;
+    let now = get_time(&env.block);
+
+    let mut total_increase = Uint128::zero();
+    let mut total_decrease = Uint128::zero();
+    for (_, delta) in &updates {
+        match delta {
+            BalanceDelta::Increase { amount } => total_increase += *amount,
+            BalanceDelta::Decrease { amount } => total_decrease += *amount,
+        }
+    }
+
+    // rewards are distributed with balances before the batch
+    state.staking_total_balance =
+        get_staking_total_balance(deps.as_ref(), &config.stake_operators, &state)?;
+    state.staking_total_balance = (state.staking_total_balance + total_decrease)
+        .checked_sub(total_increase)
+        .map_err(|_| ContractError::InconsistentBalanceUpdate {})?;
+    expire_locks(deps.storage, &mut state, now)?;
+    calculate_global_index(
+        state.virtual_reward_balance,
+        state.total_weight(),
+        &mut state.virtual_rewards,
+    )?;
+    calculate_global_index(
+        query_token_balance(deps.as_ref(), &config.reward_token, &env.contract.address),
+        state.total_weight(),
+        &mut state.real_rewards,
+    )?;
+
+    let mut updated: Vec<Addr> = vec![];
+    let mut hooks: Vec<SubMsg> = vec![];
+    for (address, delta) in updates {
+        let address = deps.api.addr_validate(&address)?;
+        if updated.contains(&address) {
+            return Err(ContractError::DuplicateBalanceUpdate {
+                staker: address.to_string(),
+            });
+        }
+
+        // stored balance is up to date, operators report every change
+        let mut staker: Staker = load_staker(deps.storage, &address)?;
+        let new_balance = match delta {
+            BalanceDelta::Increase { amount } => staker.balance + amount,
+            BalanceDelta::Decrease { amount } => staker
+                .balance
+                .checked_sub(amount)
+                .map_err(|_| ContractError::InconsistentBalanceUpdate {})?,
+        };
+
+        release_expired_locks(deps.storage, &mut state, &mut staker, now)?;
+        accrue_rewards(&state, &mut staker)?;
+
+        hooks.extend(stake_changed_hooks(
+            deps.storage,
+            &address,
+            staker.balance,
+            new_balance,
+        )?);
+        staker.balance = new_balance;
+        track_exit(&mut state, &mut staker, now);
+
+        save_or_prune_staker(deps.storage, &mut state, &address, &staker)?;
+        save_staker_balance_snapshot(deps.storage, &address, staker.balance, env.block.height)?;
+        updated.push(address);
+    }
+
+    state.staking_total_balance = state.staking_total_balance + total_increase - total_decrease;
+    save_state(deps.storage, &state)?;
+    save_total_staked_snapshot(deps.storage, state.staking_total_balance, env.block.height)?;
+
+    Ok(Response::new()
+        .add_submessages(hooks)
+        .add_attribute("action", "batch_update_balances")
+        .add_event(
+            StakingBalancesBatchUpdated {
+                stakers: updated.len() as u64,
+                total_increase,
+                total_decrease,
+                staking_total_balance: state.staking_total_balance,
+            }
+            .into_event(),
+        ))
+}
+
+pub fn release_locks(deps: DepsMut, env: Env, address: String) -> Result<Response, ContractError> {
+    let address = deps.api.addr_validate(&address)?;
+    let config = load_config(deps.storage)?;
+
+    let mut state: State = load_state(deps.storage)?;
+    let mut staker: Staker = load_staker(deps.storage, &address)?;
+
+    state.staking_total_balance =
+        get_staking_total_balance(deps.as_ref(), &config.stake_operators, &state)?;
+    staker.balance = get_staker_balance(deps.as_ref(), &config.stake_operators, &staker, &address)?;
+
+    let now = get_time(&env.block);
+    expire_locks(deps.storage, &mut state, now)?;
+    calculate_global_index(
+        state.virtual_reward_balance,
+        state.total_weight(),
+        &mut state.virtual_rewards,
+    )?;
+    calculate_global_index(
+        query_token_balance(deps.as_ref(), &config.reward_token, &env.contract.address),
+        state.total_weight(),
+        &mut state.real_rewards,
+    )?;
+
+    // boost of scheduled locks is accrued up to their expiration
+    let released_boost = release_expired_locks(deps.storage, &mut state, &mut staker, now)?;
+    if released_boost.is_zero() {
+        return Err(ContractError::NoExpiredLocks {});
+    }
+    accrue_rewards(&state, &mut staker)?;
+
+    save_staker(deps.storage, &address, &staker)?;
+    save_state(deps.storage, &state)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "release_expired_locks")
+        .add_event(
+            StakingLocksReleased {
+                staker: address.to_string(),
+                released_boost,
+            }
+            .into_event(),
+        ))
+}
+
+pub fn collect_surplus(
+    deps: DepsMut,
+    env: Env,
+    address: String,
+) -> Result<Response, ContractError> {
+    let address = deps.api.addr_validate(&address)?;
+    let config = load_config(deps.storage)?;
+    let policy = config
+        .surplus_policy
+        .clone()
+        .ok_or(ContractError::SurplusNotCollectable {})?;
+
+    let mut state: State = load_state(deps.storage)?;
+    let mut staker: Staker = load_staker(deps.storage, &address)?;
+    let now = get_time(&env.block);
+
+    match staker.exited_at {
+        Some(exited_at) if exited_at + policy.grace_period <= now => {}
+        _ => return Err(ContractError::SurplusNotCollectable {}),
+    }
+
+    // exited staker has no weight, so accrues nothing
+    let surplus = staker.surplus();
+    if surplus.is_zero() {
+        return Err(ContractError::NoRewards {});
+    }
+
+    state.staking_total_balance =
+        get_staking_total_balance(deps.as_ref(), &config.stake_operators, &state)?;
+    expire_locks(deps.storage, &mut state, now)?;
+    calculate_global_index(
+        state.virtual_reward_balance,
+        state.total_weight(),
+        &mut state.virtual_rewards,
+    )?;
+    calculate_global_index(
+        query_token_balance(deps.as_ref(), &config.reward_token, &env.contract.address),
+        state.total_weight(),
+        &mut state.real_rewards,
+    )?;
+
+    staker.real_pending_rewards = sub(
+        staker.real_pending_rewards,
+        Decimal::from_ratio(surplus, Uint128::new(1)),
+    );
+    state.stranded_surplus -= surplus;
+    // surplus is distributed as new real rewards on next global index update
+    // or leaves the contract balance
+    state.real_rewards.prev_balance -= surplus;
+
+    save_staker(deps.storage, &address, &staker)?;
+    save_state(deps.storage, &state)?;
+
+    let resp = Response::new()
+        .add_attribute("action", "collect_surplus")
+        .add_event(
+            StakingSurplusCollected {
+                staker: address.to_string(),
+                surplus,
+            }
+            .into_event(),
+        );
+
+    Ok(match policy.recipient {
+        SurplusRecipient::Stakers => resp,
+        // validated on config update
+        SurplusRecipient::Address { address } => resp.add_submessage(transfer(
+            &config.reward_token,
+            &Addr::unchecked(address),
+            surplus,
+        )?),
+    })
+}
+
+// Starts or stops surplus tracking when staker weight drops to zero or grows back.
+fn track_exit(state: &mut State, staker: &mut Staker, now: u64) {
+    match (staker.exited_at, staker.weight().is_zero()) {
+        (None, true) => {
+            staker.exited_at = Some(now);
+            state.stranded_surplus += staker.surplus();
+        }
+        (Some(_), false) => {
+            staker.exited_at = None;
+            state.stranded_surplus -= staker.surplus();
+        }
+        _ => {}
+    }
+}
+
+pub fn prune_stakers(
+    deps: DepsMut,
+    env: Env,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let config = load_config(deps.storage)?;
+    let start_after = start_after
+        .map(|addr| deps.api.addr_validate(&addr))
+        .transpose()?;
+    let limit = limit.unwrap_or(DEFAULT_SWEEP_LIMIT).min(MAX_SWEEP_LIMIT) as usize;
+
+    let mut state: State = load_state(deps.storage)?;
+    state.staking_total_balance =
+        get_staking_total_balance(deps.as_ref(), &config.stake_operators, &state)?;
+    expire_locks(deps.storage, &mut state, get_time(&env.block))?;
+    calculate_global_index(
+        state.virtual_reward_balance,
+        state.total_weight(),
+        &mut state.virtual_rewards,
+    )?;
+    calculate_global_index(
+        query_token_balance(deps.as_ref(), &config.reward_token, &env.contract.address),
+        state.total_weight(),
+        &mut state.real_rewards,
+    )?;
+
+    let stakers = load_stakers(deps.storage, start_after.as_ref(), limit)?;
+    let last_staker = stakers.last().map(|(addr, _)| addr.to_string());
+    let mut pruned: u32 = 0;
+    for (addr, mut staker) in stakers {
+        staker.balance =
+            get_staker_balance(deps.as_ref(), &config.stake_operators, &staker, &addr)?;
+        accrue_rewards(&state, &mut staker)?;
+        if staker.is_dust() {
+            prune_staker(deps.storage, &mut state, &addr, &staker)?;
+            pruned += 1;
+        }
+    }
+    save_state(deps.storage, &state)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "prune_stakers")
+        .add_attribute("pruned", pruned.to_string())
+        .add_attribute("last_staker", last_staker.unwrap_or_default()))
+}
+
+// Applies the rest of contract migration to the next page of staker entries.
+pub fn migrate_stakers(deps: DepsMut, limit: Option<u32>) -> Result<Response, ContractError> {
+    let mut migration =
+        load_stakers_migration(deps.storage)?.ok_or(ContractError::NoStakersMigration {})?;
+    let limit = limit.unwrap_or(DEFAULT_SWEEP_LIMIT).min(MAX_SWEEP_LIMIT) as usize;
+
+    let mut state: State = load_state(deps.storage)?;
+    let stakers = load_stakers(deps.storage, migration.start_after.as_ref(), limit)?;
+    let finished = stakers.len() < limit;
+    migration.start_after = stakers.last().map(|(addr, _)| addr.clone());
+
+    let migrated = stakers.len();
+    // bonded balance is set on load and first balance snapshot on save
+    for (addr, mut staker) in stakers {
+        // stakers exited before surplus tracking start their grace period now
+        if staker.exited_at.is_none() && staker.weight().is_zero() {
+            staker.exited_at = Some(migration.exited_at);
+            state.stranded_surplus += staker.surplus();
+        }
+        save_staker(deps.storage, &addr, &staker)?;
+    }
+    save_state(deps.storage, &state)?;
+
+    if finished {
+        remove_stakers_migration(deps.storage);
+    } else {
+        save_stakers_migration(deps.storage, &migration)?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "migrate_stakers")
+        .add_attribute("migrated", migrated.to_string())
+        .add_attribute("finished", finished.to_string()))
+}
+
+fn save_or_prune_staker(
+    storage: &mut dyn Storage,
+    state: &mut State,
+    addr: &Addr,
+    staker: &Staker,
+) -> StdResult<()> {
+    if staker.is_dust() {
+        prune_staker(storage, state, addr, staker)?;
+        return Ok(());
+    }
+    save_staker(storage, addr, staker)
+}
+
+// Removes staker entry, its pending dust goes to remaining stakers.
+// Rewards have to be accrued with the current global indices before.
+fn prune_staker(
+    storage: &mut dyn Storage,
+    state: &mut State,
+    addr: &Addr,
+    staker: &Staker,
+) -> StdResult<()> {
+    remove_staker(storage, addr)?;
+
+    let total_weight = state.total_weight();
+    if total_weight.is_zero() {
+        return Ok(());
+    }
+    let total_weight = Decimal::from_ratio(total_weight, Uint128::new(1));
+    state.real_rewards.global_index = sum(
+        state.real_rewards.global_index,
+        div(staker.real_pending_rewards, total_weight),
+    );
+    state.virtual_rewards.global_index = sum(
+        state.virtual_rewards.global_index,
+        div(staker.virtual_pending_rewards, total_weight),
+    );
+    Ok(())
+}
+
+// Moves staker rewards to pending ones at current global indices.
+fn accrue_rewards(state: &State, staker: &mut Staker) -> StdResult<()> {
+    accrue_rewards_until(
+        staker,
+        state.real_rewards.global_index,
+        state.virtual_rewards.global_index,
+    )
+}
+
+fn accrue_rewards_until(
+    staker: &mut Staker,
+    real_index: Decimal,
+    virtual_index: Decimal,
+) -> StdResult<()> {
+    if real_index > staker.real_index {
+        let real_rewards =
+            calculate_decimal_rewards(real_index, staker.real_index, staker.weight())?;
+        staker.real_index = real_index;
+        staker.real_pending_rewards = sum(real_rewards, staker.real_pending_rewards);
+    }
+
+    if virtual_index > staker.virtual_index {
+        let virtual_rewards =
+            calculate_decimal_rewards(virtual_index, staker.virtual_index, staker.weight())?;
+        staker.virtual_index = virtual_index;
+        staker.virtual_pending_rewards = sum(virtual_rewards, staker.virtual_pending_rewards);
+    }
+
+    Ok(())
+}
+
+// Removes boosts of locks ended since the last update from total weight, before new rewards
+// are indexed. Returned expirations keep indexes of this moment for stakers to release against.
+pub fn expire_lock_boosts(
+    storage: &dyn Storage,
+    state: &mut State,
+    now: u64,
+) -> StdResult<Vec<(u64, LockExpiration)>> {
+    if now <= state.locks_expired_until {
+        return Ok(vec![]);
+    }
+
+    let mut expirations = load_lock_expirations(storage, state.locks_expired_until, now)?;
+    for (_, expiration) in expirations.iter_mut() {
+        state.total_lock_boost -= expiration.boost;
+        expiration.indexes = Some((
+            state.real_rewards.global_index,
+            state.virtual_rewards.global_index,
+        ));
+    }
+    state.locks_expired_until = now;
+    Ok(expirations)
+}
+
+fn expire_locks(storage: &mut dyn Storage, state: &mut State, now: u64) -> StdResult<()> {
+    for (time, expiration) in expire_lock_boosts(storage, state, now)? {
+        save_lock_expiration(storage, time, &expiration)?;
+    }
+    Ok(())
+}
+
+// Drops expired locks of the staker, boost earns rewards only until its expiration.
+// `unsaved` are expirations not written to storage yet. Returns released boost
+// and expiration times of released scheduled locks.
+pub fn release_staker_locks(
+    storage: &dyn Storage,
+    state: &mut State,
+    staker: &mut Staker,
+    now: u64,
+    unsaved: &[(u64, LockExpiration)],
+) -> StdResult<(Uint128, Vec<u64>)> {
+    let (mut expired, active): (Vec<Lock>, Vec<Lock>) = staker
+        .locks
+        .drain(..)
+        .partition(|lock| lock.unlock_time <= now);
+    staker.locks = active;
+    // scheduled locks go first and by time, their indexes only grow
+    expired.sort_by_key(|lock| (!lock.scheduled, lock.unlock_time));
+
+    let mut released_boost = Uint128::zero();
+    let mut released = vec![];
+    for lock in expired {
+        if lock.scheduled {
+            let indexes = match unsaved.iter().find(|(time, _)| *time == lock.unlock_time) {
+                Some((_, expiration)) => expiration.indexes,
+                None => load_lock_expiration(storage, lock.unlock_time)?.indexes,
+            }
+            .ok_or_else(|| StdError::generic_err("lock boost hasn't expired yet"))?;
+            accrue_rewards_until(staker, indexes.0, indexes.1)?;
+            released.push(lock.unlock_time);
+        } else {
+            // older locks keep their boost in total weight until released
+            accrue_rewards(state, staker)?;
+            state.total_lock_boost -= lock.boost();
+        }
+        staker.lock_boost -= lock.boost();
+        released_boost += lock.boost();
+    }
+
+    Ok((released_boost, released))
+}
+
+fn release_expired_locks(
+    storage: &mut dyn Storage,
+    state: &mut State,
+    staker: &mut Staker,
+    now: u64,
+) -> StdResult<Uint128> {
+    let (released_boost, released) = release_staker_locks(storage, state, staker, now, &[])?;
+    for time in released {
+        let mut expiration = load_lock_expiration(storage, time)?;
+        expiration.locks -= 1;
+        save_lock_expiration(storage, time, &expiration)?;
+    }
+    Ok(released_boost)
+}
+
+fn schedule_lock(storage: &mut dyn Storage, lock: &Lock) -> StdResult<()> {
+    let mut expiration = load_lock_expiration(storage, lock.unlock_time)?;
+    expiration.boost += lock.boost();
+    expiration.locks += 1;
+    save_lock_expiration(storage, lock.unlock_time, &expiration)
+}
+
+// Donated tokens are already on contract balance, so only virtual rewards are registered.
+fn donate(deps: DepsMut, donor: String, amount: Uint128) -> Result<Response, ContractError> {
+    let donor = deps.api.addr_validate(&donor)?;
+
+    let mut state = load_state(deps.storage)?;
+    state.virtual_reward_balance += amount;
+    state.total_donated += amount;
+    save_state(deps.storage, &state)?;
+
+    let donated = load_donated(deps.storage, &donor)? + amount;
+    save_donated(deps.storage, &donor, donated)?;
+
+    Ok(Response::new().add_attribute("action", "donate").add_event(
+        StakingDonated {
+            donor: donor.to_string(),
+            amount,
+        }
+        .into_event(),
+    ))
 }
 
 pub fn reward(deps: DepsMut, amount: Uint128) -> Result<Response, ContractError> {
@@ -460,7 +1680,7 @@ pub fn reward(deps: DepsMut, amount: Uint128) -> Result<Response, ContractError>
 
     Ok(Response::new()
         .add_attribute("action", "reward")
-        .add_attribute("amount", amount))
+        .add_event(StakingRewarded { amount }.into_event()))
 }
 
 fn get_time(block: &BlockInfo) -> u64 {
@@ -469,36 +1689,56 @@ fn get_time(block: &BlockInfo) -> u64 {
 
 pub fn get_staking_total_balance(
     deps: Deps,
-    stake_operator: Option<Addr>,
+    stake_operators: &[Addr],
     state: &State,
 ) -> Result<Uint128, ContractError> {
-    Ok(if let Some(stake_operator) = stake_operator {
+    let mut total = state.total_bonded;
+    for stake_operator in stake_operators {
         let s: StakeOperatorStateResponse = deps
             .querier
             .query_wasm_smart(stake_operator, &StakeOperatorQueryMsg::State {})?;
-        s.total_share
-    } else {
-        state.staking_total_balance
-    })
+        total += s.total_share;
+    }
+    Ok(total)
 }
 
 pub fn get_staker_balance(
     deps: Deps,
-    stake_operator: Option<Addr>,
+    stake_operators: &[Addr],
     staker: &Staker,
     addr: &Addr,
 ) -> Result<Uint128, ContractError> {
-    Ok(if let Some(stake_operator) = stake_operator {
+    let mut balance = staker.bonded;
+    for stake_operator in stake_operators {
         let s: StakeOperatorStakerResponse = deps.querier.query_wasm_smart(
             stake_operator,
             &StakeOperatorQueryMsg::Staker {
                 address: addr.to_string(),
             },
         )?;
-        s.balance
-    } else {
-        staker.balance
-    })
+        balance += s.balance;
+    }
+    Ok(balance)
+}
+
+pub fn transfer_or_send(
+    token: &Addr,
+    recipient: &Addr,
+    amount: Uint128,
+    msg: Option<Binary>,
+) -> StdResult<SubMsg> {
+    match msg {
+        Some(msg) => Ok(SubMsg::new(WasmMsg::Execute {
+            contract_addr: token.to_string(),
+            msg: to_binary(&Cw20ExecuteMsg::Send {
+                contract: recipient.to_string(),
+                amount,
+                msg,
+            })?,
+            funds: vec![],
+        })),
+        None => transfer(token, recipient, amount),
+    }
 }
 
 pub fn prism_xprism_swap(
@@ -532,3 +1772,76 @@ pub fn prism_xprism_swap_and_reply(
         ReplyId::XPrismTokensMinted.into(),
     ))
 }
+
+// Vault mints nexPRISM for xPRISM 1:1, so the pair is used only when it gives more.
+// Otherwise xPRISM is deposited to the vault and claims don't depend on the pair price.
+pub fn buy_nexprism(
+    deps: Deps,
+    xprism_token: &Addr,
+    nexprism_token: &Addr,
+    nexprism_xprism_pair: &Addr,
+    amount: Uint128,
+    to: Option<&Addr>,
+) -> StdResult<WasmMsg> {
+    let simulation: SimulationResponse = deps.querier.query_wasm_smart(
+        nexprism_xprism_pair,
+        &astroport::pair::QueryMsg::Simulation {
+            offer_asset: Asset {
+                info: AssetInfo::Token {
+                    contract_addr: xprism_token.clone(),
+                },
+                amount,
+            },
+        },
+    )?;
+
+    if simulation.return_amount > amount {
+        return send_wasm_msg(
+            xprism_token,
+            nexprism_xprism_pair,
+            amount,
+            &astroport::pair::Cw20HookMsg::Swap {
+                belief_price: Some(Decimal::one()),
+                max_spread: Some(Decimal::zero()),
+                to: to.map(|to| to.to_string()),
+            },
+        );
+    }
+
+    // nexPRISM is minted by the vault
+    let minter: Option<MinterResponse> = deps
+        .querier
+        .query_wasm_smart(nexprism_token, &Cw20QueryMsg::Minter {})?;
+    let vault = minter
+        .ok_or_else(|| StdError::generic_err("nexPRISM minter not found"))?
+        .minter;
+    send_wasm_msg(
+        xprism_token,
+        &Addr::unchecked(vault),
+        amount,
+        &nexus_prism_protocol::vault::Cw20HookMsg::Deposit {
+            recipient: to.map(|to| to.to_string()),
+            then: None,
+            best_route: false,
+        },
+    )
+}
+
+pub fn query_nexprism_token(deps: Deps, pair: &Addr, xprism_token: &Addr) -> StdResult<Addr> {
+    let pair_info: astroport::asset::PairInfo = deps
+        .querier
+        .query_wasm_smart(pair, &astroport::pair::QueryMsg::Pair {})?;
+
+    pair_info
+        .asset_infos
+        .iter()
+        .find_map(|info| match info {
+            astroport::asset::AssetInfo::Token { contract_addr }
+                if contract_addr != xprism_token =>
+            {
+                Some(contract_addr.clone())
+            }
+            _ => None,
+        })
+        .ok_or_else(|| StdError::generic_err("nexPRISM token not found in pair"))
+}
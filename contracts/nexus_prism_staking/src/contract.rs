@@ -1,19 +1,25 @@
 use std::convert::TryFrom;
 
 use cosmwasm_std::{
-    entry_point, to_binary, Binary, Decimal, Deps, DepsMut, Env, MessageInfo, Reply, Response,
-    StdError, StdResult, Uint128,
+    entry_point, to_binary, Addr, Binary, Decimal, Deps, DepsMut, Env, MessageInfo, Reply,
+    Response, StdError, StdResult, SubMsg, Uint128,
 };
 use cw0::nonpayable;
 use cw2::{get_contract_version, set_contract_version};
-use nexus_prism_protocol::common::{optional_addr_validate, send};
 
 use crate::commands::{
-    accept_governance, claim_rewards, claim_rewards_for_someone, decrease_balance,
-    increase_balance, receive_cw20, reward, unbond, update_global_index, update_governance,
+    accept_governance, batch_update_balances, buy_nexprism, claim_rewards,
+    claim_rewards_for_someone, collect_surplus, decrease_balance, increase_balance,
+    query_nexprism_token, receive_cw20, release_locks, reward, set_payout_preference,
+    transfer_or_send, transfer_stake, unbond, update_global_index, update_governance,
+    validate_payout_mode, validate_stake_operators, withdraw_vested_rewards,
 };
 use crate::replies_id::ReplyId;
-use crate::state::{Config, REPLY_CONTEXT};
+use crate::state::{
+    is_surplus_tracked, is_total_staked_snapshotted, load_legacy_config,
+    load_legacy_stake_operator, load_stakers_migration, load_state, save_stakers_migration,
+    save_total_staked_snapshot, Config, Payout, StakersMigration, REPLY_CONTEXT,
+};
 use crate::{
     commands,
     error::ContractError,
@@ -23,7 +29,7 @@ use crate::{
 use nexus_prism_protocol::{
     common::query_token_balance,
     staking::{
-        AnyoneMsg, ExecuteMsg, GovernanceMsg, InstantiateMsg, MigrateMsg, QueryMsg,
+        AnyoneMsg, ExecuteMsg, GovernanceMsg, InstantiateMsg, MigrateMsg, PayoutMode, QueryMsg,
         RewardOperatorMsg, StakeOperatorMsg,
     },
 };
@@ -34,21 +40,26 @@ const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 #[entry_point]
 pub fn instantiate(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
     nonpayable(&info)?;
 
+    let reward_token = deps.api.addr_validate(&msg.reward_token)?;
     let config = Config {
         governance: deps.api.addr_validate(&msg.governance)?,
         staking_token: deps.api.addr_validate(&msg.staking_token)?,
-        stake_operator: optional_addr_validate(deps.as_ref(), msg.stake_operator)?,
-        reward_token: deps.api.addr_validate(&msg.reward_token)?,
+        stake_operators: validate_stake_operators(deps.as_ref(), msg.stake_operators)?,
+        direct_bonding: msg.direct_bonding,
         reward_operator: deps.api.addr_validate(&msg.reward_operator)?,
-        xprism_token: optional_addr_validate(deps.as_ref(), msg.xprism_token)?,
-        prism_governance: optional_addr_validate(deps.as_ref(), msg.prism_governance)?,
-        nexprism_xprism_pair: optional_addr_validate(deps.as_ref(), msg.nexprism_xprism_pair)?,
+        reward_operators: vec![],
+        payout: validate_payout_mode(deps.as_ref(), &reward_token, msg.payout_mode)?,
+        reward_token,
+        lockup_tiers: vec![],
+        surplus_policy: None,
+        reward_vesting: None,
+        escrow_exempt: vec![],
     };
     save_config(deps.storage, &config)?;
 
@@ -65,9 +76,16 @@ pub fn instantiate(
                 global_index: Decimal::zero(),
                 prev_balance: Uint128::zero(),
             },
+            total_lock_boost: Uint128::zero(),
+            total_bonded: Uint128::zero(),
+            stranded_surplus: Uint128::zero(),
+            total_donated: Uint128::zero(),
+            locks_expired_until: 0,
         },
     )?;
 
+    save_total_staked_snapshot(deps.storage, Uint128::zero(), env.block.height)?;
+
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
 
     Ok(Response::new().add_attribute("action", "instantiate"))
@@ -86,33 +104,56 @@ pub fn execute(
         ExecuteMsg::Receive(msg) => receive_cw20(deps, env, info, msg),
 
         ExecuteMsg::Anyone { anyone_msg } => match anyone_msg {
-            AnyoneMsg::Unbond { amount } => unbond(deps, env, info, amount),
+            AnyoneMsg::Unbond {
+                amount,
+                recipient,
+                claim_rewards,
+            } => unbond(deps, env, info, amount, recipient, claim_rewards),
+            AnyoneMsg::TransferStake { recipient, amount } => {
+                transfer_stake(deps, env, info, recipient, amount)
+            }
             AnyoneMsg::UpdateGlobalIndex {} => update_global_index(deps, env),
-            AnyoneMsg::ClaimRewards { recipient } => claim_rewards(deps, env, info, recipient),
+            AnyoneMsg::ClaimRewards { recipient, msg } => {
+                claim_rewards(deps, env, info, recipient, msg)
+            }
             AnyoneMsg::ClaimRewardsForSomeone { address } => {
                 claim_rewards_for_someone(deps, env, address)
             }
+            AnyoneMsg::ReleaseExpiredLocks { address } => release_locks(deps, env, address),
+            AnyoneMsg::CollectSurplus { address } => collect_surplus(deps, env, address),
+            AnyoneMsg::SetPayoutPreference { preference } => {
+                set_payout_preference(deps, info, preference)
+            }
+            AnyoneMsg::WithdrawVestedRewards { recipient, msg } => {
+                withdraw_vested_rewards(deps, env, info, recipient, msg, false)
+            }
+            AnyoneMsg::ExitVesting { recipient, msg } => {
+                withdraw_vested_rewards(deps, env, info, recipient, msg, true)
+            }
             AnyoneMsg::AcceptGovernance {} => accept_governance(deps, env, info),
         },
 
         ExecuteMsg::StakeOperator { msg } => {
             let config = load_config(deps.storage)?;
-            if Some(info.sender) != config.stake_operator {
+            if !config.is_stake_operator(&info.sender) {
                 return Err(ContractError::Unauthorized);
             }
             match msg {
                 StakeOperatorMsg::IncreaseBalance { staker, amount } => {
-                    increase_balance(deps, env, &config, staker, amount)
+                    increase_balance(deps, env, &config, staker, amount, None, true)
                 }
                 StakeOperatorMsg::DecreaseBalance { staker, amount } => {
-                    decrease_balance(deps, env, &config, staker, amount)
+                    decrease_balance(deps, env, &config, staker, amount, true)
+                }
+                StakeOperatorMsg::BatchUpdateBalances { updates } => {
+                    batch_update_balances(deps, env, &config, updates)
                 }
             }
         }
 
         ExecuteMsg::RewardOperator { msg } => {
             let config = load_config(deps.storage)?;
-            if info.sender != config.reward_operator {
+            if !config.is_reward_operator(&info.sender) {
                 return Err(ContractError::Unauthorized);
             }
             match msg {
@@ -127,20 +168,38 @@ pub fn execute(
             }
             match governance_msg {
                 GovernanceMsg::UpdateConfig {
-                    stake_operator,
+                    stake_operators,
+                    direct_bonding,
                     reward_operator,
-                    nexprism_xprism_pair,
+                    reward_operators,
+                    payout_mode,
+                    lockup_tiers,
+                    surplus_policy,
+                    reward_vesting,
+                    escrow_exempt,
                 } => commands::update_config(
                     deps,
                     config,
-                    stake_operator,
+                    stake_operators,
+                    direct_bonding,
                     reward_operator,
-                    nexprism_xprism_pair,
+                    reward_operators,
+                    payout_mode,
+                    lockup_tiers,
+                    surplus_policy,
+                    reward_vesting,
+                    escrow_exempt,
                 ),
                 GovernanceMsg::UpdateGovernance {
                     gov_addr,
                     seconds_to_wait_for_accept_gov_tx,
                 } => update_governance(deps, env, gov_addr, seconds_to_wait_for_accept_gov_tx),
+                GovernanceMsg::AddHook { addr } => commands::add_hook(deps, addr),
+                GovernanceMsg::RemoveHook { addr } => commands::remove_hook(deps, addr),
+                GovernanceMsg::PruneStakers { start_after, limit } => {
+                    commands::prune_stakers(deps, env, start_after, limit)
+                }
+                GovernanceMsg::MigrateStakers { limit } => commands::migrate_stakers(deps, limit),
             }
         }
     }
@@ -154,23 +213,88 @@ pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> Result<Response, ContractEr
         ReplyId::try_from(msg.id).map_err(|_| ContractError::UnknownReplyId { id: msg.id })?;
 
     match reply_id {
-        ReplyId::XPrismTokensMinted => match (config.xprism_token, config.nexprism_xprism_pair) {
-            (Some(xprism_token), Some(nexprism_xprism_pair)) => {
+        ReplyId::XPrismTokensMinted => {
+            let mut context = REPLY_CONTEXT.load(deps.storage)?;
+            let xprism_token = config
+                .payout
+                .xprism_token()
+                .cloned()
+                .ok_or(ContractError::InvalidConfig {})?;
+            let xprism_amount =
+                query_token_balance(deps.as_ref(), &xprism_token, &env.contract.address)
+                    .checked_sub(context.balance_before)
+                    .map_err(StdError::from)?;
+            let resp = Response::new()
+                .add_attribute("minted_xprism_amount", xprism_amount)
+                .add_attribute("recipient", context.rewards_recipient.clone());
+
+            if !context.buy_nexprism {
+                return Ok(resp.add_submessage(transfer_or_send(
+                    &xprism_token,
+                    &context.rewards_recipient,
+                    xprism_amount,
+                    context.rewards_msg,
+                )?));
+            }
+
+            let nexprism_xprism_pair = config
+                .payout
+                .nexprism_xprism_pair()
+                .cloned()
+                .ok_or(ContractError::InvalidConfig {})?;
+            let nexprism_token =
+                query_nexprism_token(deps.as_ref(), &nexprism_xprism_pair, &xprism_token)?;
+
+            if context.rewards_msg.is_none() {
+                return Ok(resp.add_submessage(SubMsg::new(buy_nexprism(
+                    deps.as_ref(),
+                    &xprism_token,
+                    &nexprism_token,
+                    &nexprism_xprism_pair,
+                    xprism_amount,
+                    Some(&context.rewards_recipient),
+                )?)));
+            }
+
+            // bought nexPRISM is sent with the message in the next reply
+            context.balance_before =
+                query_token_balance(deps.as_ref(), &nexprism_token, &env.contract.address);
+            REPLY_CONTEXT.save(deps.storage, &context)?;
+
+            Ok(resp.add_submessage(SubMsg::reply_on_success(
+                buy_nexprism(
+                    deps.as_ref(),
+                    &xprism_token,
+                    &nexprism_token,
+                    &nexprism_xprism_pair,
+                    xprism_amount,
+                    None,
+                )?,
+                ReplyId::NexPrismTokensBought.into(),
+            )))
+        }
+
+        ReplyId::NexPrismTokensBought => match config.payout {
+            Payout::Nexprism {
+                xprism_token,
+                nexprism_xprism_pair,
+                ..
+            } => {
                 let context = REPLY_CONTEXT.load(deps.storage)?;
-                let xprism_balance =
-                    query_token_balance(deps.as_ref(), &xprism_token, &env.contract.address);
+                let nexprism_token =
+                    query_nexprism_token(deps.as_ref(), &nexprism_xprism_pair, &xprism_token)?;
+                let nexprism_amount =
+                    query_token_balance(deps.as_ref(), &nexprism_token, &env.contract.address)
+                        .checked_sub(context.balance_before)
+                        .map_err(StdError::from)?;
                 Ok(Response::new()
-                    .add_submessage(send(
-                        &xprism_token,
-                        &nexprism_xprism_pair,
-                        xprism_balance,
-                        &astroport::pair::Cw20HookMsg::Swap {
-                            belief_price: None,
-                            max_spread: None,
-                            to: Some(context.rewards_recipient.to_string()),
-                        },
+                    .add_submessage(transfer_or_send(
+                        &nexprism_token,
+                        &context.rewards_recipient,
+                        nexprism_amount,
+                        context.rewards_msg,
                     )?)
-                    .add_attribute("minted_xprism_amount", xprism_balance)
+                    .add_attribute("bought_nexprism_amount", nexprism_amount)
                     .add_attribute("recipient", context.rewards_recipient))
             }
             _ => Err(ContractError::InvalidConfig {}),
@@ -182,9 +306,25 @@ pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> Result<Response, ContractEr
 pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::Config {} => to_binary(&queries::query_config(deps)?),
-        QueryMsg::State {} => to_binary(&queries::query_state(deps)?),
-        QueryMsg::Rewards { address } => to_binary(&queries::query_rewards(deps, address)?),
+        QueryMsg::State {} => to_binary(&queries::query_state(deps, env)?),
+        QueryMsg::Rewards { address } => to_binary(&queries::query_rewards(deps, env, address)?),
         QueryMsg::Staker { address } => to_binary(&queries::query_staker(deps, env, address)?),
+        QueryMsg::Locks { address } => to_binary(&queries::query_locks(deps, env, address)?),
+        QueryMsg::Donations { donor } => to_binary(&queries::query_donations(deps, donor)?),
+        QueryMsg::Surplus { address } => to_binary(&queries::query_surplus(deps, address)?),
+        QueryMsg::VestingEntries { address } => {
+            to_binary(&queries::query_vesting_entries(deps, env, address)?)
+        }
+        QueryMsg::ClaimableRewards { address } => {
+            to_binary(&queries::query_claimable_rewards(deps, env, address)?)
+        }
+        QueryMsg::BalanceAt { address, height } => {
+            to_binary(&queries::query_balance_at(deps, address, height)?)
+        }
+        QueryMsg::TotalStakedAt { height } => {
+            to_binary(&queries::query_total_staked_at(deps, height)?)
+        }
+        QueryMsg::Hooks {} => to_binary(&queries::query_hooks(deps)?),
         QueryMsg::GetPotentialRewards {
             potential_rewards_total,
             address,
@@ -198,7 +338,7 @@ pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
 }
 
 #[entry_point]
-pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> StdResult<Response> {
+pub fn migrate(mut deps: DepsMut, env: Env, msg: MigrateMsg) -> StdResult<Response> {
     let ver = get_contract_version(deps.storage)?;
 
     if ver.contract != CONTRACT_NAME {
@@ -209,7 +349,1907 @@ pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> StdResult<Response
         return Err(StdError::generic_err("Cannot upgrade from a newer version"));
     }
 
+    if load_stakers_migration(deps.storage)?.is_some() {
+        return Err(StdError::generic_err("stakers migration is not finished"));
+    }
+
+    let legacy_stake_operator = load_legacy_stake_operator(deps.storage)?;
+    let surplus_tracked = is_surplus_tracked(deps.storage)?;
+
+    // config stored before payout mode
+    if load_config(deps.storage).is_err() {
+        migrate_legacy_config(deps.branch(), msg.xprism_token)?;
+    }
+
+    // config stored before multiple stake operators
+    let mut set_bonded = false;
+    if let Some(stake_operator) = legacy_stake_operator {
+        set_bonded = migrate_stake_operator(deps.branch(), stake_operator)?;
+    }
+
+    let mut snapshot_height = None;
+    if !is_total_staked_snapshotted(deps.storage)? {
+        let state = load_state(deps.storage)?;
+        save_total_staked_snapshot(deps.storage, state.staking_total_balance, env.block.height)?;
+        snapshot_height = Some(env.block.height);
+    }
+
+    // staker entries are updated with `MigrateStakers`, only when stored with an older layout
+    if set_bonded || !surplus_tracked || snapshot_height.is_some() {
+        save_stakers_migration(
+            deps.storage,
+            &StakersMigration {
+                start_after: None,
+                set_bonded,
+                exited_at: env.block.time.seconds(),
+                snapshot_height,
+            },
+        )?;
+    }
+
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
 
     Ok(Response::new())
 }
+
+// Without stake operator all staked tokens were bonded directly,
+// returns whether staker entries have to be updated.
+fn migrate_stake_operator(deps: DepsMut, stake_operator: Option<Addr>) -> StdResult<bool> {
+    let mut config = load_config(deps.storage)?;
+    config.direct_bonding = stake_operator.is_none();
+    config.stake_operators = stake_operator.into_iter().collect();
+    save_config(deps.storage, &config)?;
+
+    if !config.direct_bonding {
+        return Ok(false);
+    }
+
+    let mut state = load_state(deps.storage)?;
+    state.total_bonded = state.staking_total_balance;
+    save_state(deps.storage, &state)?;
+    Ok(true)
+}
+
+fn migrate_legacy_config(deps: DepsMut, xprism_token: Option<String>) -> StdResult<()> {
+    let legacy = load_legacy_config(deps.storage)?;
+
+    let xprism_token = legacy
+        .xprism_token
+        .map(|addr| addr.to_string())
+        .or(xprism_token);
+    let payout_mode = match (legacy.prism_governance, legacy.nexprism_xprism_pair) {
+        (None, None) => PayoutMode::Prism {},
+        (Some(prism_governance), nexprism_xprism_pair) => {
+            let xprism_token = xprism_token.ok_or_else(|| {
+                StdError::generic_err("xprism_token is required to migrate xPRISM payout")
+            })?;
+            match nexprism_xprism_pair {
+                None => PayoutMode::Xprism {
+                    prism_governance: prism_governance.to_string(),
+                    xprism_token,
+                },
+                Some(nexprism_xprism_pair) => PayoutMode::Nexprism {
+                    prism_governance: prism_governance.to_string(),
+                    xprism_token,
+                    nexprism_xprism_pair: nexprism_xprism_pair.to_string(),
+                },
+            }
+        }
+        (None, Some(_)) => return Err(ContractError::InvalidConfig {}.into()),
+    };
+
+    let config = Config {
+        governance: legacy.governance,
+        staking_token: legacy.staking_token,
+        direct_bonding: legacy.stake_operator.is_none(),
+        stake_operators: legacy.stake_operator.into_iter().collect(),
+        payout: validate_payout_mode(deps.as_ref(), &legacy.reward_token, payout_mode)?,
+        reward_token: legacy.reward_token,
+        reward_operator: legacy.reward_operator,
+        reward_operators: vec![],
+        lockup_tiers: vec![],
+        surplus_policy: None,
+        reward_vesting: None,
+        escrow_exempt: vec![],
+    };
+    save_config(deps.storage, &config)
+}
+
+#[cfg(test)]
+mod tests {
+    use astroport::{
+        asset::{AssetInfo, PairInfo},
+        factory::PairType,
+        pair::{Cw20HookMsg as PairHookMsg, QueryMsg as PairQueryMsg, SimulationResponse},
+    };
+    use cosmwasm_std::{
+        from_binary,
+        testing::{mock_env, mock_info, MockApi, MockStorage, MOCK_CONTRACT_ADDR},
+        to_binary, Addr, Binary, ContractResult, Decimal, Env, OwnedDeps, Reply, Response,
+        StdError, StdResult, SubMsg, SubMsgExecutionResponse, Uint128, WasmMsg,
+    };
+    use cw20::{Cw20QueryMsg, Cw20ReceiveMsg, MinterResponse};
+    use cw_storage_plus::{Item, Map};
+    use nexus_prism_protocol::{
+        common::{send, send_wasm_msg, transfer},
+        events::{
+            parse_events, StakingBalanceChanged, StakingBalancesBatchUpdated, StakingDonated,
+            StakingRewardsClaimed,
+        },
+        staking::{
+            AnyoneMsg, BalanceDelta, ClaimableRewardsResponse, ConfigResponse, Cw20HookMsg,
+            ExecuteMsg, GovernanceMsg, InstantiateMsg, LockupTier, PayoutMode, PayoutPreference,
+            QueryMsg, RewardOperatorMsg, RewardVesting, RewardsLimit, RewardsResponse,
+            StakeChangedHookMsg, StakeOperatorMsg, StakeOperatorQueryMsg,
+            StakeOperatorStakerResponse, StakeOperatorStateResponse, StakerResponse, StateResponse,
+            SurplusPolicy, SurplusRecipient,
+        },
+        testing::{mock_dependencies, WasmMockQuerier},
+        vault::{
+            Cw20HookMsg as VaultHookMsg, PrismVestingScheduleResponse,
+            PrismVestingSchedulesResponse, QueryMsg as VaultQueryMsg,
+        },
+    };
+
+    use crate::{
+        commands::{prism_xprism_swap, prism_xprism_swap_and_reply, transfer_or_send},
+        contract::{execute, instantiate, migrate_legacy_config, query, reply},
+        error::ContractError,
+        replies_id::ReplyId,
+        state::{
+            is_unbonded_layout, load_config, load_hooks, load_staker, load_staker_balance_at,
+            load_stakers_migration, load_state, load_vesting_entries, save_stakers_migration,
+            LegacyConfig, Payout, ReplyContext, StakersMigration, VestingEntry, REPLY_CONTEXT,
+            STAKERS,
+        },
+    };
+
+    type MockDeps = OwnedDeps<MockStorage, MockApi, WasmMockQuerier>;
+
+    // Staker entry stored before direct bonding.
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct LegacyStaker {
+        balance: Uint128,
+        real_index: Decimal,
+        real_pending_rewards: Decimal,
+        virtual_index: Decimal,
+        virtual_pending_rewards: Decimal,
+    }
+
+    fn setup() -> MockDeps {
+        setup_with(PayoutMode::Prism {})
+    }
+
+    fn nexprism_payout() -> PayoutMode {
+        PayoutMode::Nexprism {
+            prism_governance: "prism_gov".to_string(),
+            xprism_token: "xprism".to_string(),
+            nexprism_xprism_pair: "pair".to_string(),
+        }
+    }
+
+    fn setup_with(payout_mode: PayoutMode) -> MockDeps {
+        let mut deps = mock_dependencies(&[]);
+        mock_pair(&mut deps.querier);
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("creator", &[]),
+            instantiate_msg(payout_mode),
+        )
+        .unwrap();
+        deps
+    }
+
+    fn instantiate_msg(payout_mode: PayoutMode) -> InstantiateMsg {
+        InstantiateMsg {
+            governance: "gov".to_string(),
+            staking_token: "nexprism".to_string(),
+            stake_operators: vec![],
+            direct_bonding: true,
+            reward_token: "prism".to_string(),
+            reward_operator: "vault".to_string(),
+            payout_mode,
+        }
+    }
+
+    fn mock_pair(querier: &mut WasmMockQuerier) {
+        mock_pair_price(querier, Decimal::percent(110));
+    }
+
+    // `price` is nexPRISM given for one xPRISM
+    fn mock_pair_price(querier: &mut WasmMockQuerier, price: Decimal) {
+        querier.set_handler("pair", move |msg| match from_binary(msg)? {
+            PairQueryMsg::Pair {} => to_binary(&PairInfo {
+                asset_infos: [
+                    AssetInfo::Token {
+                        contract_addr: Addr::unchecked("xprism"),
+                    },
+                    AssetInfo::Token {
+                        contract_addr: Addr::unchecked("nexprism"),
+                    },
+                ],
+                contract_addr: Addr::unchecked("pair"),
+                liquidity_token: Addr::unchecked("lp"),
+                pair_type: PairType::Xyk {},
+            }),
+            PairQueryMsg::Simulation { offer_asset } => to_binary(&SimulationResponse {
+                return_amount: offer_asset.amount * price,
+                spread_amount: Uint128::zero(),
+                commission_amount: Uint128::zero(),
+            }),
+            _ => Err(StdError::generic_err("unexpected pair query")),
+        });
+        querier.set_handler("nexprism", |msg| match from_binary(msg)? {
+            Cw20QueryMsg::Minter {} => to_binary(&Some(MinterResponse {
+                minter: "nexprism_vault".to_string(),
+                cap: None,
+            })),
+            _ => Err(StdError::generic_err("unexpected token query")),
+        });
+    }
+
+    fn bond(
+        deps: &mut MockDeps,
+        sender: &str,
+        amount: u128,
+        recipient: Option<&str>,
+        lock_duration: Option<u64>,
+    ) -> Result<Response, ContractError> {
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("nexprism", &[]),
+            ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: sender.to_string(),
+                amount: Uint128::new(amount),
+                msg: to_binary(&Cw20HookMsg::Bond {
+                    recipient: recipient.map(String::from),
+                    lock_duration,
+                })?,
+            }),
+        )
+    }
+
+    fn anyone(
+        deps: &mut MockDeps,
+        sender: &str,
+        anyone_msg: AnyoneMsg,
+    ) -> Result<Response, ContractError> {
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(sender, &[]),
+            ExecuteMsg::Anyone { anyone_msg },
+        )
+    }
+
+    #[test]
+    fn bond_for_recipient() {
+        let mut deps = setup();
+
+        let resp = bond(&mut deps, "router", 100, Some("alice"), None).unwrap();
+        let changed: Vec<StakingBalanceChanged> = parse_events(&resp.events).unwrap();
+        assert_eq!(changed[0].staker, "alice");
+
+        let alice = load_staker(&deps.storage, &Addr::unchecked("alice")).unwrap();
+        assert_eq!(alice.balance, Uint128::new(100));
+        assert_eq!(alice.bonded, Uint128::new(100));
+        assert!(STAKERS
+            .may_load(&deps.storage, &Addr::unchecked("router"))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn unbond_to_recipient() {
+        let mut deps = setup();
+        bond(&mut deps, "alice", 100, None, None).unwrap();
+
+        let resp = anyone(
+            &mut deps,
+            "alice",
+            AnyoneMsg::Unbond {
+                amount: Some(Uint128::new(40)),
+                recipient: Some("bob".to_string()),
+                claim_rewards: false,
+            },
+        )
+        .unwrap();
+        assert!(resp.messages.contains(
+            &transfer(
+                &Addr::unchecked("nexprism"),
+                &Addr::unchecked("bob"),
+                Uint128::new(40)
+            )
+            .unwrap()
+        ));
+
+        let alice = load_staker(&deps.storage, &Addr::unchecked("alice")).unwrap();
+        assert_eq!(alice.bonded, Uint128::new(60));
+        assert!(STAKERS
+            .may_load(&deps.storage, &Addr::unchecked("bob"))
+            .unwrap()
+            .is_none());
+    }
+
+    fn governance(
+        deps: &mut MockDeps,
+        governance_msg: GovernanceMsg,
+    ) -> Result<Response, ContractError> {
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::Governance { governance_msg },
+        )
+    }
+
+    fn hook_msg(hook: &str, staker: &str, old_balance: u128, new_balance: u128) -> SubMsg {
+        SubMsg::new(WasmMsg::Execute {
+            contract_addr: hook.to_string(),
+            msg: to_binary(&StakeChangedHookMsg::StakeChangedHook {
+                staker: staker.to_string(),
+                old_balance: Uint128::new(old_balance),
+                new_balance: Uint128::new(new_balance),
+            })
+            .unwrap(),
+            funds: vec![],
+        })
+    }
+
+    #[test]
+    fn hooks_are_registered_once() {
+        let mut deps = setup();
+        let add_hook = |addr: &str| GovernanceMsg::AddHook {
+            addr: addr.to_string(),
+        };
+
+        governance(&mut deps, add_hook("hook")).unwrap();
+        assert_eq!(
+            governance(&mut deps, add_hook("hook")),
+            Err(ContractError::HookAlreadyRegistered {
+                addr: "hook".to_string()
+            })
+        );
+        assert_eq!(
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info("alice", &[]),
+                ExecuteMsg::Governance {
+                    governance_msg: add_hook("alice"),
+                },
+            ),
+            Err(ContractError::Unauthorized)
+        );
+
+        let remove_hook = || GovernanceMsg::RemoveHook {
+            addr: "hook".to_string(),
+        };
+        governance(&mut deps, remove_hook()).unwrap();
+        assert_eq!(
+            governance(&mut deps, remove_hook()),
+            Err(ContractError::HookNotRegistered {
+                addr: "hook".to_string()
+            })
+        );
+        assert!(load_hooks(&deps.storage).unwrap().is_empty());
+    }
+
+    #[test]
+    fn hooks_are_capped() {
+        let mut deps = setup();
+        let add_hook = |addr: String| GovernanceMsg::AddHook { addr };
+        for i in 0..10 {
+            governance(&mut deps, add_hook(format!("hook{}", i))).unwrap();
+        }
+        assert_eq!(
+            governance(&mut deps, add_hook("hook10".to_string())),
+            Err(ContractError::TooManyHooks {})
+        );
+
+        // removed hook frees a slot
+        governance(
+            &mut deps,
+            GovernanceMsg::RemoveHook {
+                addr: "hook0".to_string(),
+            },
+        )
+        .unwrap();
+        governance(&mut deps, add_hook("hook10".to_string())).unwrap();
+        assert_eq!(load_hooks(&deps.storage).unwrap().len(), 10);
+    }
+
+    #[test]
+    fn hooks_are_called_on_balance_change() {
+        let mut deps = setup();
+        for hook in ["hook0", "hook1"] {
+            governance(
+                &mut deps,
+                GovernanceMsg::AddHook {
+                    addr: hook.to_string(),
+                },
+            )
+            .unwrap();
+        }
+
+        let resp = bond(&mut deps, "alice", 100, None, None).unwrap();
+        assert!(resp.messages.contains(&hook_msg("hook0", "alice", 0, 100)));
+        assert!(resp.messages.contains(&hook_msg("hook1", "alice", 0, 100)));
+
+        let resp = anyone(
+            &mut deps,
+            "alice",
+            AnyoneMsg::Unbond {
+                amount: Some(Uint128::new(40)),
+                recipient: None,
+                claim_rewards: false,
+            },
+        )
+        .unwrap();
+        assert!(resp.messages.contains(&hook_msg("hook0", "alice", 100, 60)));
+        assert!(resp.messages.contains(&hook_msg("hook1", "alice", 100, 60)));
+
+        let resp = transfer_stake(&mut deps, "alice", "bob", 10).unwrap();
+        assert!(resp.messages.contains(&hook_msg("hook0", "alice", 60, 50)));
+        assert!(resp.messages.contains(&hook_msg("hook0", "bob", 0, 10)));
+    }
+
+    fn xprism_payout() -> PayoutMode {
+        PayoutMode::Xprism {
+            prism_governance: "prism_gov".to_string(),
+            xprism_token: "xprism".to_string(),
+        }
+    }
+
+    // Registers rewards the way vault does: virtual ones with a message, real ones with transfer.
+    fn reward(deps: &mut MockDeps, amount: u128, real_balance: u128) {
+        deps.querier
+            .set_token_balance("prism", MOCK_CONTRACT_ADDR, real_balance);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("vault", &[]),
+            ExecuteMsg::RewardOperator {
+                msg: RewardOperatorMsg::Reward {
+                    amount: Uint128::new(amount),
+                },
+            },
+        )
+        .unwrap();
+    }
+
+    fn set_surplus_policy(
+        deps: &mut MockDeps,
+        surplus_policy: Option<SurplusPolicy>,
+    ) -> Result<Response, ContractError> {
+        governance(
+            deps,
+            GovernanceMsg::UpdateConfig {
+                stake_operators: None,
+                direct_bonding: None,
+                reward_operator: None,
+                reward_operators: None,
+                payout_mode: None,
+                lockup_tiers: None,
+                surplus_policy: Some(surplus_policy),
+                reward_vesting: None,
+                escrow_exempt: None,
+            },
+        )
+    }
+
+    #[test]
+    fn surplus_policy_is_validated() {
+        let mut deps = setup();
+        let policy = |grace_period: u64, address: &str| SurplusPolicy {
+            grace_period,
+            recipient: SurplusRecipient::Address {
+                address: address.to_string(),
+            },
+        };
+
+        assert_eq!(
+            set_surplus_policy(&mut deps, Some(policy(0, "treasury"))),
+            Err(ContractError::InvalidConfig {})
+        );
+        assert!(set_surplus_policy(&mut deps, Some(policy(100, ""))).is_err());
+
+        set_surplus_policy(&mut deps, Some(policy(100, "treasury"))).unwrap();
+        assert_eq!(
+            load_config(&deps.storage).unwrap().surplus_policy,
+            Some(policy(100, "treasury"))
+        );
+
+        // missing field keeps the policy, null clears it
+        let keep: GovernanceMsg =
+            from_binary(&Binary::from(br#"{"update_config":{}}"#.to_vec())).unwrap();
+        governance(&mut deps, keep).unwrap();
+        assert!(load_config(&deps.storage).unwrap().surplus_policy.is_some());
+
+        let clear: GovernanceMsg = from_binary(&Binary::from(
+            br#"{"update_config":{"surplus_policy":null}}"#.to_vec(),
+        ))
+        .unwrap();
+        governance(&mut deps, clear).unwrap();
+        assert_eq!(load_config(&deps.storage).unwrap().surplus_policy, None);
+    }
+
+    #[test]
+    fn surplus_is_sent_to_policy_address() {
+        let mut deps = setup();
+        set_surplus_policy(
+            &mut deps,
+            Some(SurplusPolicy {
+                grace_period: 100,
+                recipient: SurplusRecipient::Address {
+                    address: "treasury".to_string(),
+                },
+            }),
+        )
+        .unwrap();
+
+        bond(&mut deps, "alice", 100, None, None).unwrap();
+        bond(&mut deps, "bob", 100, None, None).unwrap();
+        reward(&mut deps, 20, 100);
+        anyone(
+            &mut deps,
+            "alice",
+            AnyoneMsg::Unbond {
+                amount: None,
+                recipient: None,
+                claim_rewards: false,
+            },
+        )
+        .unwrap();
+
+        let collect = |deps: &mut MockDeps, env: Env| {
+            execute(
+                deps.as_mut(),
+                env,
+                mock_info("anyone", &[]),
+                ExecuteMsg::Anyone {
+                    anyone_msg: AnyoneMsg::CollectSurplus {
+                        address: "alice".to_string(),
+                    },
+                },
+            )
+        };
+        assert_eq!(
+            collect(&mut deps, env_after(50)),
+            Err(ContractError::SurplusNotCollectable {})
+        );
+        let resp = collect(&mut deps, env_after(100)).unwrap();
+        assert_eq!(
+            resp.messages,
+            vec![transfer(
+                &Addr::unchecked("prism"),
+                &Addr::unchecked("treasury"),
+                Uint128::new(40)
+            )
+            .unwrap()]
+        );
+    }
+
+    fn claimable_rewards(deps: &MockDeps) -> StdResult<ClaimableRewardsResponse> {
+        from_binary(&query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::ClaimableRewards {
+                address: "alice".to_string(),
+            },
+        )?)
+    }
+
+    #[test]
+    fn next_claimable_time_is_nearest_vesting_end() {
+        let mut deps = setup();
+        bond(&mut deps, "alice", 100, None, None).unwrap();
+        // PRISM for virtual rewards is still vesting
+        reward(&mut deps, 100, 40);
+
+        let now = mock_env().block.time.seconds();
+        deps.querier
+            .set_handler("vault", move |msg| match from_binary(msg)? {
+                VaultQueryMsg::PrismVestingSchedules {} => {
+                    to_binary(&PrismVestingSchedulesResponse {
+                        schedules: [now - 10, now + 100, now + 50]
+                            .iter()
+                            .map(|end_time| PrismVestingScheduleResponse {
+                                end_time: *end_time,
+                                amount: Uint128::new(20),
+                            })
+                            .collect(),
+                    })
+                }
+                _ => Err(StdError::generic_err("unexpected query")),
+            });
+
+        let resp = claimable_rewards(&deps).unwrap();
+        assert_eq!(resp.limited_by, Some(RewardsLimit::Real));
+        assert_eq!(resp.claimable, Uint128::new(40));
+        assert_eq!(resp.next_claimable_time, Some(now + 50));
+
+        // failed vault query isn't hidden
+        deps.querier
+            .set_handler("vault", |_| Err(StdError::generic_err("vault is down")));
+        assert!(claimable_rewards(&deps).is_err());
+
+        // no need to ask vault when real rewards are not behind
+        reward(&mut deps, 0, 100);
+        let resp = claimable_rewards(&deps).unwrap();
+        assert_eq!(resp.limited_by, None);
+        assert_eq!(resp.next_claimable_time, None);
+    }
+
+    #[test]
+    fn listed_reward_operators_can_reward() {
+        let mut deps = setup();
+        let reward_as = |deps: &mut MockDeps, sender: &str| {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(sender, &[]),
+                ExecuteMsg::RewardOperator {
+                    msg: RewardOperatorMsg::Reward {
+                        amount: Uint128::new(10),
+                    },
+                },
+            )
+        };
+        let set_reward_operators = |deps: &mut MockDeps, operators: &[&str]| {
+            governance(
+                deps,
+                GovernanceMsg::UpdateConfig {
+                    stake_operators: None,
+                    direct_bonding: None,
+                    reward_operator: None,
+                    reward_operators: Some(operators.iter().map(|addr| addr.to_string()).collect()),
+                    payout_mode: None,
+                    lockup_tiers: None,
+                    surplus_policy: None,
+                    reward_vesting: None,
+                    escrow_exempt: None,
+                },
+            )
+        };
+
+        set_reward_operators(&mut deps, &["partner"]).unwrap();
+        let config: ConfigResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap()).unwrap();
+        assert_eq!(config.reward_operators, vec!["partner".to_string()]);
+
+        reward_as(&mut deps, "partner").unwrap();
+        reward_as(&mut deps, "vault").unwrap();
+        assert_eq!(
+            reward_as(&mut deps, "stranger"),
+            Err(ContractError::Unauthorized)
+        );
+        assert_eq!(
+            load_state(&deps.storage).unwrap().virtual_reward_balance,
+            Uint128::new(20)
+        );
+
+        // list is replaced, removed partner is rejected
+        set_reward_operators(&mut deps, &[]).unwrap();
+        assert_eq!(
+            reward_as(&mut deps, "partner"),
+            Err(ContractError::Unauthorized)
+        );
+        reward_as(&mut deps, "vault").unwrap();
+    }
+
+    #[test]
+    fn only_donations_add_partner_rewards() {
+        let mut deps = setup();
+        bond(&mut deps, "alice", 100, None, None).unwrap();
+
+        // virtual rewards without tokens can't be registered by partners not listed by governance
+        assert_eq!(
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info("partner", &[]),
+                ExecuteMsg::RewardOperator {
+                    msg: RewardOperatorMsg::Reward {
+                        amount: Uint128::new(10),
+                    },
+                },
+            ),
+            Err(ContractError::Unauthorized)
+        );
+
+        deps.querier
+            .set_token_balance("prism", MOCK_CONTRACT_ADDR, 10);
+        let resp = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("prism", &[]),
+            ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: "partner".to_string(),
+                amount: Uint128::new(10),
+                msg: to_binary(&Cw20HookMsg::Donate {}).unwrap(),
+            }),
+        )
+        .unwrap();
+        assert_eq!(
+            parse_events::<StakingDonated>(&resp.events).unwrap(),
+            vec![StakingDonated {
+                donor: "partner".to_string(),
+                amount: Uint128::new(10),
+            }]
+        );
+
+        let resp = claim(&mut deps, "alice").unwrap();
+        assert_eq!(
+            resp.messages,
+            vec![transfer(
+                &Addr::unchecked("prism"),
+                &Addr::unchecked("alice"),
+                Uint128::new(10)
+            )
+            .unwrap()]
+        );
+    }
+
+    fn set_preference(
+        deps: &mut MockDeps,
+        staker: &str,
+        preference: Option<PayoutPreference>,
+    ) -> Result<Response, ContractError> {
+        anyone(deps, staker, AnyoneMsg::SetPayoutPreference { preference })
+    }
+
+    fn claim(deps: &mut MockDeps, staker: &str) -> Result<Response, ContractError> {
+        anyone(
+            deps,
+            staker,
+            AnyoneMsg::ClaimRewards {
+                recipient: None,
+                msg: None,
+            },
+        )
+    }
+
+    fn setup_escrow(exempt: &[&str]) -> MockDeps {
+        let mut deps = setup();
+        governance(
+            &mut deps,
+            GovernanceMsg::UpdateConfig {
+                stake_operators: None,
+                direct_bonding: None,
+                reward_operator: None,
+                reward_operators: None,
+                payout_mode: None,
+                lockup_tiers: None,
+                surplus_policy: None,
+                reward_vesting: Some(RewardVesting {
+                    duration: 100,
+                    early_exit_penalty: Decimal::percent(50),
+                }),
+                escrow_exempt: Some(exempt.iter().map(|addr| addr.to_string()).collect()),
+            },
+        )
+        .unwrap();
+        deps
+    }
+
+    fn vesting_entries(deps: &MockDeps, staker: &str) -> Vec<VestingEntry> {
+        load_vesting_entries(&deps.storage, &Addr::unchecked(staker)).unwrap()
+    }
+
+    #[test]
+    fn escrow_belongs_to_staker() {
+        let mut deps = setup_escrow(&[]);
+        bond(&mut deps, "alice", 100, None, None).unwrap();
+        bond(&mut deps, "bob", 100, None, None).unwrap();
+        reward(&mut deps, 20, 20);
+
+        // can't be pushed to someone else's escrow
+        assert_eq!(
+            anyone(
+                &mut deps,
+                "bob",
+                AnyoneMsg::ClaimRewards {
+                    recipient: Some("alice".to_string()),
+                    msg: None,
+                },
+            ),
+            Err(ContractError::RewardsEscrowed {})
+        );
+        assert!(vesting_entries(&deps, "alice").is_empty());
+
+        let resp = claim(&mut deps, "bob").unwrap();
+        assert!(resp.messages.is_empty());
+        assert_eq!(
+            vesting_entries(&deps, "bob"),
+            vec![VestingEntry {
+                amount: Uint128::new(10),
+                withdrawn: Uint128::zero(),
+                start_time: mock_env().block.time.seconds(),
+                end_time: mock_env().block.time.seconds() + 100,
+            }]
+        );
+    }
+
+    #[test]
+    fn escrow_over_cap_joins_latest_entry() {
+        let mut deps = setup_escrow(&[]);
+        bond(&mut deps, "alice", 100, None, None).unwrap();
+        for i in 1..=33 {
+            reward(&mut deps, 10, 10 * i);
+            claim(&mut deps, "alice").unwrap();
+        }
+        let now = mock_env().block.time.seconds();
+        let entries = vesting_entries(&deps, "alice");
+        assert_eq!(entries.len(), 32);
+        assert_eq!(entries[31].amount, Uint128::new(20));
+
+        // half of the latest entry is vested and stays withdrawable
+        reward(&mut deps, 10, 340);
+        execute(
+            deps.as_mut(),
+            env_after(50),
+            mock_info("alice", &[]),
+            ExecuteMsg::Anyone {
+                anyone_msg: AnyoneMsg::ClaimRewards {
+                    recipient: None,
+                    msg: None,
+                },
+            },
+        )
+        .unwrap();
+        let latest = vesting_entries(&deps, "alice").pop().unwrap();
+        assert_eq!(
+            latest,
+            VestingEntry {
+                amount: Uint128::new(30),
+                withdrawn: Uint128::zero(),
+                start_time: now,
+                end_time: now + 150,
+            }
+        );
+        assert_eq!(latest.vested(now + 50), Uint128::new(10));
+    }
+
+    #[test]
+    fn escrow_exempt_staker_is_paid_directly() {
+        let mut deps = setup_escrow(&["autocompounder"]);
+        bond(&mut deps, "autocompounder", 100, None, None).unwrap();
+        reward(&mut deps, 10, 10);
+
+        let resp = claim(&mut deps, "autocompounder").unwrap();
+        assert_eq!(
+            resp.messages,
+            vec![transfer(
+                &Addr::unchecked("prism"),
+                &Addr::unchecked("autocompounder"),
+                Uint128::new(10)
+            )
+            .unwrap()]
+        );
+        assert!(vesting_entries(&deps, "autocompounder").is_empty());
+    }
+
+    #[test]
+    fn unbond_skips_claim_that_cant_be_paid() {
+        let mut deps = setup_escrow(&[]);
+        bond(&mut deps, "alice", 100, None, None).unwrap();
+        reward(&mut deps, 10, 10);
+
+        // escrowed rewards can't go to another recipient
+        let resp = anyone(
+            &mut deps,
+            "alice",
+            AnyoneMsg::Unbond {
+                amount: Some(Uint128::new(40)),
+                recipient: Some("bob".to_string()),
+                claim_rewards: true,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            resp.messages,
+            vec![transfer(
+                &Addr::unchecked("nexprism"),
+                &Addr::unchecked("bob"),
+                Uint128::new(40)
+            )
+            .unwrap()]
+        );
+        assert!(parse_events::<StakingRewardsClaimed>(&resp.events)
+            .unwrap()
+            .is_empty());
+        assert!(vesting_entries(&deps, "alice").is_empty());
+
+        anyone(
+            &mut deps,
+            "alice",
+            AnyoneMsg::Unbond {
+                amount: None,
+                recipient: None,
+                claim_rewards: true,
+            },
+        )
+        .unwrap();
+        assert_eq!(vesting_entries(&deps, "alice")[0].amount, Uint128::new(10));
+    }
+
+    #[test]
+    fn payout_preference_overrides_default() {
+        let mut deps = setup_with(xprism_payout());
+        bond(&mut deps, "alice", 100, None, None).unwrap();
+        bond(&mut deps, "bob", 100, None, None).unwrap();
+        reward(&mut deps, 20, 20);
+
+        set_preference(&mut deps, "alice", Some(PayoutPreference::Prism)).unwrap();
+        assert_eq!(
+            load_staker(&deps.storage, &Addr::unchecked("alice"))
+                .unwrap()
+                .payout_preference,
+            Some(PayoutPreference::Prism)
+        );
+        let resp = claim(&mut deps, "alice").unwrap();
+        assert_eq!(
+            resp.messages,
+            vec![transfer(
+                &Addr::unchecked("prism"),
+                &Addr::unchecked("alice"),
+                Uint128::new(10)
+            )
+            .unwrap()]
+        );
+
+        // claim for someone honours their preference too, bob uses the default one
+        let resp = anyone(
+            &mut deps,
+            "anyone",
+            AnyoneMsg::ClaimRewardsForSomeone {
+                address: "bob".to_string(),
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            resp.messages,
+            vec![prism_xprism_swap(
+                &Addr::unchecked("prism"),
+                &Addr::unchecked("prism_gov"),
+                Uint128::new(10),
+                &Addr::unchecked("bob"),
+            )
+            .unwrap()]
+        );
+    }
+
+    #[test]
+    fn unsupported_payout_preference() {
+        let mut deps = setup();
+        for preference in [
+            PayoutPreference::Xprism,
+            PayoutPreference::Nexprism,
+            PayoutPreference::Restake,
+        ] {
+            assert_eq!(
+                set_preference(&mut deps, "alice", Some(preference)),
+                Err(ContractError::InvalidPayoutPreference {})
+            );
+        }
+
+        // xPRISM can't be restaked into nexPRISM staking
+        let mut deps = setup_with(xprism_payout());
+        assert_eq!(
+            set_preference(&mut deps, "alice", Some(PayoutPreference::Restake)),
+            Err(ContractError::InvalidPayoutPreference {})
+        );
+        set_preference(&mut deps, "alice", Some(PayoutPreference::Xprism)).unwrap();
+        set_preference(&mut deps, "alice", None).unwrap();
+        assert_eq!(
+            load_staker(&deps.storage, &Addr::unchecked("alice"))
+                .unwrap()
+                .payout_preference,
+            None
+        );
+    }
+
+    #[test]
+    fn restake_rewards() {
+        let mut deps = setup_with(nexprism_payout());
+        bond(&mut deps, "alice", 100, None, None).unwrap();
+        reward(&mut deps, 10, 10);
+        set_preference(&mut deps, "alice", Some(PayoutPreference::Restake)).unwrap();
+
+        // PRISM -> xPRISM -> nexPRISM is sent back to staking with bond message
+        let resp = claim(&mut deps, "alice").unwrap();
+        assert_eq!(
+            resp.messages,
+            vec![prism_xprism_swap_and_reply(
+                &Addr::unchecked("prism"),
+                &Addr::unchecked("prism_gov"),
+                Uint128::new(10),
+            )
+            .unwrap()]
+        );
+        let bond_msg = to_binary(&Cw20HookMsg::Bond {
+            recipient: Some("alice".to_string()),
+            lock_duration: None,
+        })
+        .unwrap();
+        let context = REPLY_CONTEXT.load(&deps.storage).unwrap();
+        assert_eq!(
+            context.rewards_recipient,
+            Addr::unchecked(MOCK_CONTRACT_ADDR)
+        );
+        assert_eq!(context.rewards_msg, Some(bond_msg.clone()));
+        assert!(context.buy_nexprism);
+
+        deps.querier
+            .set_token_balance("xprism", MOCK_CONTRACT_ADDR, 10);
+        reply_ok(&mut deps, ReplyId::XPrismTokensMinted).unwrap();
+        deps.querier
+            .set_token_balance("nexprism", MOCK_CONTRACT_ADDR, 12);
+        let resp = reply_ok(&mut deps, ReplyId::NexPrismTokensBought).unwrap();
+        assert_eq!(
+            resp.messages,
+            vec![transfer_or_send(
+                &Addr::unchecked("nexprism"),
+                &Addr::unchecked(MOCK_CONTRACT_ADDR),
+                Uint128::new(12),
+                Some(bond_msg.clone()),
+            )
+            .unwrap()]
+        );
+
+        // cw20 Send to itself bonds bought tokens for the staker
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("nexprism", &[]),
+            ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: MOCK_CONTRACT_ADDR.to_string(),
+                amount: Uint128::new(12),
+                msg: bond_msg,
+            }),
+        )
+        .unwrap();
+        let alice = load_staker(&deps.storage, &Addr::unchecked("alice")).unwrap();
+        assert_eq!(alice.bonded, Uint128::new(112));
+    }
+
+    #[test]
+    fn invalid_payout_mode() {
+        let mut deps = mock_dependencies(&[]);
+        mock_pair(&mut deps.querier);
+        let mut instantiate_with = |payout_mode| {
+            instantiate(
+                deps.as_mut(),
+                mock_env(),
+                mock_info("creator", &[]),
+                instantiate_msg(payout_mode),
+            )
+        };
+
+        assert_eq!(
+            instantiate_with(PayoutMode::Xprism {
+                prism_governance: "prism_gov".to_string(),
+                xprism_token: "prism".to_string(),
+            }),
+            Err(ContractError::InvalidConfig {})
+        );
+        // pair doesn't trade the configured xPRISM token
+        assert_eq!(
+            instantiate_with(PayoutMode::Nexprism {
+                prism_governance: "prism_gov".to_string(),
+                xprism_token: "other_xprism".to_string(),
+                nexprism_xprism_pair: "pair".to_string(),
+            }),
+            Err(ContractError::InvalidConfig {})
+        );
+        // not a pair
+        assert!(instantiate_with(PayoutMode::Nexprism {
+            prism_governance: "prism_gov".to_string(),
+            xprism_token: "xprism".to_string(),
+            nexprism_xprism_pair: "vault".to_string(),
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn governance_switches_payout_mode() {
+        let mut deps = setup();
+        let update_payout = |deps: &mut MockDeps, sender: &str| {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(sender, &[]),
+                ExecuteMsg::Governance {
+                    governance_msg: GovernanceMsg::UpdateConfig {
+                        stake_operators: None,
+                        direct_bonding: None,
+                        reward_operator: None,
+                        reward_operators: None,
+                        payout_mode: Some(nexprism_payout()),
+                        lockup_tiers: None,
+                        surplus_policy: None,
+                        reward_vesting: None,
+                        escrow_exempt: None,
+                    },
+                },
+            )
+        };
+
+        assert_eq!(
+            update_payout(&mut deps, "alice"),
+            Err(ContractError::Unauthorized)
+        );
+        update_payout(&mut deps, "gov").unwrap();
+        assert_eq!(
+            load_config(&deps.storage).unwrap().payout,
+            Payout::Nexprism {
+                prism_governance: Addr::unchecked("prism_gov"),
+                xprism_token: Addr::unchecked("xprism"),
+                nexprism_xprism_pair: Addr::unchecked("pair"),
+            }
+        );
+    }
+
+    #[test]
+    fn migrate_legacy_payout() {
+        let mut deps = mock_dependencies(&[]);
+        let legacy = LegacyConfig {
+            governance: Addr::unchecked("gov"),
+            staking_token: Addr::unchecked("nexprism"),
+            stake_operator: None,
+            reward_token: Addr::unchecked("prism"),
+            reward_operator: Addr::unchecked("vault"),
+            xprism_token: None,
+            prism_governance: Some(Addr::unchecked("prism_gov")),
+            nexprism_xprism_pair: None,
+        };
+        let legacy_config: Item<LegacyConfig> = Item::new("config");
+        legacy_config.save(&mut deps.storage, &legacy).unwrap();
+
+        // xPRISM payout used to mint to recipient without knowing the token
+        assert!(migrate_legacy_config(deps.as_mut(), None).is_err());
+        migrate_legacy_config(deps.as_mut(), Some("xprism".to_string())).unwrap();
+        let config = load_config(&deps.storage).unwrap();
+        assert_eq!(
+            config.payout,
+            Payout::Xprism {
+                prism_governance: Addr::unchecked("prism_gov"),
+                xprism_token: Addr::unchecked("xprism"),
+            }
+        );
+        assert!(config.direct_bonding);
+    }
+
+    fn migrate_stakers(deps: &mut MockDeps, limit: Option<u32>) -> Result<Response, ContractError> {
+        governance(deps, GovernanceMsg::MigrateStakers { limit })
+    }
+
+    #[test]
+    fn stakers_are_migrated_by_pages() {
+        let mut deps = setup();
+        bond(&mut deps, "alice", 100, None, None).unwrap();
+        bond(&mut deps, "bob", 50, None, None).unwrap();
+        assert!(migrate_stakers(&mut deps, None).is_err());
+
+        // entries stored before direct bonding
+        let legacy_stakers: Map<&Addr, LegacyStaker> = Map::new("state");
+        for addr in ["alice", "bob"] {
+            let addr = Addr::unchecked(addr);
+            let staker = load_staker(&deps.storage, &addr).unwrap();
+            let legacy = LegacyStaker {
+                balance: staker.balance,
+                real_index: staker.real_index,
+                real_pending_rewards: staker.real_pending_rewards,
+                virtual_index: staker.virtual_index,
+                virtual_pending_rewards: staker.virtual_pending_rewards,
+            };
+            legacy_stakers
+                .save(&mut deps.storage, &addr, &legacy)
+                .unwrap();
+        }
+        // and before balance snapshots
+        let dave = Addr::unchecked("dave");
+        let legacy = LegacyStaker {
+            balance: Uint128::new(30),
+            real_index: Decimal::zero(),
+            real_pending_rewards: Decimal::zero(),
+            virtual_index: Decimal::zero(),
+            virtual_pending_rewards: Decimal::zero(),
+        };
+        legacy_stakers
+            .save(&mut deps.storage, &dave, &legacy)
+            .unwrap();
+        let height = mock_env().block.height - 10;
+        save_stakers_migration(
+            &mut deps.storage,
+            &StakersMigration {
+                start_after: None,
+                set_bonded: true,
+                exited_at: 0,
+                snapshot_height: Some(height),
+            },
+        )
+        .unwrap();
+
+        // nothing waits for the sweep, entries are updated on use
+        let alice = Addr::unchecked("alice");
+        let bob = Addr::unchecked("bob");
+        assert_eq!(
+            load_staker(&deps.storage, &bob).unwrap().bonded,
+            Uint128::new(50)
+        );
+        reward(&mut deps, 10, 10);
+        bond(&mut deps, "carol", 10, None, None).unwrap();
+        bond(&mut deps, "dave", 5, None, None).unwrap();
+        anyone(
+            &mut deps,
+            "bob",
+            AnyoneMsg::Unbond {
+                amount: Some(Uint128::new(20)),
+                recipient: None,
+                claim_rewards: false,
+            },
+        )
+        .unwrap();
+        assert!(!is_unbonded_layout(&deps.storage, &bob).unwrap());
+
+        migrate_stakers(&mut deps, Some(1)).unwrap();
+        assert_eq!(
+            load_staker(&deps.storage, &alice).unwrap().bonded,
+            Uint128::new(100)
+        );
+        assert!(!is_unbonded_layout(&deps.storage, &alice).unwrap());
+        assert!(load_stakers_migration(&deps.storage).unwrap().is_some());
+
+        // used entry is not bonded again
+        migrate_stakers(&mut deps, Some(1)).unwrap();
+        assert_eq!(
+            load_staker(&deps.storage, &bob).unwrap().bonded,
+            Uint128::new(30)
+        );
+        assert!(load_stakers_migration(&deps.storage).unwrap().is_some());
+
+        migrate_stakers(&mut deps, None).unwrap();
+        assert!(load_stakers_migration(&deps.storage).unwrap().is_none());
+        assert_eq!(
+            load_staker(&deps.storage, &dave).unwrap().bonded,
+            Uint128::new(35)
+        );
+        // balance before the first change is recorded at migration height
+        assert_eq!(
+            load_staker_balance_at(&deps.storage, &dave, height + 1).unwrap(),
+            Uint128::new(30)
+        );
+        assert_eq!(
+            load_staker_balance_at(&deps.storage, &dave, mock_env().block.height + 1).unwrap(),
+            Uint128::new(35)
+        );
+        bond(&mut deps, "carol", 10, None, None).unwrap();
+    }
+
+    fn env_after(seconds: u64) -> Env {
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(seconds);
+        env
+    }
+
+    fn setup_lockup() -> MockDeps {
+        let mut deps = setup();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("gov", &[]),
+            ExecuteMsg::Governance {
+                governance_msg: GovernanceMsg::UpdateConfig {
+                    stake_operators: None,
+                    direct_bonding: None,
+                    reward_operator: None,
+                    reward_operators: None,
+                    payout_mode: None,
+                    lockup_tiers: Some(vec![LockupTier {
+                        duration: 100,
+                        multiplier: Decimal::from_ratio(2u128, 1u128),
+                    }]),
+                    surplus_policy: None,
+                    reward_vesting: None,
+                    escrow_exempt: None,
+                },
+            },
+        )
+        .unwrap();
+        deps
+    }
+
+    #[test]
+    fn lock_for_recipient_is_rejected() {
+        let mut deps = setup_lockup();
+        assert_eq!(
+            bond(&mut deps, "router", 100, Some("alice"), Some(100)),
+            Err(ContractError::LockForRecipient {})
+        );
+        bond(&mut deps, "alice", 100, Some("alice"), Some(100)).unwrap();
+        bond(&mut deps, "router", 100, Some("alice"), None).unwrap();
+
+        let alice = load_staker(&deps.storage, &Addr::unchecked("alice")).unwrap();
+        assert_eq!(alice.locks.len(), 1);
+        assert_eq!(alice.bonded, Uint128::new(200));
+    }
+
+    #[test]
+    fn locked_tokens_are_released_on_expiry() {
+        let mut deps = setup_lockup();
+        assert_eq!(
+            bond(&mut deps, "alice", 100, None, Some(50)),
+            Err(ContractError::InvalidLockDuration { duration: 50 })
+        );
+        bond(&mut deps, "alice", 100, None, Some(100)).unwrap();
+
+        let alice = load_staker(&deps.storage, &Addr::unchecked("alice")).unwrap();
+        assert_eq!(alice.lock_boost, Uint128::new(100));
+        assert_eq!(alice.weight(), Uint128::new(200));
+        assert_eq!(
+            load_state(&deps.storage).unwrap().total_lock_boost,
+            Uint128::new(100)
+        );
+
+        let unbond = AnyoneMsg::Unbond {
+            amount: Some(Uint128::new(100)),
+            recipient: None,
+            claim_rewards: false,
+        };
+        assert_eq!(
+            anyone(&mut deps, "alice", unbond.clone()),
+            Err(ContractError::NotEnoughTokens {
+                name: "nexprism".to_string(),
+                value: Uint128::zero(),
+                required: Uint128::new(100),
+            })
+        );
+
+        let release = |deps: &mut MockDeps, env| {
+            execute(
+                deps.as_mut(),
+                env,
+                mock_info("anyone", &[]),
+                ExecuteMsg::Anyone {
+                    anyone_msg: AnyoneMsg::ReleaseExpiredLocks {
+                        address: "alice".to_string(),
+                    },
+                },
+            )
+        };
+        assert_eq!(
+            release(&mut deps, env_after(99)),
+            Err(ContractError::NoExpiredLocks {})
+        );
+        release(&mut deps, env_after(100)).unwrap();
+
+        let alice = load_staker(&deps.storage, &Addr::unchecked("alice")).unwrap();
+        assert!(alice.locks.is_empty());
+        assert_eq!(alice.weight(), Uint128::new(100));
+        assert!(load_state(&deps.storage)
+            .unwrap()
+            .total_lock_boost
+            .is_zero());
+
+        execute(
+            deps.as_mut(),
+            env_after(100),
+            mock_info("alice", &[]),
+            ExecuteMsg::Anyone { anyone_msg: unbond },
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn expired_lock_boost_stops_earning_without_release() {
+        let mut deps = setup_lockup();
+        bond(&mut deps, "alice", 100, None, Some(100)).unwrap();
+        bond(&mut deps, "bob", 100, None, None).unwrap();
+
+        // alice has weight 200 of 300 before expiry
+        reward(&mut deps, 300, 300);
+        execute(
+            deps.as_mut(),
+            env_after(50),
+            mock_info("anyone", &[]),
+            ExecuteMsg::Anyone {
+                anyone_msg: AnyoneMsg::UpdateGlobalIndex {},
+            },
+        )
+        .unwrap();
+        // and 100 of 200 after it, lock is not released
+        reward(&mut deps, 200, 500);
+
+        let rewards = |deps: &MockDeps, address: &str| -> RewardsResponse {
+            from_binary(
+                &query(
+                    deps.as_ref(),
+                    env_after(100),
+                    QueryMsg::Rewards {
+                        address: address.to_string(),
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap()
+        };
+        let state: StateResponse =
+            from_binary(&query(deps.as_ref(), env_after(100), QueryMsg::State {}).unwrap())
+                .unwrap();
+        assert_eq!(state.total_weight, Uint128::new(200));
+
+        execute(
+            deps.as_mut(),
+            env_after(100),
+            mock_info("anyone", &[]),
+            ExecuteMsg::Anyone {
+                anyone_msg: AnyoneMsg::UpdateGlobalIndex {},
+            },
+        )
+        .unwrap();
+        let state = load_state(&deps.storage).unwrap();
+        assert!(state.total_lock_boost.is_zero());
+        assert_eq!(state.total_weight(), Uint128::new(200));
+        assert_eq!(rewards(&deps, "alice").virtual_rewards, Uint128::new(300));
+        assert_eq!(rewards(&deps, "bob").virtual_rewards, Uint128::new(200));
+
+        // release only drops the lock, boost is already out of total weight
+        execute(
+            deps.as_mut(),
+            env_after(150),
+            mock_info("anyone", &[]),
+            ExecuteMsg::Anyone {
+                anyone_msg: AnyoneMsg::ReleaseExpiredLocks {
+                    address: "alice".to_string(),
+                },
+            },
+        )
+        .unwrap();
+        let alice = load_staker(&deps.storage, &Addr::unchecked("alice")).unwrap();
+        assert!(alice.locks.is_empty());
+        assert_eq!(alice.weight(), Uint128::new(100));
+        assert_eq!(
+            load_state(&deps.storage).unwrap().total_weight(),
+            Uint128::new(200)
+        );
+        assert_eq!(rewards(&deps, "alice").virtual_rewards, Uint128::new(300));
+    }
+
+    #[test]
+    fn locks_are_capped() {
+        let mut deps = setup_lockup();
+        for _ in 0..16 {
+            bond(&mut deps, "alice", 10, None, Some(100)).unwrap();
+        }
+        assert_eq!(
+            bond(&mut deps, "alice", 10, None, Some(100)),
+            Err(ContractError::TooManyLocks {})
+        );
+        // bonding without lock is still possible
+        bond(&mut deps, "alice", 10, None, None).unwrap();
+    }
+
+    // Stake operator reporting `balances` of stakers.
+    fn mock_operator(querier: &mut WasmMockQuerier, operator: &str, balances: &[(&str, u128)]) {
+        let balances: Vec<(String, u128)> = balances
+            .iter()
+            .map(|(staker, balance)| (staker.to_string(), *balance))
+            .collect();
+        querier.set_handler(operator, move |msg| match from_binary(msg)? {
+            StakeOperatorQueryMsg::State {} => to_binary(&StakeOperatorStateResponse {
+                total_share: Uint128::new(balances.iter().map(|(_, balance)| balance).sum()),
+            }),
+            StakeOperatorQueryMsg::Staker { address } => to_binary(&StakeOperatorStakerResponse {
+                balance: balances
+                    .iter()
+                    .find(|(staker, _)| *staker == address)
+                    .map_or(Uint128::zero(), |(_, balance)| Uint128::new(*balance)),
+            }),
+        });
+    }
+
+    fn setup_with_operator(balances: &[(&str, u128)]) -> MockDeps {
+        let mut deps = mock_dependencies(&[]);
+        mock_operator(&mut deps.querier, "operator", balances);
+        let mut msg = instantiate_msg(PayoutMode::Prism {});
+        msg.stake_operators = vec!["operator".to_string()];
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        deps
+    }
+
+    fn stake_operator(
+        deps: &mut MockDeps,
+        operator: &str,
+        msg: StakeOperatorMsg,
+    ) -> Result<Response, ContractError> {
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(operator, &[]),
+            ExecuteMsg::StakeOperator { msg },
+        )
+    }
+
+    fn balance_changed(resp: &Response) -> StakingBalanceChanged {
+        parse_events::<StakingBalanceChanged>(&resp.events)
+            .unwrap()
+            .remove(0)
+    }
+
+    #[test]
+    fn balance_sums_all_operators() {
+        let mut deps = mock_dependencies(&[]);
+        mock_operator(&mut deps.querier, "operator", &[("alice", 50), ("bob", 5)]);
+        mock_operator(&mut deps.querier, "operator2", &[("alice", 30)]);
+        let mut msg = instantiate_msg(PayoutMode::Prism {});
+        msg.stake_operators = vec!["operator".to_string(), "operator2".to_string()];
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+
+        let resp = bond(&mut deps, "alice", 20, None, None).unwrap();
+        let event = balance_changed(&resp);
+        assert_eq!(event.old_balance, Uint128::new(80));
+        assert_eq!(event.new_balance, Uint128::new(100));
+
+        let staker: StakerResponse = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::Staker {
+                    address: "alice".to_string(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(staker.balance, Uint128::new(100));
+
+        let state: StateResponse =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap()).unwrap();
+        assert_eq!(state.staking_total_balance, Uint128::new(105));
+    }
+
+    #[test]
+    fn operator_balance_is_already_updated() {
+        // operator balances after the change
+        let mut deps = setup_with_operator(&[("alice", 50)]);
+        bond(&mut deps, "alice", 20, None, None).unwrap();
+
+        let resp = stake_operator(
+            &mut deps,
+            "operator",
+            StakeOperatorMsg::IncreaseBalance {
+                staker: "alice".to_string(),
+                amount: Uint128::new(10),
+            },
+        )
+        .unwrap();
+        let event = balance_changed(&resp);
+        assert_eq!(event.old_balance, Uint128::new(60));
+        assert_eq!(event.new_balance, Uint128::new(70));
+
+        mock_operator(&mut deps.querier, "operator", &[("alice", 40)]);
+        let resp = stake_operator(
+            &mut deps,
+            "operator",
+            StakeOperatorMsg::DecreaseBalance {
+                staker: "alice".to_string(),
+                amount: Uint128::new(10),
+            },
+        )
+        .unwrap();
+        let event = balance_changed(&resp);
+        assert_eq!(event.old_balance, Uint128::new(70));
+        assert_eq!(event.new_balance, Uint128::new(60));
+
+        let alice = load_staker(&deps.storage, &Addr::unchecked("alice")).unwrap();
+        assert_eq!(alice.balance, Uint128::new(60));
+        // operator part isn't bonded directly
+        assert_eq!(alice.bonded, Uint128::new(20));
+        assert_eq!(
+            load_state(&deps.storage).unwrap().staking_total_balance,
+            Uint128::new(60)
+        );
+
+        assert_eq!(
+            stake_operator(
+                &mut deps,
+                "alice",
+                StakeOperatorMsg::IncreaseBalance {
+                    staker: "alice".to_string(),
+                    amount: Uint128::new(10),
+                },
+            ),
+            Err(ContractError::Unauthorized)
+        );
+    }
+
+    #[test]
+    fn batch_update_uses_stored_balances() {
+        let mut deps = setup_with_operator(&[("alice", 50)]);
+        stake_operator(
+            &mut deps,
+            "operator",
+            StakeOperatorMsg::IncreaseBalance {
+                staker: "alice".to_string(),
+                amount: Uint128::new(50),
+            },
+        )
+        .unwrap();
+
+        let batch = |updates: Vec<(&str, BalanceDelta)>| StakeOperatorMsg::BatchUpdateBalances {
+            updates: updates
+                .into_iter()
+                .map(|(staker, delta)| (staker.to_string(), delta))
+                .collect(),
+        };
+        let increase = |amount: u128| BalanceDelta::Increase {
+            amount: Uint128::new(amount),
+        };
+        let decrease = |amount: u128| BalanceDelta::Decrease {
+            amount: Uint128::new(amount),
+        };
+
+        mock_operator(&mut deps.querier, "operator", &[("alice", 30), ("bob", 40)]);
+        let resp = stake_operator(
+            &mut deps,
+            "operator",
+            batch(vec![("alice", decrease(20)), ("bob", increase(40))]),
+        )
+        .unwrap();
+        assert_eq!(
+            parse_events::<StakingBalancesBatchUpdated>(&resp.events).unwrap(),
+            vec![StakingBalancesBatchUpdated {
+                stakers: 2,
+                total_increase: Uint128::new(40),
+                total_decrease: Uint128::new(20),
+                staking_total_balance: Uint128::new(70),
+            }]
+        );
+        let alice = load_staker(&deps.storage, &Addr::unchecked("alice")).unwrap();
+        assert_eq!(alice.balance, Uint128::new(30));
+
+        // more than stored or total balance
+        assert_eq!(
+            stake_operator(&mut deps, "operator", batch(vec![("alice", decrease(100))])),
+            Err(ContractError::InconsistentBalanceUpdate {})
+        );
+        assert_eq!(
+            stake_operator(&mut deps, "operator", batch(vec![("bob", increase(100))])),
+            Err(ContractError::InconsistentBalanceUpdate {})
+        );
+
+        // emptied entry is pruned
+        mock_operator(&mut deps.querier, "operator", &[("bob", 40)]);
+        stake_operator(&mut deps, "operator", batch(vec![("alice", decrease(30))])).unwrap();
+        assert!(STAKERS
+            .may_load(&deps.storage, &Addr::unchecked("alice"))
+            .unwrap()
+            .is_none());
+    }
+
+    fn transfer_stake(
+        deps: &mut MockDeps,
+        sender: &str,
+        recipient: &str,
+        amount: u128,
+    ) -> Result<Response, ContractError> {
+        anyone(
+            deps,
+            sender,
+            AnyoneMsg::TransferStake {
+                recipient: recipient.to_string(),
+                amount: Uint128::new(amount),
+            },
+        )
+    }
+
+    #[test]
+    fn transfer_all_stake() {
+        let mut deps = setup();
+        bond(&mut deps, "alice", 100, None, None).unwrap();
+
+        transfer_stake(&mut deps, "alice", "bob", 100).unwrap();
+        let bob = load_staker(&deps.storage, &Addr::unchecked("bob")).unwrap();
+        assert_eq!(bob.balance, Uint128::new(100));
+        assert_eq!(bob.bonded, Uint128::new(100));
+        // nothing is left to sender
+        assert!(STAKERS
+            .may_load(&deps.storage, &Addr::unchecked("alice"))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn payout_preference_is_kept_without_stake() {
+        let mut deps = setup_with(xprism_payout());
+        bond(&mut deps, "alice", 100, None, None).unwrap();
+        set_preference(&mut deps, "alice", Some(PayoutPreference::Prism)).unwrap();
+
+        transfer_stake(&mut deps, "alice", "bob", 100).unwrap();
+        governance(
+            &mut deps,
+            GovernanceMsg::PruneStakers {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+        let alice = load_staker(&deps.storage, &Addr::unchecked("alice")).unwrap();
+        assert!(alice.balance.is_zero());
+        assert_eq!(alice.payout_preference, Some(PayoutPreference::Prism));
+    }
+
+    #[test]
+    fn operator_stake_is_not_transferable() {
+        let mut deps = setup_with_operator(&[("alice", 50)]);
+        bond(&mut deps, "alice", 10, None, None).unwrap();
+
+        assert_eq!(
+            transfer_stake(&mut deps, "alice", "bob", 20),
+            Err(ContractError::OperatorStakeNotTransferable {
+                transferable: Uint128::new(10),
+            })
+        );
+        transfer_stake(&mut deps, "alice", "bob", 10).unwrap();
+
+        let alice = load_staker(&deps.storage, &Addr::unchecked("alice")).unwrap();
+        assert_eq!(alice.balance, Uint128::new(50));
+        assert!(alice.bonded.is_zero());
+    }
+
+    fn reply_ok(deps: &mut MockDeps, id: ReplyId) -> Result<Response, ContractError> {
+        reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: id.into(),
+                result: ContractResult::Ok(SubMsgExecutionResponse {
+                    events: vec![],
+                    data: None,
+                }),
+            },
+        )
+    }
+
+    fn buy_nexprism_context(rewards_msg: Option<Binary>) -> ReplyContext {
+        ReplyContext {
+            rewards_recipient: Addr::unchecked("alice"),
+            rewards_msg,
+            balance_before: Uint128::new(50),
+            buy_nexprism: true,
+        }
+    }
+
+    #[test]
+    fn bought_nexprism_is_floored() {
+        let mut deps = setup_with(nexprism_payout());
+        deps.querier
+            .set_token_balance("xprism", MOCK_CONTRACT_ADDR, 150);
+        REPLY_CONTEXT
+            .save(&mut deps.storage, &buy_nexprism_context(None))
+            .unwrap();
+
+        let resp = reply_ok(&mut deps, ReplyId::XPrismTokensMinted).unwrap();
+        assert_eq!(
+            resp.messages,
+            vec![send(
+                &Addr::unchecked("xprism"),
+                &Addr::unchecked("pair"),
+                Uint128::new(100),
+                &PairHookMsg::Swap {
+                    belief_price: Some(Decimal::one()),
+                    max_spread: Some(Decimal::zero()),
+                    to: Some("alice".to_string()),
+                },
+            )
+            .unwrap()]
+        );
+    }
+
+    #[test]
+    fn bought_nexprism_is_sent_with_msg() {
+        let mut deps = setup_with(nexprism_payout());
+        let msg = Binary::from(br#"{"deposit":{}}"#.as_ref());
+        deps.querier
+            .set_token_balance("xprism", MOCK_CONTRACT_ADDR, 150);
+        deps.querier
+            .set_token_balance("nexprism", MOCK_CONTRACT_ADDR, 7);
+        REPLY_CONTEXT
+            .save(&mut deps.storage, &buy_nexprism_context(Some(msg.clone())))
+            .unwrap();
+
+        let resp = reply_ok(&mut deps, ReplyId::XPrismTokensMinted).unwrap();
+        assert_eq!(
+            resp.messages,
+            vec![SubMsg::reply_on_success(
+                send_wasm_msg(
+                    &Addr::unchecked("xprism"),
+                    &Addr::unchecked("pair"),
+                    Uint128::new(100),
+                    &PairHookMsg::Swap {
+                        belief_price: Some(Decimal::one()),
+                        max_spread: Some(Decimal::zero()),
+                        to: None,
+                    },
+                )
+                .unwrap(),
+                ReplyId::NexPrismTokensBought.into(),
+            )]
+        );
+        assert_eq!(
+            REPLY_CONTEXT.load(&deps.storage).unwrap().balance_before,
+            Uint128::new(7)
+        );
+
+        deps.querier
+            .set_token_balance("nexprism", MOCK_CONTRACT_ADDR, 120);
+        let resp = reply_ok(&mut deps, ReplyId::NexPrismTokensBought).unwrap();
+        assert_eq!(
+            resp.messages,
+            vec![transfer_or_send(
+                &Addr::unchecked("nexprism"),
+                &Addr::unchecked("alice"),
+                Uint128::new(113),
+                Some(msg),
+            )
+            .unwrap()]
+        );
+    }
+
+    #[test]
+    fn xprism_is_deposited_when_pair_price_is_below_one() {
+        let mut deps = setup_with(nexprism_payout());
+        mock_pair_price(&mut deps.querier, Decimal::percent(95));
+        deps.querier
+            .set_token_balance("xprism", MOCK_CONTRACT_ADDR, 150);
+        REPLY_CONTEXT
+            .save(&mut deps.storage, &buy_nexprism_context(None))
+            .unwrap();
+
+        // swap would revert, vault mints nexPRISM 1:1 to the recipient instead
+        let resp = reply_ok(&mut deps, ReplyId::XPrismTokensMinted).unwrap();
+        assert_eq!(
+            resp.messages,
+            vec![send(
+                &Addr::unchecked("xprism"),
+                &Addr::unchecked("nexprism_vault"),
+                Uint128::new(100),
+                &VaultHookMsg::Deposit {
+                    recipient: Some("alice".to_string()),
+                    then: None,
+                    best_route: false,
+                },
+            )
+            .unwrap()]
+        );
+
+        let msg = Binary::from(br#"{"deposit":{}}"#.as_ref());
+        deps.querier
+            .set_token_balance("nexprism", MOCK_CONTRACT_ADDR, 7);
+        REPLY_CONTEXT
+            .save(&mut deps.storage, &buy_nexprism_context(Some(msg)))
+            .unwrap();
+        let resp = reply_ok(&mut deps, ReplyId::XPrismTokensMinted).unwrap();
+        assert_eq!(
+            resp.messages,
+            vec![SubMsg::reply_on_success(
+                send_wasm_msg(
+                    &Addr::unchecked("xprism"),
+                    &Addr::unchecked("nexprism_vault"),
+                    Uint128::new(100),
+                    &VaultHookMsg::Deposit {
+                        recipient: None,
+                        then: None,
+                        best_route: false,
+                    },
+                )
+                .unwrap(),
+                ReplyId::NexPrismTokensBought.into(),
+            )]
+        );
+    }
+}
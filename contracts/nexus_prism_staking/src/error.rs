@@ -19,6 +19,51 @@ pub enum ContractError {
     #[error("invalid config")]
     InvalidConfig {},
 
+    #[error("payout preference is not supported")]
+    InvalidPayoutPreference {},
+
+    #[error("no lockup tier with duration {duration}")]
+    InvalidLockDuration { duration: u64 },
+
+    #[error("tokens can be locked only by their owner")]
+    LockForRecipient {},
+
+    #[error("too many locks")]
+    TooManyLocks {},
+
+    #[error("no expired locks")]
+    NoExpiredLocks {},
+
+    #[error("hook {addr} is already registered")]
+    HookAlreadyRegistered { addr: String },
+
+    #[error("hook {addr} is not registered")]
+    HookNotRegistered { addr: String },
+
+    #[error("too many hooks")]
+    TooManyHooks {},
+
+    #[error("staker {staker} is updated more than once")]
+    DuplicateBalanceUpdate { staker: String },
+
+    #[error("balance update doesn't match stored balances")]
+    InconsistentBalanceUpdate {},
+
+    #[error("surplus can't be collected")]
+    SurplusNotCollectable {},
+
+    #[error("rewards are escrowed, they can be sent only on withdrawal")]
+    RewardsEscrowed {},
+
+    #[error("only {transferable} directly bonded tokens can be transferred, stake operator balances can't")]
+    OperatorStakeNotTransferable { transferable: Uint128 },
+
+    #[error("no stakers migration")]
+    NoStakersMigration {},
+
+    #[error("nothing to unbond")]
+    NothingToUnbond {},
+
     #[error("no rewards")]
     NoRewards {},
 
@@ -1,18 +1,27 @@
 use crate::{
-    commands::{calculate_global_index, get_staker_balance, get_staking_total_balance},
+    commands::{
+        calculate_global_index, expire_lock_boosts, get_staker_balance, get_staking_total_balance,
+        release_staker_locks,
+    },
     state::State,
-    utils::calculate_decimal_rewards,
+    utils::{calculate_decimal_rewards, sum_decimals_and_split_result_to_uint_and_decimal},
 };
 use cosmwasm_std::{Decimal, Deps, Env, StdResult, Uint128};
 use nexus_prism_protocol::{
     common::{query_token_balance, sum},
     staking::{
-        ConfigResponse, PotentialRewardsResponse, RewardStateResponse, RewardsResponse,
-        StakerResponse, StateResponse,
+        BalanceAtResponse, ClaimableRewardsResponse, ConfigResponse, DonationsResponse,
+        HooksResponse, LockResponse, LocksResponse, PotentialRewardsResponse, RewardStateResponse,
+        RewardsLimit, RewardsResponse, StakerResponse, StateResponse, SurplusResponse,
+        TotalStakedAtResponse, VestingEntriesResponse, VestingEntryResponse,
     },
+    vault::{PrismVestingSchedulesResponse, QueryMsg as VaultQueryMsg},
 };
 
-use crate::state::{load_config, load_staker, load_state, Config};
+use crate::state::{
+    load_config, load_donated, load_hooks, load_staker, load_staker_balance_at, load_state,
+    load_total_staked_at, load_vesting_entries, Config,
+};
 
 pub fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
     let config = load_config(deps.storage)?;
@@ -20,22 +29,40 @@ pub fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
     Ok(ConfigResponse {
         governance: config.governance.to_string(),
         staking_token: config.staking_token.to_string(),
-        stake_operator: config.stake_operator.map(|addr| addr.to_string()),
+        stake_operators: config
+            .stake_operators
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect(),
+        direct_bonding: config.direct_bonding,
         reward_token: config.reward_token.to_string(),
         reward_operator: config.reward_operator.to_string(),
-        xprism_token: config.xprism_token.map(|addr| addr.to_string()),
-        prism_governance: config.prism_governance.map(|addr| addr.to_string()),
-        nexprism_xprism_pair: config.nexprism_xprism_pair.map(|addr| addr.to_string()),
+        reward_operators: config
+            .reward_operators
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect(),
+        payout_mode: (&config.payout).into(),
+        lockup_tiers: config.lockup_tiers,
+        surplus_policy: config.surplus_policy,
+        reward_vesting: config.reward_vesting,
+        escrow_exempt: config
+            .escrow_exempt
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect(),
     })
 }
 
-pub fn query_state(deps: Deps) -> StdResult<StateResponse> {
+pub fn query_state(deps: Deps, env: Env) -> StdResult<StateResponse> {
     let config = load_config(deps.storage)?;
     let mut state = load_state(deps.storage)?;
-    state.staking_total_balance = get_staking_total_balance(deps, config.stake_operator, &state)?;
+    state.staking_total_balance = get_staking_total_balance(deps, &config.stake_operators, &state)?;
+    expire_lock_boosts(deps.storage, &mut state, env.block.time.seconds())?;
 
     Ok(StateResponse {
         staking_total_balance: state.staking_total_balance,
+        total_weight: state.total_weight(),
         virtual_reward_balance: state.virtual_reward_balance,
         virtual_rewards: RewardStateResponse {
             global_index: state.virtual_rewards.global_index,
@@ -45,25 +72,32 @@ pub fn query_state(deps: Deps) -> StdResult<StateResponse> {
             global_index: state.real_rewards.global_index,
             prev_balance: state.real_rewards.prev_balance,
         },
+        stranded_surplus: state.stranded_surplus,
+        total_donated: state.total_donated,
     })
 }
 
-pub fn query_rewards(deps: Deps, address: String) -> StdResult<RewardsResponse> {
+pub fn query_rewards(deps: Deps, env: Env, address: String) -> StdResult<RewardsResponse> {
     let config = load_config(deps.storage)?;
 
     let staker_addr = deps.api.addr_validate(&address)?;
     let mut staker = load_staker(deps.storage, &staker_addr)?;
-    staker.balance = get_staker_balance(deps, config.stake_operator, &staker, &staker_addr)?;
+    staker.balance = get_staker_balance(deps, &config.stake_operators, &staker, &staker_addr)?;
 
-    let real_global_index = load_state(deps.storage)?.real_rewards.global_index;
+    let mut state = load_state(deps.storage)?;
+    let now = env.block.time.seconds();
+    let expirations = expire_lock_boosts(deps.storage, &mut state, now)?;
+    release_staker_locks(deps.storage, &mut state, &mut staker, now, &expirations)?;
+
+    let real_global_index = state.real_rewards.global_index;
     let real_reward_with_decimals =
-        calculate_decimal_rewards(real_global_index, staker.real_index, staker.balance)?;
+        calculate_decimal_rewards(real_global_index, staker.real_index, staker.weight())?;
     let all_real_reward_with_decimals = sum(real_reward_with_decimals, staker.real_pending_rewards);
     let real_rewards = all_real_reward_with_decimals * Uint128::new(1);
 
-    let virtual_global_index = load_state(deps.storage)?.virtual_rewards.global_index;
+    let virtual_global_index = state.virtual_rewards.global_index;
     let virtual_reward_with_decimals =
-        calculate_decimal_rewards(virtual_global_index, staker.virtual_index, staker.balance)?;
+        calculate_decimal_rewards(virtual_global_index, staker.virtual_index, staker.weight())?;
     let all_virtual_reward_with_decimals =
         sum(virtual_reward_with_decimals, staker.virtual_pending_rewards);
     let virtual_rewards = all_virtual_reward_with_decimals * Uint128::new(1);
@@ -81,25 +115,27 @@ pub fn query_staker(deps: Deps, env: Env, address: String) -> StdResult<StakerRe
     let mut state: State = load_state(deps.storage)?;
     let config: Config = load_config(deps.storage)?;
 
-    state.staking_total_balance =
-        get_staking_total_balance(deps, config.stake_operator.clone(), &state)?;
-    staker.balance = get_staker_balance(deps, config.stake_operator, &staker, &staker_addr)?;
+    state.staking_total_balance = get_staking_total_balance(deps, &config.stake_operators, &state)?;
+    staker.balance = get_staker_balance(deps, &config.stake_operators, &staker, &staker_addr)?;
 
+    let now = env.block.time.seconds();
+    let expirations = expire_lock_boosts(deps.storage, &mut state, now)?;
     calculate_global_index(
         state.virtual_reward_balance,
-        state.staking_total_balance,
+        state.total_weight(),
         &mut state.virtual_rewards,
     )?;
     calculate_global_index(
         query_token_balance(deps, &config.reward_token, &env.contract.address),
-        state.staking_total_balance,
+        state.total_weight(),
         &mut state.real_rewards,
     )?;
+    release_staker_locks(deps.storage, &mut state, &mut staker, now, &expirations)?;
 
     let virtual_reward_with_decimals = calculate_decimal_rewards(
         state.virtual_rewards.global_index,
         staker.virtual_index,
-        staker.balance,
+        staker.weight(),
     )?;
     let all_virtual_reward_with_decimals: Decimal =
         sum(virtual_reward_with_decimals, staker.virtual_pending_rewards);
@@ -109,7 +145,7 @@ pub fn query_staker(deps: Deps, env: Env, address: String) -> StdResult<StakerRe
     let real_reward_with_decimals = calculate_decimal_rewards(
         state.real_rewards.global_index,
         staker.real_index,
-        staker.balance,
+        staker.weight(),
     )?;
     let all_real_reward_with_decimals: Decimal =
         sum(real_reward_with_decimals, staker.real_pending_rewards);
@@ -121,16 +157,208 @@ pub fn query_staker(deps: Deps, env: Env, address: String) -> StdResult<StakerRe
         balance: staker.balance,
         virtual_pending_rewards: staker.virtual_pending_rewards,
         real_pending_rewards: staker.real_pending_rewards,
+        payout_preference: staker.payout_preference,
+    })
+}
+
+pub fn query_claimable_rewards(
+    deps: Deps,
+    env: Env,
+    address: String,
+) -> StdResult<ClaimableRewardsResponse> {
+    let staker_addr = deps.api.addr_validate(&address)?;
+    let mut staker = load_staker(deps.storage, &staker_addr)?;
+
+    let mut state: State = load_state(deps.storage)?;
+    let config: Config = load_config(deps.storage)?;
+
+    state.staking_total_balance = get_staking_total_balance(deps, &config.stake_operators, &state)?;
+    staker.balance = get_staker_balance(deps, &config.stake_operators, &staker, &staker_addr)?;
+
+    let now = env.block.time.seconds();
+    let expirations = expire_lock_boosts(deps.storage, &mut state, now)?;
+    calculate_global_index(
+        state.virtual_reward_balance,
+        state.total_weight(),
+        &mut state.virtual_rewards,
+    )?;
+    calculate_global_index(
+        query_token_balance(deps, &config.reward_token, &env.contract.address),
+        state.total_weight(),
+        &mut state.real_rewards,
+    )?;
+    release_staker_locks(deps.storage, &mut state, &mut staker, now, &expirations)?;
+
+    // same amounts as in `claim_rewards`
+    let (real_rewards, _) = sum_decimals_and_split_result_to_uint_and_decimal(
+        calculate_decimal_rewards(
+            state.real_rewards.global_index,
+            staker.real_index,
+            staker.weight(),
+        )?,
+        staker.real_pending_rewards,
+    )?;
+    let (virtual_rewards, _) = sum_decimals_and_split_result_to_uint_and_decimal(
+        calculate_decimal_rewards(
+            state.virtual_rewards.global_index,
+            staker.virtual_index,
+            staker.weight(),
+        )?,
+        staker.virtual_pending_rewards,
+    )?;
+
+    let limited_by = if real_rewards < virtual_rewards {
+        Some(RewardsLimit::Real)
+    } else if virtual_rewards < real_rewards {
+        Some(RewardsLimit::Virtual)
+    } else {
+        None
+    };
+
+    // real rewards come with the end of reward operator PRISM vesting
+    let next_claimable_time = match limited_by {
+        Some(RewardsLimit::Real) => deps
+            .querier
+            .query_wasm_smart::<PrismVestingSchedulesResponse>(
+                &config.reward_operator,
+                &VaultQueryMsg::PrismVestingSchedules {},
+            )?
+            .schedules
+            .into_iter()
+            .map(|schedule| schedule.end_time)
+            .filter(|end_time| *end_time > env.block.time.seconds())
+            .min(),
+        _ => None,
+    };
+
+    Ok(ClaimableRewardsResponse {
+        claimable: real_rewards.min(virtual_rewards),
+        real_rewards,
+        virtual_rewards,
+        limited_by,
+        next_claimable_time,
+    })
+}
+
+pub fn query_donations(deps: Deps, donor: String) -> StdResult<DonationsResponse> {
+    let donor_addr = deps.api.addr_validate(&donor)?;
+
+    Ok(DonationsResponse {
+        amount: load_donated(deps.storage, &donor_addr)?,
+        donor,
+    })
+}
+
+pub fn query_surplus(deps: Deps, address: String) -> StdResult<SurplusResponse> {
+    let config = load_config(deps.storage)?;
+    let staker = load_staker(deps.storage, &deps.api.addr_validate(&address)?)?;
+
+    Ok(SurplusResponse {
+        surplus: staker.surplus(),
+        exited_at: staker.exited_at,
+        collectable_at: staker
+            .exited_at
+            .zip(config.surplus_policy)
+            .map(|(exited_at, policy)| exited_at + policy.grace_period),
+    })
+}
+
+pub fn query_vesting_entries(
+    deps: Deps,
+    env: Env,
+    address: String,
+) -> StdResult<VestingEntriesResponse> {
+    let config = load_config(deps.storage)?;
+    let entries = load_vesting_entries(deps.storage, &deps.api.addr_validate(&address)?)?;
+    let now = env.block.time.seconds();
+
+    let mut claimable = Uint128::zero();
+    let mut locked = Uint128::zero();
+    for entry in &entries {
+        let vested = entry.vested(now);
+        claimable += vested - entry.withdrawn;
+        locked += entry.amount - vested;
+    }
+    let early_exit_penalty = config
+        .reward_vesting
+        .map(|vesting| locked * vesting.early_exit_penalty)
+        .unwrap_or_default();
+
+    Ok(VestingEntriesResponse {
+        entries: entries
+            .into_iter()
+            .map(|entry| VestingEntryResponse {
+                amount: entry.amount,
+                withdrawn: entry.withdrawn,
+                start_time: entry.start_time,
+                end_time: entry.end_time,
+            })
+            .collect(),
+        claimable,
+        locked,
+        early_exit_penalty,
+    })
+}
+
+pub fn query_locks(deps: Deps, env: Env, address: String) -> StdResult<LocksResponse> {
+    let config = load_config(deps.storage)?;
+
+    let staker_addr = deps.api.addr_validate(&address)?;
+    let mut staker = load_staker(deps.storage, &staker_addr)?;
+    staker.balance = get_staker_balance(deps, &config.stake_operators, &staker, &staker_addr)?;
+
+    // expired locks are shown as released
+    let mut state = load_state(deps.storage)?;
+    let now = env.block.time.seconds();
+    let expirations = expire_lock_boosts(deps.storage, &mut state, now)?;
+    release_staker_locks(deps.storage, &mut state, &mut staker, now, &expirations)?;
+
+    Ok(LocksResponse {
+        locks: staker
+            .locks
+            .iter()
+            .map(|lock| LockResponse {
+                amount: lock.amount,
+                unlock_time: lock.unlock_time,
+                multiplier: lock.multiplier,
+            })
+            .collect(),
+        locked_amount: staker.locked_amount(now),
+        weight: staker.weight(),
+    })
+}
+
+pub fn query_balance_at(deps: Deps, address: String, height: u64) -> StdResult<BalanceAtResponse> {
+    let staker_addr = deps.api.addr_validate(&address)?;
+
+    Ok(BalanceAtResponse {
+        balance: load_staker_balance_at(deps.storage, &staker_addr, height)?,
+    })
+}
+
+pub fn query_total_staked_at(deps: Deps, height: u64) -> StdResult<TotalStakedAtResponse> {
+    Ok(TotalStakedAtResponse {
+        total_staked: load_total_staked_at(deps.storage, height)?,
+    })
+}
+
+pub fn query_hooks(deps: Deps) -> StdResult<HooksResponse> {
+    Ok(HooksResponse {
+        hooks: load_hooks(deps.storage)?
+            .into_iter()
+            .map(|hook| hook.to_string())
+            .collect(),
     })
 }
 
 pub fn query_potential_rewards(
     deps: Deps,
-    _env: Env,
+    env: Env,
     potential_rewards_total: Uint128,
     address: String,
 ) -> StdResult<PotentialRewardsResponse> {
     let mut state = load_state(deps.storage)?;
+    let now = env.block.time.seconds();
 
     if state.staking_total_balance.is_zero() {
         return Ok(PotentialRewardsResponse {
@@ -138,18 +366,20 @@ pub fn query_potential_rewards(
         });
     }
 
+    let expirations = expire_lock_boosts(deps.storage, &mut state, now)?;
     calculate_global_index(
         potential_rewards_total,
-        state.staking_total_balance,
+        state.total_weight(),
         &mut state.real_rewards,
     )?;
 
     let staker_addr = deps.api.addr_validate(&address)?;
-    let staker = load_staker(deps.storage, &staker_addr)?;
+    let mut staker = load_staker(deps.storage, &staker_addr)?;
+    release_staker_locks(deps.storage, &mut state, &mut staker, now, &expirations)?;
 
     let real_global_index = state.real_rewards.global_index;
     let real_reward_with_decimals =
-        calculate_decimal_rewards(real_global_index, staker.real_index, staker.balance)?;
+        calculate_decimal_rewards(real_global_index, staker.real_index, staker.weight())?;
     let all_real_reward_with_decimals = sum(real_reward_with_decimals, staker.real_pending_rewards);
     let real_rewards = all_real_reward_with_decimals * Uint128::new(1);
 
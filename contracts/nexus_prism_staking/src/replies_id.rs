@@ -4,4 +4,5 @@ use num_enum::{IntoPrimitive, TryFromPrimitive};
 #[repr(u64)]
 pub enum ReplyId {
     XPrismTokensMinted,
+    NexPrismTokensBought,
 }
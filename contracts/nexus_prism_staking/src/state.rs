@@ -1,16 +1,160 @@
-use cw_storage_plus::{Item, Map};
+use std::convert::TryInto;
+
+use cw_storage_plus::{Bound, Item, Map, SnapshotItem, SnapshotMap, Strategy, U64Key};
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{Addr, Decimal, StdResult, Storage, Uint128};
+use cosmwasm_std::{Addr, Binary, Decimal, Order, StdError, StdResult, Storage, Uint128};
+use nexus_prism_protocol::staking::{
+    LockupTier, PayoutMode, PayoutPreference, RewardVesting, SurplusPolicy,
+};
 
 const KEY_CONFIG: Item<Config> = Item::new("config");
+const KEY_LEGACY_CONFIG: Item<LegacyConfig> = Item::new("config");
+const KEY_STAKE_OPERATOR_LAYOUT: Item<StakeOperatorLayout> = Item::new("config");
 const KEY_STATE: Item<State> = Item::new("state");
+const KEY_SURPLUS_LAYOUT: Item<SurplusLayout> = Item::new("state");
 const KEY_GOVERNANCE_UPDATE: Item<GovernanceUpdateState> = Item::new("gov_update");
+const KEY_HOOKS: Item<Vec<Addr>> = Item::new("hooks");
+const KEY_STAKERS_MIGRATION: Item<StakersMigration> = Item::new("stakers_migration");
 pub const STAKERS: Map<&Addr, Staker> = Map::new("state");
+const STAKER_LAYOUTS: Map<&Addr, BondedLayout> = Map::new("state");
 pub const REPLY_CONTEXT: Item<ReplyContext> = Item::new("reply");
+const VESTING_ENTRIES: Map<&Addr, Vec<VestingEntry>> = Map::new("vesting");
+const DONATIONS: Map<&Addr, Uint128> = Map::new("donations");
+const LOCK_EXPIRATIONS: Map<U64Key, LockExpiration> = Map::new("lock_expirations");
+// staked amounts by height for governance voting power
+const STAKER_BALANCES: SnapshotMap<&Addr, Uint128> = SnapshotMap::new(
+    "staker_balance",
+    "staker_balance__checkpoints",
+    "staker_balance__changelog",
+    Strategy::EveryBlock,
+);
+const TOTAL_STAKED: SnapshotItem<Uint128> = SnapshotItem::new(
+    "total_staked",
+    "total_staked__checkpoints",
+    "total_staked__changelog",
+    Strategy::EveryBlock,
+);
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Config {
+    pub governance: Addr,
+    pub staking_token: Addr,
+    #[serde(default)]
+    pub stake_operators: Vec<Addr>,
+    #[serde(default)]
+    pub direct_bonding: bool,
+    pub reward_token: Addr,
+    pub reward_operator: Addr,
+    #[serde(default)]
+    pub reward_operators: Vec<Addr>,
+    pub payout: Payout,
+    #[serde(default)]
+    pub lockup_tiers: Vec<LockupTier>,
+    #[serde(default)]
+    pub surplus_policy: Option<SurplusPolicy>,
+    #[serde(default)]
+    pub reward_vesting: Option<RewardVesting>,
+    #[serde(default)]
+    pub escrow_exempt: Vec<Addr>,
+}
+
+impl Config {
+    pub fn is_stake_operator(&self, addr: &Addr) -> bool {
+        self.stake_operators.contains(addr)
+    }
+
+    pub fn is_reward_operator(&self, addr: &Addr) -> bool {
+        self.reward_operator == *addr || self.reward_operators.contains(addr)
+    }
+
+    pub fn is_escrow_exempt(&self, addr: &Addr) -> bool {
+        self.escrow_exempt.contains(addr) || self.is_stake_operator(addr)
+    }
+
+    pub fn lockup_multiplier(&self, duration: u64) -> Option<Decimal> {
+        self.lockup_tiers
+            .iter()
+            .find(|tier| tier.duration == duration)
+            .map(|tier| tier.multiplier)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Payout {
+    Prism,
+    Xprism {
+        prism_governance: Addr,
+        xprism_token: Addr,
+    },
+    Nexprism {
+        prism_governance: Addr,
+        xprism_token: Addr,
+        nexprism_xprism_pair: Addr,
+    },
+}
+
+impl Payout {
+    pub fn prism_governance(&self) -> Option<&Addr> {
+        match self {
+            Payout::Prism => None,
+            Payout::Xprism {
+                prism_governance, ..
+            }
+            | Payout::Nexprism {
+                prism_governance, ..
+            } => Some(prism_governance),
+        }
+    }
+
+    pub fn xprism_token(&self) -> Option<&Addr> {
+        match self {
+            Payout::Prism => None,
+            Payout::Xprism { xprism_token, .. } | Payout::Nexprism { xprism_token, .. } => {
+                Some(xprism_token)
+            }
+        }
+    }
+
+    pub fn nexprism_xprism_pair(&self) -> Option<&Addr> {
+        match self {
+            Payout::Nexprism {
+                nexprism_xprism_pair,
+                ..
+            } => Some(nexprism_xprism_pair),
+            _ => None,
+        }
+    }
+}
+
+impl From<&Payout> for PayoutMode {
+    fn from(payout: &Payout) -> Self {
+        match payout {
+            Payout::Prism => PayoutMode::Prism {},
+            Payout::Xprism {
+                prism_governance,
+                xprism_token,
+            } => PayoutMode::Xprism {
+                prism_governance: prism_governance.to_string(),
+                xprism_token: xprism_token.to_string(),
+            },
+            Payout::Nexprism {
+                prism_governance,
+                xprism_token,
+                nexprism_xprism_pair,
+            } => PayoutMode::Nexprism {
+                prism_governance: prism_governance.to_string(),
+                xprism_token: xprism_token.to_string(),
+                nexprism_xprism_pair: nexprism_xprism_pair.to_string(),
+            },
+        }
+    }
+}
+
+// Config layout before `Payout`, read once on migration.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LegacyConfig {
     pub governance: Addr,
     pub staking_token: Addr,
     pub stake_operator: Option<Addr>,
@@ -21,10 +165,27 @@ pub struct Config {
     pub nexprism_xprism_pair: Option<Addr>,
 }
 
-impl Config {
-    pub fn with_stake_operator(&self) -> bool {
-        self.stake_operator.is_some()
-    }
+// Stake operator fields of any config stored before multiple stake operators.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct StakeOperatorLayout {
+    #[serde(default)]
+    stake_operator: Option<Addr>,
+    #[serde(default)]
+    direct_bonding: Option<bool>,
+}
+
+// Surplus field of any state stored before surplus tracking.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct SurplusLayout {
+    #[serde(default)]
+    stranded_surplus: Option<Uint128>,
+}
+
+// Bonded field of any staker entry stored before direct bonding.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct BondedLayout {
+    #[serde(default)]
+    bonded: Option<Uint128>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -33,6 +194,27 @@ pub struct State {
     pub virtual_reward_balance: Uint128,
     pub virtual_rewards: RewardState,
     pub real_rewards: RewardState,
+    // sum of stakers lock boosts
+    #[serde(default)]
+    pub total_lock_boost: Uint128,
+    // part of `staking_total_balance` bonded directly
+    #[serde(default)]
+    pub total_bonded: Uint128,
+    // sum of exited stakers surpluses
+    #[serde(default)]
+    pub stranded_surplus: Uint128,
+    #[serde(default)]
+    pub total_donated: Uint128,
+    // scheduled lock boosts are removed from `total_lock_boost` up to this time
+    #[serde(default)]
+    pub locks_expired_until: u64,
+}
+
+impl State {
+    // Rewards are distributed by weight instead of balance.
+    pub fn total_weight(&self) -> Uint128 {
+        self.staking_total_balance + self.total_lock_boost
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -44,15 +226,127 @@ pub struct RewardState {
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct ReplyContext {
     pub rewards_recipient: Addr,
+    #[serde(default)]
+    pub rewards_msg: Option<Binary>,
+    #[serde(default)]
+    pub balance_before: Uint128,
+    #[serde(default)]
+    pub buy_nexprism: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
 pub struct Staker {
+    // bonded tokens and balances reported by stake operators
     pub balance: Uint128,
+    // tokens bonded directly, only they can be locked
+    #[serde(default)]
+    pub bonded: Uint128,
     pub real_index: Decimal,
     pub real_pending_rewards: Decimal,
     pub virtual_index: Decimal,
     pub virtual_pending_rewards: Decimal,
+    // contract default payout is used when not set
+    #[serde(default)]
+    pub payout_preference: Option<PayoutPreference>,
+    #[serde(default)]
+    pub locks: Vec<Lock>,
+    // sum of `locks` boosts
+    #[serde(default)]
+    pub lock_boost: Uint128,
+    // time when weight became zero
+    #[serde(default)]
+    pub exited_at: Option<u64>,
+}
+
+impl Staker {
+    pub fn weight(&self) -> Uint128 {
+        self.balance + self.lock_boost
+    }
+
+    // Entry without stake, anything to claim or collect as surplus and chosen payout.
+    pub fn is_dust(&self) -> bool {
+        self.weight().is_zero()
+            && self.bonded.is_zero()
+            && self.locks.is_empty()
+            && self.real_pending_rewards < Decimal::one()
+            && self.payout_preference.is_none()
+    }
+
+    // Real rewards that can't be claimed with virtual ones.
+    pub fn surplus(&self) -> Uint128 {
+        (self.real_pending_rewards * Uint128::new(1))
+            .saturating_sub(self.virtual_pending_rewards * Uint128::new(1))
+    }
+
+    pub fn locked_amount(&self, now: u64) -> Uint128 {
+        self.locks
+            .iter()
+            .filter(|lock| lock.unlock_time > now)
+            .map(|lock| lock.amount)
+            .sum()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Lock {
+    pub amount: Uint128,
+    pub unlock_time: u64,
+    pub multiplier: Decimal,
+    // boost of older locks leaves total weight only when staker releases them
+    #[serde(default)]
+    pub scheduled: bool,
+}
+
+// Boost of locks ending at the same time, it leaves total weight once the time has passed.
+// Stakers release their locks later, with rewards accrued up to `indexes`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct LockExpiration {
+    pub boost: Uint128,
+    // locks not released by stakers yet
+    pub locks: u32,
+    // real and virtual global indexes when boost left total weight
+    pub indexes: Option<(Decimal, Decimal)>,
+}
+
+impl Lock {
+    // Extra weight on top of locked amount.
+    pub fn boost(&self) -> Uint128 {
+        self.amount * self.multiplier - self.amount
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct VestingEntry {
+    pub amount: Uint128,
+    pub withdrawn: Uint128,
+    pub start_time: u64,
+    pub end_time: u64,
+}
+
+impl VestingEntry {
+    pub fn vested(&self, now: u64) -> Uint128 {
+        if now >= self.end_time {
+            self.amount
+        } else if now <= self.start_time {
+            Uint128::zero()
+        } else {
+            self.amount
+                .multiply_ratio(now - self.start_time, self.end_time - self.start_time)
+        }
+    }
+}
+
+// Staker entries update left after contract migration, done page by page.
+// Entries are also updated on their first use before the sweep reaches them.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StakersMigration {
+    pub start_after: Option<Addr>,
+    // all staked tokens were bonded directly
+    pub set_bonded: bool,
+    // exit time of stakers without weight and surplus tracking
+    pub exited_at: u64,
+    // height of the first balances snapshot
+    pub snapshot_height: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -65,6 +359,10 @@ pub fn load_state(storage: &dyn Storage) -> StdResult<State> {
     KEY_STATE.load(storage)
 }
 
+pub fn is_surplus_tracked(storage: &dyn Storage) -> StdResult<bool> {
+    Ok(KEY_SURPLUS_LAYOUT.load(storage)?.stranded_surplus.is_some())
+}
+
 pub fn save_state(storage: &mut dyn Storage, state: &State) -> StdResult<()> {
     KEY_STATE.save(storage, state)
 }
@@ -77,14 +375,223 @@ pub fn save_config(storage: &mut dyn Storage, config: &Config) -> StdResult<()>
     KEY_CONFIG.save(storage, config)
 }
 
+pub fn load_legacy_config(storage: &dyn Storage) -> StdResult<LegacyConfig> {
+    KEY_LEGACY_CONFIG.load(storage)
+}
+
+// Returns single stake operator if config was stored before multiple ones.
+pub fn load_legacy_stake_operator(storage: &dyn Storage) -> StdResult<Option<Option<Addr>>> {
+    let layout = KEY_STAKE_OPERATOR_LAYOUT.load(storage)?;
+    Ok(match layout.direct_bonding {
+        Some(_) => None,
+        None => Some(layout.stake_operator),
+    })
+}
+
 pub fn load_staker(storage: &dyn Storage, addr: &Addr) -> StdResult<Staker> {
+    let staker = STAKERS.may_load(storage, addr)?.unwrap_or_default();
+    migrate_staker(storage, addr, staker)
+}
+
+// Entries stored before direct bonding get their balance bonded while migration is pending.
+fn migrate_staker(storage: &dyn Storage, addr: &Addr, mut staker: Staker) -> StdResult<Staker> {
+    if let Some(migration) = load_stakers_migration(storage)? {
+        if migration.set_bonded && is_unbonded_layout(storage, addr)? {
+            staker.bonded = staker.balance;
+        }
+    }
+    Ok(staker)
+}
+
+pub fn is_unbonded_layout(storage: &dyn Storage, addr: &Addr) -> StdResult<bool> {
+    Ok(matches!(
+        STAKER_LAYOUTS.may_load(storage, addr)?,
+        Some(BondedLayout { bonded: None })
+    ))
+}
+
+pub fn save_staker(storage: &mut dyn Storage, addr: &Addr, holder: &Staker) -> StdResult<()> {
+    snapshot_migrated_balance(storage, addr)?;
+    STAKERS.save(storage, addr, holder)
+}
+
+pub fn remove_staker(storage: &mut dyn Storage, addr: &Addr) -> StdResult<()> {
+    snapshot_migrated_balance(storage, addr)?;
+    STAKERS.remove(storage, addr);
+    Ok(())
+}
+
+// Balance not changed since migration is recorded at its height before the first change.
+fn snapshot_migrated_balance(storage: &mut dyn Storage, addr: &Addr) -> StdResult<()> {
+    let height = match load_stakers_migration(storage)?.and_then(|m| m.snapshot_height) {
+        Some(height) => height,
+        None => return Ok(()),
+    };
+    if has_staker_balance_snapshot(storage, addr)? {
+        return Ok(());
+    }
+    match STAKERS.may_load(storage, addr)? {
+        Some(staker) => save_staker_balance_snapshot(storage, addr, staker.balance, height),
+        None => Ok(()),
+    }
+}
+
+pub fn load_stakers(
+    storage: &dyn Storage,
+    start_after: Option<&Addr>,
+    limit: usize,
+) -> StdResult<Vec<(Addr, Staker)>> {
     STAKERS
+        .range(
+            storage,
+            start_after.map(|addr| Bound::exclusive(addr.as_str())),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| {
+            let (addr, staker) = item?;
+            let addr = Addr::unchecked(String::from_utf8(addr)?);
+            let staker = migrate_staker(storage, &addr, staker)?;
+            Ok((addr, staker))
+        })
+        .collect()
+}
+
+pub fn save_staker_balance_snapshot(
+    storage: &mut dyn Storage,
+    addr: &Addr,
+    balance: Uint128,
+    height: u64,
+) -> StdResult<()> {
+    STAKER_BALANCES.save(storage, addr, &balance, height)
+}
+
+pub fn has_staker_balance_snapshot(storage: &dyn Storage, addr: &Addr) -> StdResult<bool> {
+    Ok(STAKER_BALANCES.may_load(storage, addr)?.is_some())
+}
+
+pub fn load_staker_balance_at(
+    storage: &dyn Storage,
+    addr: &Addr,
+    height: u64,
+) -> StdResult<Uint128> {
+    STAKER_BALANCES
+        .may_load_at_height(storage, addr, height)
+        .map(|res| res.unwrap_or_default())
+}
+
+pub fn save_total_staked_snapshot(
+    storage: &mut dyn Storage,
+    total: Uint128,
+    height: u64,
+) -> StdResult<()> {
+    TOTAL_STAKED.save(storage, &total, height)
+}
+
+pub fn load_total_staked_at(storage: &dyn Storage, height: u64) -> StdResult<Uint128> {
+    TOTAL_STAKED
+        .may_load_at_height(storage, height)
+        .map(|res| res.unwrap_or_default())
+}
+
+pub fn is_total_staked_snapshotted(storage: &dyn Storage) -> StdResult<bool> {
+    Ok(TOTAL_STAKED.may_load(storage)?.is_some())
+}
+
+pub fn load_vesting_entries(storage: &dyn Storage, addr: &Addr) -> StdResult<Vec<VestingEntry>> {
+    VESTING_ENTRIES
         .may_load(storage, addr)
         .map(|res| res.unwrap_or_default())
 }
 
-pub fn save_staker(storage: &mut dyn Storage, addr: &Addr, holder: &Staker) -> StdResult<()> {
-    STAKERS.save(storage, addr, holder)
+pub fn save_vesting_entries(
+    storage: &mut dyn Storage,
+    addr: &Addr,
+    entries: &[VestingEntry],
+) -> StdResult<()> {
+    if entries.is_empty() {
+        VESTING_ENTRIES.remove(storage, addr);
+        return Ok(());
+    }
+    VESTING_ENTRIES.save(storage, addr, &entries.to_vec())
+}
+
+pub fn load_donated(storage: &dyn Storage, donor: &Addr) -> StdResult<Uint128> {
+    DONATIONS
+        .may_load(storage, donor)
+        .map(|res| res.unwrap_or_default())
+}
+
+pub fn save_donated(storage: &mut dyn Storage, donor: &Addr, amount: Uint128) -> StdResult<()> {
+    DONATIONS.save(storage, donor, &amount)
+}
+
+pub fn load_lock_expiration(storage: &dyn Storage, time: u64) -> StdResult<LockExpiration> {
+    Ok(LOCK_EXPIRATIONS
+        .may_load(storage, time.into())?
+        .unwrap_or_default())
+}
+
+pub fn save_lock_expiration(
+    storage: &mut dyn Storage,
+    time: u64,
+    expiration: &LockExpiration,
+) -> StdResult<()> {
+    if expiration.locks == 0 {
+        LOCK_EXPIRATIONS.remove(storage, time.into());
+        return Ok(());
+    }
+    LOCK_EXPIRATIONS.save(storage, time.into(), expiration)
+}
+
+// Expirations after `after` time up to `until` inclusive.
+pub fn load_lock_expirations(
+    storage: &dyn Storage,
+    after: u64,
+    until: u64,
+) -> StdResult<Vec<(u64, LockExpiration)>> {
+    LOCK_EXPIRATIONS
+        .range(
+            storage,
+            Some(Bound::exclusive_int(after)),
+            Some(Bound::inclusive_int(until)),
+            Order::Ascending,
+        )
+        .map(|item| {
+            let (time, expiration) = item?;
+            let time: [u8; 8] = time
+                .as_slice()
+                .try_into()
+                .map_err(|_| StdError::generic_err("invalid lock expiration key"))?;
+            Ok((u64::from_be_bytes(time), expiration))
+        })
+        .collect()
+}
+
+pub fn load_hooks(storage: &dyn Storage) -> StdResult<Vec<Addr>> {
+    KEY_HOOKS
+        .may_load(storage)
+        .map(|res| res.unwrap_or_default())
+}
+
+pub fn save_hooks(storage: &mut dyn Storage, hooks: &[Addr]) -> StdResult<()> {
+    KEY_HOOKS.save(storage, &hooks.to_vec())
+}
+
+pub fn load_stakers_migration(storage: &dyn Storage) -> StdResult<Option<StakersMigration>> {
+    KEY_STAKERS_MIGRATION.may_load(storage)
+}
+
+pub fn save_stakers_migration(
+    storage: &mut dyn Storage,
+    migration: &StakersMigration,
+) -> StdResult<()> {
+    KEY_STAKERS_MIGRATION.save(storage, migration)
+}
+
+pub fn remove_stakers_migration(storage: &mut dyn Storage) {
+    KEY_STAKERS_MIGRATION.remove(storage)
 }
 
 pub fn load_gov_update(storage: &dyn Storage) -> StdResult<GovernanceUpdateState> {
@@ -1,12 +1,22 @@
-use cosmwasm_bignumber::{Decimal256, Uint256};
+use astroport::{
+    asset::{Asset, AssetInfo},
+    pair::SimulationResponse,
+};
 use cosmwasm_std::{
     to_binary, Addr, Decimal, Deps, DepsMut, Env, MessageInfo, Response, StdError, StdResult,
     SubMsg, Uint128, WasmMsg,
 };
-use integer_sqrt::IntegerSquareRoot;
+use cw20::Cw20ExecuteMsg;
+use nexus_prism_protocol::distribution::{
+    calculate_inner, next_ratios, split_rewards, RatioLimits, RewardRatios, Value,
+};
+use nexus_prism_protocol::events::{
+    RewardsDistributed, TypedEvent, VaultDeposited, VaultWithdrawn, VirtualRewardsDistributed,
+};
+use nexus_prism_protocol::vault::PostDepositAction;
 use nexus_prism_protocol::{
     cfg_addr, cfg_var,
-    common::{burn, div, get_price, get_time, mint, mul, send, transfer},
+    common::{burn, get_price, get_time, mint, query_token_balance, send, send_wasm_msg, transfer},
 };
 use prism_protocol::{
     launch_pool::{DistributionStatusResponse, RewardInfoResponse, VestingStatusResponse},
@@ -17,9 +27,11 @@ use crate::{
     error::ContractError,
     replies_id::ReplyId,
     state::{
-        load_config, load_state, may_load_prism_vesting_schedules, save_config,
+        load_config, load_deposit_reply_context, load_state, may_load_prism_vesting_schedules,
+        remove_deposit_reply_context, save_config, save_deposit_reply_context,
         save_locked_vested_prism_amount, save_prism_vesting_schedules, save_state, Config,
-        GovernanceUpdateState, PrismVestingSchedule, State, GOVERNANCE_UPDATE,
+        DepositReplyContext, GovernanceUpdateState, PrismVestingSchedule, State, GOVERNANCE_UPDATE,
+        INST_CONFIG,
     },
 };
 
@@ -50,6 +62,11 @@ pub fn update_config_by_owner(
     owner: Option<String>,
     prism_launch_pool: Option<String>,
     prism_xprism_boost: Option<String>,
+    prism_vault: Option<String>,
+    cluna_token: Option<String>,
+    pluna_token: Option<String>,
+    nexprism_autocompounder: Option<String>,
+    nyluna_autocompounder: Option<String>,
     prism_xprism_pair: Option<String>,
     prism_yluna_pair: Option<String>,
     rewards_distribution_update_period_secs: Option<u64>,
@@ -69,6 +86,22 @@ pub fn update_config_by_owner(
         prism_yluna_pair
     );
 
+    if let Some(prism_vault) = prism_vault {
+        config.prism_vault = Some(deps.api.addr_validate(&prism_vault)?);
+    }
+    if let Some(cluna_token) = cluna_token {
+        config.cluna_token = Some(deps.api.addr_validate(&cluna_token)?);
+    }
+    if let Some(pluna_token) = pluna_token {
+        config.pluna_token = Some(deps.api.addr_validate(&pluna_token)?);
+    }
+    if let Some(nexprism_autocompounder) = nexprism_autocompounder {
+        config.nexprism_autocompounder = Some(deps.api.addr_validate(&nexprism_autocompounder)?);
+    }
+    if let Some(nyluna_autocompounder) = nyluna_autocompounder {
+        config.nyluna_autocompounder = Some(deps.api.addr_validate(&nyluna_autocompounder)?);
+    }
+
     if let Some(rewards_distribution_update_period_secs) = rewards_distribution_update_period_secs {
         config.rewards_distribution_update_period_secs =
             if rewards_distribution_update_period_secs != 0 {
@@ -256,23 +289,19 @@ pub fn distribute_virtual_rewards(
             .add_submessage(update_staking_global_index(&config.psi_staking)?);
     }
 
-    Ok(resp
-        .add_attribute("virtual_rewards_total", amount)
-        .add_attribute("nexprism_stakers_virtual_rewards", nexprism_stakers_rewards)
-        .add_attribute("nyluna_stakers_virtual_rewards", nyluna_stakers_rewards)
-        .add_attribute("psi_stakers_virtual_rewards", psi_stakers_rewards))
+    Ok(resp.add_event(
+        VirtualRewardsDistributed(RewardsDistributed {
+            total: amount,
+            nexprism_stakers: nexprism_stakers_rewards,
+            nyluna_stakers: nyluna_stakers_rewards,
+            psi_stakers: psi_stakers_rewards,
+        })
+        .into_event(),
+    ))
 }
 
 pub fn calc_stakers_rewards(state: &State, total_rewards: Uint128) -> (Uint128, Uint128, Uint128) {
-    let nexprism_stakers_rewards = total_rewards * state.nexprism_stakers_reward_ratio;
-    let nyluna_stakers_rewards = total_rewards * state.nyluna_stakers_reward_ratio;
-    let psi_stakers_rewards = total_rewards - nexprism_stakers_rewards - nyluna_stakers_rewards;
-
-    (
-        nexprism_stakers_rewards,
-        nyluna_stakers_rewards,
-        psi_stakers_rewards,
-    )
+    split_rewards(&reward_ratios(state), total_rewards)
 }
 
 fn transfer_virtual_rewards(staking: &Addr, amount: Uint128) -> StdResult<SubMsg> {
@@ -335,10 +364,10 @@ fn claim_all_rewards_from_prism(env: &Env) -> StdResult<Vec<SubMsg>> {
 pub fn deposit_xprism(
     deps: DepsMut,
     env: Env,
-    _info: MessageInfo,
     config: Config,
-    sender: String,
+    recipient: Addr,
     amount: Uint128,
+    then: Option<PostDepositAction>,
 ) -> Result<Response, ContractError> {
     let mut state = load_state(deps.storage)?;
     state.xprism_amount_total += amount;
@@ -347,10 +376,13 @@ pub fn deposit_xprism(
 
     Ok(Response::new()
         .add_submessage(register_virtual_rewards_from_prism(&env)?)
-        .add_submessage(mint(
+        .add_submessages(mint_deposited(
+            &env,
+            &config,
             &config.nexprism_token,
-            &Addr::unchecked(sender),
+            &recipient,
             amount,
+            then,
         )?)
         .add_submessage(deposit_to_xprism_boost(
             &config.prism_xprism_boost,
@@ -359,9 +391,156 @@ pub fn deposit_xprism(
         )?)
         .add_submessages(claim_all_rewards_from_prism(&env)?)
         .add_attribute("action", "deposit_xprism")
+        .add_event(
+            VaultDeposited {
+                recipient: recipient.to_string(),
+                deposit_token: config.xprism_token.to_string(),
+                amount,
+            }
+            .into_event(),
+        ))
+}
+
+pub fn deposit_prism(
+    deps: DepsMut,
+    env: Env,
+    config: Config,
+    recipient: Addr,
+    amount: Uint128,
+    then: Option<PostDepositAction>,
+    best_route: bool,
+) -> Result<Response, ContractError> {
+    let prism_governance = INST_CONFIG.load(deps.storage)?.prism_governance;
+
+    let context = DepositReplyContext {
+        recipient,
+        xprism_balance_before: query_token_balance(
+            deps.as_ref(),
+            &config.xprism_token,
+            &env.contract.address,
+        ),
+        nexprism_balance_before: Uint128::zero(),
+        minted_xprism: Uint128::zero(),
+        best_route,
+        then,
+    };
+    save_deposit_reply_context(deps.storage, &context)?;
+
+    Ok(Response::new()
+        .add_submessage(SubMsg::reply_on_success(
+            send_wasm_msg(
+                &config.prism_token,
+                &prism_governance,
+                amount,
+                &prism_protocol::gov::Cw20HookMsg::MintXprism { receiver: None },
+            )?,
+            ReplyId::XPrismMinted.into(),
+        ))
+        .add_attribute("action", "deposit_prism")
         .add_attribute("amount", amount))
 }
 
+// Deposit of xPRISM gives nexPRISM 1:1, so the pair is better
+// only when it returns more than the deposited amount.
+pub fn deposit_minted_xprism(
+    deps: DepsMut,
+    env: Env,
+    config: Config,
+) -> Result<Response, ContractError> {
+    let mut context = load_deposit_reply_context(deps.storage)?;
+    let xprism_balance =
+        query_token_balance(deps.as_ref(), &config.xprism_token, &env.contract.address);
+    let minted_xprism = xprism_balance
+        .checked_sub(context.xprism_balance_before)
+        .map_err(StdError::from)?;
+
+    if context.best_route {
+        let nexprism_xprism_pair = INST_CONFIG.load(deps.storage)?.nexprism_xprism_pair;
+        let simulation: SimulationResponse = deps.querier.query_wasm_smart(
+            &nexprism_xprism_pair,
+            &astroport::pair::QueryMsg::Simulation {
+                offer_asset: Asset {
+                    info: AssetInfo::Token {
+                        contract_addr: config.xprism_token.clone(),
+                    },
+                    amount: minted_xprism,
+                },
+            },
+        )?;
+
+        if simulation.return_amount > minted_xprism {
+            // bought nexPRISM passes through the vault to check it against the minimum
+            context.nexprism_balance_before =
+                query_token_balance(deps.as_ref(), &config.nexprism_token, &env.contract.address);
+            context.minted_xprism = minted_xprism;
+            save_deposit_reply_context(deps.storage, &context)?;
+
+            return Ok(Response::new()
+                .add_submessage(SubMsg::reply_on_success(
+                    send_wasm_msg(
+                        &config.xprism_token,
+                        &nexprism_xprism_pair,
+                        minted_xprism,
+                        &astroport::pair::Cw20HookMsg::Swap {
+                            belief_price: Some(Decimal::one()),
+                            max_spread: Some(Decimal::zero()),
+                            to: None,
+                        },
+                    )?,
+                    ReplyId::NexPrismBought.into(),
+                ))
+                .add_attribute("action", "swap_minted_xprism")
+                .add_attribute("minted_xprism_amount", minted_xprism)
+                .add_attribute("nexprism_amount", simulation.return_amount)
+                .add_attribute("recipient", context.recipient));
+        }
+    }
+
+    remove_deposit_reply_context(deps.storage);
+
+    Ok(deposit_xprism(
+        deps,
+        env,
+        config,
+        context.recipient.clone(),
+        minted_xprism,
+        context.then,
+    )?
+    .add_attribute("minted_xprism_amount", minted_xprism))
+}
+
+pub fn deposit_bought_nexprism(
+    deps: DepsMut,
+    env: Env,
+    config: Config,
+) -> Result<Response, ContractError> {
+    let context = load_deposit_reply_context(deps.storage)?;
+    let nexprism_balance =
+        query_token_balance(deps.as_ref(), &config.nexprism_token, &env.contract.address);
+    let bought_nexprism = nexprism_balance
+        .checked_sub(context.nexprism_balance_before)
+        .map_err(StdError::from)?;
+    if bought_nexprism < context.minted_xprism {
+        return Err(ContractError::MinimumReceiveNotReached {
+            minimum: context.minted_xprism,
+            received: bought_nexprism,
+        });
+    }
+    remove_deposit_reply_context(deps.storage);
+
+    Ok(Response::new()
+        .add_submessages(post_deposit(
+            &config,
+            &config.nexprism_token,
+            &context.recipient,
+            bought_nexprism,
+            context.then,
+        )?)
+        .add_attribute("action", "deposit_bought_nexprism")
+        .add_attribute("nexprism_amount", bought_nexprism)
+        .add_attribute("recipient", context.recipient))
+}
+
 fn deposit_to_xprism_boost(
     xprism_boost: &Addr,
     xprism_token: &Addr,
@@ -378,10 +557,10 @@ fn deposit_to_xprism_boost(
 pub fn deposit_yluna(
     deps: DepsMut,
     env: Env,
-    _info: MessageInfo,
     config: Config,
-    sender: String,
+    recipient: Addr,
     amount: Uint128,
+    then: Option<PostDepositAction>,
 ) -> Result<Response, ContractError> {
     let mut state = load_state(deps.storage)?;
     state.yluna_amount_total += amount;
@@ -390,10 +569,13 @@ pub fn deposit_yluna(
 
     Ok(Response::new()
         .add_submessage(register_virtual_rewards_from_prism(&env)?)
-        .add_submessage(mint(
+        .add_submessages(mint_deposited(
+            &env,
+            &config,
             &config.nyluna_token,
-            &Addr::unchecked(sender),
+            &recipient,
             amount,
+            then,
         )?)
         .add_submessage(deposit_to_launch_pool(
             &config.prism_launch_pool,
@@ -402,7 +584,119 @@ pub fn deposit_yluna(
         )?)
         .add_submessages(claim_all_rewards_from_prism(&env)?)
         .add_attribute("action", "deposit_yluna")
-        .add_attribute("amount", amount))
+        .add_event(
+            VaultDeposited {
+                recipient: recipient.to_string(),
+                deposit_token: config.yluna_token.to_string(),
+                amount,
+            }
+            .into_event(),
+        ))
+}
+
+pub fn deposit_cluna(
+    deps: DepsMut,
+    env: Env,
+    config: Config,
+    recipient: Addr,
+    amount: Uint128,
+    then: Option<PostDepositAction>,
+) -> Result<Response, ContractError> {
+    let (cluna_token, pluna_token, prism_vault) = match (
+        config.cluna_token.clone(),
+        config.pluna_token.clone(),
+        config.prism_vault.clone(),
+    ) {
+        (Some(cluna_token), Some(pluna_token), Some(prism_vault)) => {
+            (cluna_token, pluna_token, prism_vault)
+        }
+        _ => return Err(ContractError::InvalidConfig {}),
+    };
+
+    // split gives pLUNA and yLUNA 1:1 to cLUNA
+    let deposit_resp = deposit_yluna(deps, env, config, recipient.clone(), amount, then)?;
+    Ok(Response::new()
+        .add_submessage(SubMsg::new(WasmMsg::Execute {
+            contract_addr: cluna_token.to_string(),
+            msg: to_binary(&Cw20ExecuteMsg::IncreaseAllowance {
+                spender: prism_vault.to_string(),
+                amount,
+                expires: None,
+            })?,
+            funds: vec![],
+        }))
+        .add_submessage(SubMsg::new(WasmMsg::Execute {
+            contract_addr: prism_vault.to_string(),
+            msg: to_binary(&prism_protocol::vault::ExecuteMsg::Split { amount })?,
+            funds: vec![],
+        }))
+        .add_submessage(transfer(&pluna_token, &recipient, amount)?)
+        .add_submessages(deposit_resp.messages)
+        .add_attributes(deposit_resp.attributes)
+        .add_events(deposit_resp.events)
+        .add_attribute("action", "deposit_cluna")
+        .add_attribute("cluna_amount", amount))
+}
+
+// With post deposit action the token is minted to the vault
+// and then forwarded on behalf of recipient.
+fn mint_deposited(
+    env: &Env,
+    config: &Config,
+    token: &Addr,
+    recipient: &Addr,
+    amount: Uint128,
+    then: Option<PostDepositAction>,
+) -> Result<Vec<SubMsg>, ContractError> {
+    if then.is_none() {
+        return Ok(vec![mint(token, recipient, amount)?]);
+    }
+
+    let mut msgs = vec![mint(token, &env.contract.address, amount)?];
+    msgs.extend(post_deposit(config, token, recipient, amount, then)?);
+    Ok(msgs)
+}
+
+fn post_deposit(
+    config: &Config,
+    token: &Addr,
+    recipient: &Addr,
+    amount: Uint128,
+    then: Option<PostDepositAction>,
+) -> Result<Vec<SubMsg>, ContractError> {
+    let (staking, autocompounder) = if *token == config.nexprism_token {
+        (&config.nexprism_staking, &config.nexprism_autocompounder)
+    } else if *token == config.nyluna_token {
+        (&config.nyluna_staking, &config.nyluna_autocompounder)
+    } else {
+        return Err(ContractError::InvalidConfig {});
+    };
+
+    match then {
+        None => Ok(vec![transfer(token, recipient, amount)?]),
+        Some(PostDepositAction::Stake {}) => Ok(vec![send(
+            token,
+            staking,
+            amount,
+            &nexus_prism_protocol::staking::Cw20HookMsg::Bond {
+                recipient: Some(recipient.to_string()),
+                lock_duration: None,
+            },
+        )?]),
+        Some(PostDepositAction::Autocompound {}) => {
+            let autocompounder = autocompounder
+                .as_ref()
+                .ok_or(ContractError::InvalidConfig {})?;
+            Ok(vec![send(
+                token,
+                autocompounder,
+                amount,
+                &nexus_prism_protocol::autocompounder::Cw20HookMsg::Deposit {
+                    recipient: Some(recipient.to_string()),
+                },
+            )?])
+        }
+    }
 }
 
 fn deposit_to_launch_pool(
@@ -421,9 +715,8 @@ fn deposit_to_launch_pool(
 pub fn withdraw_yluna(
     deps: DepsMut,
     env: Env,
-    _info: MessageInfo,
     config: Config,
-    sender: String,
+    recipient: Addr,
     amount: Uint128,
 ) -> Result<Response, ContractError> {
     let mut state = load_state(deps.storage)?;
@@ -438,14 +731,17 @@ pub fn withdraw_yluna(
             &config.prism_launch_pool,
             amount,
         )?)
-        .add_submessage(transfer(
-            &config.yluna_token,
-            &Addr::unchecked(sender),
-            amount,
-        )?)
+        .add_submessage(transfer(&config.yluna_token, &recipient, amount)?)
         .add_submessages(claim_all_rewards_from_prism(&env)?)
         .add_attribute("action", "withdraw_yluna")
-        .add_attribute("amount", amount))
+        .add_event(
+            VaultWithdrawn {
+                recipient: recipient.to_string(),
+                withdraw_token: config.yluna_token.to_string(),
+                amount,
+            }
+            .into_event(),
+        ))
 }
 
 fn withdraw_from_launch_pool(launch_pool: &Addr, amount: Uint128) -> StdResult<SubMsg> {
@@ -602,50 +898,35 @@ pub fn update_rewards_distribution(
         xprism_price,
     )?;
 
-    let mut new_state = state.clone();
-    match value {
-        Value::Negative => {
-            new_state.nexprism_stakers_reward_ratio = mul(
-                new_state.nexprism_stakers_reward_ratio,
-                config.rewards_distribution_update_step,
-            );
-            if new_state.nexprism_stakers_reward_ratio > config.max_nexprism_stakers_reward_ratio {
-                return Ok(state.clone());
-            }
-            new_state.nyluna_stakers_reward_ratio = Decimal::one()
-                - new_state.nexprism_stakers_reward_ratio
-                - new_state.psi_stakers_reward_ratio;
-            if new_state.nyluna_stakers_reward_ratio < config.min_nyluna_stakers_reward_ratio {
-                return Ok(state.clone());
-            }
-        }
-        Value::Positive => {
-            new_state.nexprism_stakers_reward_ratio = div(
-                new_state.nexprism_stakers_reward_ratio,
-                config.rewards_distribution_update_step,
-            );
-            if new_state.nexprism_stakers_reward_ratio < config.min_nexprism_stakers_reward_ratio {
-                return Ok(state.clone());
-            }
-            new_state.nyluna_stakers_reward_ratio = Decimal::one()
-                - new_state.nexprism_stakers_reward_ratio
-                - new_state.psi_stakers_reward_ratio;
-            if new_state.nyluna_stakers_reward_ratio > config.max_nyluna_stakers_reward_ratio {
-                return Ok(state.clone());
-            }
-        }
-        Value::Zero => {
-            return Ok(state.clone());
-        }
-    }
+    Ok(next_rewards_distribution(config, state, value))
+}
+
+fn next_rewards_distribution(config: &Config, state: &State, value: Value) -> State {
+    let ratios = next_ratios(
+        &RatioLimits {
+            update_step: config.rewards_distribution_update_step,
+            min_nexprism_stakers: config.min_nexprism_stakers_reward_ratio,
+            max_nexprism_stakers: config.max_nexprism_stakers_reward_ratio,
+            min_nyluna_stakers: config.min_nyluna_stakers_reward_ratio,
+            max_nyluna_stakers: config.max_nyluna_stakers_reward_ratio,
+        },
+        &reward_ratios(state),
+        value,
+    );
 
-    Ok(new_state)
+    let mut new_state = state.clone();
+    new_state.nexprism_stakers_reward_ratio = ratios.nexprism_stakers;
+    new_state.nyluna_stakers_reward_ratio = ratios.nyluna_stakers;
+    new_state.psi_stakers_reward_ratio = ratios.psi_stakers;
+    new_state
 }
 
-enum Value {
-    Zero,
-    Positive,
-    Negative,
+fn reward_ratios(state: &State) -> RewardRatios {
+    RewardRatios {
+        nexprism_stakers: state.nexprism_stakers_reward_ratio,
+        nyluna_stakers: state.nyluna_stakers_reward_ratio,
+        psi_stakers: state.psi_stakers_reward_ratio,
+    }
 }
 
 fn calculate(
@@ -688,36 +969,7 @@ fn calculate(
         reward_info.active_boost.into(),
         yluna_price.into(),
         xprism_price.into(),
-    ))
-}
-
-#[allow(clippy::too_many_arguments)]
-fn calculate_inner(
-    base_ratio: Decimal256,
-    xprism: Uint256,
-    yluna_total: Uint256,
-    yluna: Uint256,
-    weight_total: Uint256,
-    weight: Uint256,
-    ampl: Uint256,
-    yluna_price: Decimal256,
-    xprism_price: Decimal256,
-) -> Value {
-    let a = base_ratio * Decimal256::from_ratio(yluna_total - yluna, yluna_total * yluna_total);
-    let b = Decimal256::from_uint256(weight_total - weight);
-    let big_sqrt = (Uint128::from(ampl).u128() * Uint128::from(yluna).u128()).integer_sqrt();
-    let c = b + Decimal256::from_uint256(big_sqrt);
-    let d = (Decimal256::one() - base_ratio)
-        * b
-        * Decimal256::from(Decimal::from_ratio(ampl, yluna).sqrt())
-        / (Decimal256::from_uint256(2u64) * c * c);
-    let e = d * Decimal256::from_ratio(yluna, xprism) * yluna_price / xprism_price;
-
-    match (a + d).cmp(&e) {
-        std::cmp::Ordering::Greater => Value::Positive,
-        std::cmp::Ordering::Less => Value::Negative,
-        std::cmp::Ordering::Equal => Value::Zero,
-    }
+    )?)
 }
 
 pub fn accept_governance(
@@ -764,13 +1016,194 @@ pub fn update_governance(
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use std::str::FromStr;
 
+    use astroport::pair::SimulationResponse;
     use cosmwasm_bignumber::{Decimal256, Uint256};
-    use cosmwasm_std::Uint128;
+    use cosmwasm_std::{
+        testing::{mock_env, MockApi, MockStorage, MOCK_CONTRACT_ADDR},
+        to_binary, Addr, Decimal, OwnedDeps, SubMsg, Uint128, WasmMsg,
+    };
+    use cw20::Cw20ExecuteMsg;
+    use nexus_prism_protocol::{
+        common::{div, send, send_wasm_msg, transfer},
+        distribution::Value,
+        events::{TypedEvent, VaultDeposited},
+        testing::{mock_dependencies, WasmMockQuerier},
+        vault::PostDepositAction,
+    };
+
+    use crate::{
+        commands::{
+            calculate_inner, deposit_bought_nexprism, deposit_cluna, deposit_minted_xprism,
+            find_unregistered_rewards, next_rewards_distribution,
+        },
+        error::ContractError,
+        state::{
+            load_deposit_reply_context, load_state, save_deposit_reply_context, save_state, Config,
+            DepositReplyContext, InstantiationConfig, State, INST_CONFIG,
+        },
+    };
 
-    use crate::commands::{calculate_inner, find_unregistered_rewards};
+    pub(crate) fn config() -> Config {
+        Config {
+            owner: Addr::unchecked("owner"),
+            governance: Addr::unchecked("gov"),
+            xprism_token: Addr::unchecked("xprism"),
+            nexprism_token: Addr::unchecked("nexprism"),
+            yluna_token: Addr::unchecked("yluna"),
+            nyluna_token: Addr::unchecked("nyluna"),
+            prism_token: Addr::unchecked("prism"),
+            cluna_token: None,
+            pluna_token: None,
+            prism_launch_pool: Addr::unchecked("launch_pool"),
+            prism_xprism_boost: Addr::unchecked("xprism_boost"),
+            prism_vault: None,
+            nexprism_staking: Addr::unchecked("nexprism_staking"),
+            psi_staking: Addr::unchecked("psi_staking"),
+            nyluna_staking: Addr::unchecked("nyluna_staking"),
+            nexprism_autocompounder: None,
+            nyluna_autocompounder: None,
+            prism_xprism_pair: Addr::unchecked("prism_xprism_pair"),
+            prism_yluna_pair: Addr::unchecked("prism_yluna_pair"),
+            rewards_distribution_update_period_secs: None,
+            rewards_distribution_update_step: Decimal::from_str("1.05").unwrap(),
+            min_nexprism_stakers_reward_ratio: Decimal::percent(10),
+            max_nexprism_stakers_reward_ratio: Decimal::percent(90),
+            min_nyluna_stakers_reward_ratio: Decimal::percent(10),
+            max_nyluna_stakers_reward_ratio: Decimal::percent(90),
+        }
+    }
+
+    fn mock_simulation(querier: &mut WasmMockQuerier, return_amount: u128) {
+        querier.set_handler("nexprism_xprism_pair", move |_| {
+            to_binary(&SimulationResponse {
+                return_amount: Uint128::new(return_amount),
+                spread_amount: Uint128::zero(),
+                commission_amount: Uint128::zero(),
+            })
+        });
+    }
+
+    fn setup() -> OwnedDeps<MockStorage, MockApi, WasmMockQuerier> {
+        let mut deps = mock_dependencies(&[]);
+        INST_CONFIG
+            .save(
+                &mut deps.storage,
+                &InstantiationConfig {
+                    admin: Addr::unchecked("admin"),
+                    cw20_token_code_id: 1,
+                    staking_code_id: 2,
+                    autocompounder_code_id: 3,
+                    autocompounder_reward_swap_max_spread: Decimal::percent(1),
+                    astroport_factory: Addr::unchecked("factory"),
+                    nexprism_xprism_pair: Addr::unchecked("nexprism_xprism_pair"),
+                    nexprism_xprism_amp_coef: 10,
+                    psi_token: Addr::unchecked("psi"),
+                    prism_governance: Addr::unchecked("prism_gov"),
+                },
+            )
+            .unwrap();
+        save_state(
+            &mut deps.storage,
+            &config(),
+            &State {
+                nexprism_stakers_reward_ratio: Decimal::percent(50),
+                nyluna_stakers_reward_ratio: Decimal::percent(30),
+                psi_stakers_reward_ratio: Decimal::percent(20),
+                last_calculation_time: 0,
+                xprism_amount_total: Uint128::zero(),
+                yluna_amount_total: Uint128::zero(),
+            },
+        )
+        .unwrap();
+        deps
+    }
+
+    fn setup_prism_deposit(
+        minted_xprism: u128,
+    ) -> OwnedDeps<MockStorage, MockApi, WasmMockQuerier> {
+        let mut deps = setup();
+        save_deposit_reply_context(
+            &mut deps.storage,
+            &DepositReplyContext {
+                recipient: Addr::unchecked("depositor"),
+                xprism_balance_before: Uint128::new(10),
+                nexprism_balance_before: Uint128::zero(),
+                minted_xprism: Uint128::zero(),
+                best_route: true,
+                then: None,
+            },
+        )
+        .unwrap();
+        deps.querier
+            .set_token_balance("xprism", MOCK_CONTRACT_ADDR, 10 + minted_xprism);
+        deps
+    }
+
+    #[test]
+    fn best_route_swap_is_floored_by_deposit() {
+        let mut deps = setup_prism_deposit(100);
+        mock_simulation(&mut deps.querier, 110);
+        deps.querier
+            .set_token_balance("nexprism", MOCK_CONTRACT_ADDR, 7);
+
+        let res = deposit_minted_xprism(deps.as_mut(), mock_env(), config()).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(
+            res.messages[0].msg,
+            send_wasm_msg(
+                &Addr::unchecked("xprism"),
+                &Addr::unchecked("nexprism_xprism_pair"),
+                Uint128::new(100),
+                &astroport::pair::Cw20HookMsg::Swap {
+                    belief_price: Some(Decimal::one()),
+                    max_spread: Some(Decimal::zero()),
+                    to: None,
+                },
+            )
+            .unwrap()
+            .into()
+        );
+        let context = load_deposit_reply_context(&deps.storage).unwrap();
+        assert_eq!(context.minted_xprism, Uint128::new(100));
+        assert_eq!(context.nexprism_balance_before, Uint128::new(7));
+
+        // swap returned less than deposit would mint
+        deps.querier
+            .set_token_balance("nexprism", MOCK_CONTRACT_ADDR, 106);
+        assert_eq!(
+            deposit_bought_nexprism(deps.as_mut(), mock_env(), config()),
+            Err(ContractError::MinimumReceiveNotReached {
+                minimum: Uint128::new(100),
+                received: Uint128::new(99),
+            })
+        );
+
+        deps.querier
+            .set_token_balance("nexprism", MOCK_CONTRACT_ADDR, 117);
+        let res = deposit_bought_nexprism(deps.as_mut(), mock_env(), config()).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![transfer(
+                &Addr::unchecked("nexprism"),
+                &Addr::unchecked("depositor"),
+                Uint128::new(110)
+            )
+            .unwrap()]
+        );
+        assert!(load_deposit_reply_context(&deps.storage).is_err());
+    }
+
+    #[test]
+    fn deposit_context_is_removed_without_swap() {
+        let mut deps = setup_prism_deposit(100);
+        mock_simulation(&mut deps.querier, 100);
+
+        deposit_minted_xprism(deps.as_mut(), mock_env(), config()).unwrap();
+        assert!(load_deposit_reply_context(&deps.storage).is_err());
+    }
 
     #[test]
     fn calculation_fails_when_no_more_bonds_available() {
@@ -794,7 +1227,65 @@ mod tests {
             ampl,
             yluna_price,
             xprism_price,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn rewards_distribution_follows_value() {
+        let state = State {
+            nexprism_stakers_reward_ratio: Decimal::percent(50),
+            nyluna_stakers_reward_ratio: Decimal::percent(30),
+            psi_stakers_reward_ratio: Decimal::percent(20),
+            last_calculation_time: 0,
+            xprism_amount_total: Uint128::zero(),
+            yluna_amount_total: Uint128::zero(),
+        };
+        let value = |xprism_price: &str, ampl: Uint256| {
+            calculate_inner(
+                Decimal256::from_str("0.8").unwrap(),
+                Uint256::from(1_000_000u64),
+                Uint256::from(10_000_000u64),
+                Uint256::from(1_000_000u64),
+                Uint256::from(10_000_000u64),
+                Uint256::from(1_000_000u64),
+                ampl,
+                Decimal256::one(),
+                Decimal256::from_str(xprism_price).unwrap(),
+            )
+        };
+
+        let positive = value("1", Uint256::from(1_000_000u64)).unwrap();
+        assert_eq!(positive, Value::Positive);
+        let new_state = next_rewards_distribution(&config(), &state, positive);
+        assert_eq!(
+            new_state.nexprism_stakers_reward_ratio,
+            div(Decimal::percent(50), Decimal::from_str("1.05").unwrap())
+        );
+        assert_eq!(
+            new_state.nyluna_stakers_reward_ratio,
+            Decimal::one() - new_state.nexprism_stakers_reward_ratio - Decimal::percent(20)
+        );
+
+        let negative = value("0.05", Uint256::from(1_000_000u64)).unwrap();
+        assert_eq!(negative, Value::Negative);
+        let new_state = next_rewards_distribution(&config(), &state, negative);
+        assert_eq!(
+            new_state.nexprism_stakers_reward_ratio,
+            Decimal::from_str("0.525").unwrap()
+        );
+        assert_eq!(
+            new_state.nyluna_stakers_reward_ratio,
+            Decimal::from_str("0.275").unwrap()
         );
+        assert_eq!(new_state.psi_stakers_reward_ratio, Decimal::percent(20));
+
+        // boost bigger than Uint128 is an error instead of a panic
+        assert!(value(
+            "1",
+            Uint256::from_str("1000000000000000000000000000000000000000").unwrap()
+        )
+        .is_err());
     }
 
     #[test]
@@ -880,4 +1371,90 @@ mod tests {
         let rewards = find_unregistered_rewards(cur, prev);
         assert_eq!(rewards, Uint128::zero());
     }
+
+    #[test]
+    fn cluna_is_split_and_deposited() {
+        let mut deps = setup();
+        let mut config = config();
+        assert_eq!(
+            deposit_cluna(
+                deps.as_mut(),
+                mock_env(),
+                config.clone(),
+                Addr::unchecked("depositor"),
+                Uint128::new(100),
+                None,
+            ),
+            Err(ContractError::InvalidConfig {})
+        );
+
+        config.cluna_token = Some(Addr::unchecked("cluna"));
+        config.pluna_token = Some(Addr::unchecked("pluna"));
+        config.prism_vault = Some(Addr::unchecked("prism_vault"));
+        let res = deposit_cluna(
+            deps.as_mut(),
+            mock_env(),
+            config,
+            Addr::unchecked("depositor"),
+            Uint128::new(100),
+            Some(PostDepositAction::Stake {}),
+        )
+        .unwrap();
+
+        assert_eq!(
+            res.messages[..3],
+            vec![
+                SubMsg::new(WasmMsg::Execute {
+                    contract_addr: "cluna".to_string(),
+                    msg: to_binary(&Cw20ExecuteMsg::IncreaseAllowance {
+                        spender: "prism_vault".to_string(),
+                        amount: Uint128::new(100),
+                        expires: None,
+                    })
+                    .unwrap(),
+                    funds: vec![],
+                }),
+                SubMsg::new(WasmMsg::Execute {
+                    contract_addr: "prism_vault".to_string(),
+                    msg: to_binary(&prism_protocol::vault::ExecuteMsg::Split {
+                        amount: Uint128::new(100)
+                    })
+                    .unwrap(),
+                    funds: vec![],
+                }),
+                transfer(
+                    &Addr::unchecked("pluna"),
+                    &Addr::unchecked("depositor"),
+                    Uint128::new(100)
+                )
+                .unwrap(),
+            ]
+        );
+        // minted nyLUNA is staked on behalf of depositor
+        assert!(res.messages.contains(
+            &send(
+                &Addr::unchecked("nyluna"),
+                &Addr::unchecked("nyluna_staking"),
+                Uint128::new(100),
+                &nexus_prism_protocol::staking::Cw20HookMsg::Bond {
+                    recipient: Some("depositor".to_string()),
+                    lock_duration: None,
+                },
+            )
+            .unwrap()
+        ));
+        // deposit event of the split yLUNA is kept
+        assert_eq!(
+            VaultDeposited::from_event(&res.events[0]).unwrap(),
+            VaultDeposited {
+                recipient: "depositor".to_string(),
+                deposit_token: "yluna".to_string(),
+                amount: Uint128::new(100),
+            }
+        );
+        assert_eq!(
+            load_state(&deps.storage).unwrap().yluna_amount_total,
+            Uint128::new(100)
+        );
+    }
 }
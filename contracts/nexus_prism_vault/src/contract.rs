@@ -1,22 +1,23 @@
 use crate::commands::{
-    accept_governance, claim_all_rewards, claim_real_rewards, claim_virtual_rewards,
-    deposit_xprism, deposit_yluna, register_virtual_rewards, update_config_by_governance,
-    update_config_by_owner, update_governance, update_rewards_distribution_by_owner, update_state,
-    withdraw_yluna,
+    accept_governance, claim_all_rewards, claim_real_rewards, claim_virtual_rewards, deposit_cluna,
+    deposit_prism, deposit_xprism, deposit_yluna, register_virtual_rewards,
+    update_config_by_governance, update_config_by_owner, update_governance,
+    update_rewards_distribution_by_owner, update_state, withdraw_yluna,
 };
 use crate::queries::{
-    query_config, query_potential_rewards, query_state, simulate_update_rewards_distribution,
+    query_config, query_potential_rewards, query_prism_vesting_schedules, query_state,
+    simulate_update_rewards_distribution,
 };
 use crate::replies_id::ReplyId;
 use cosmwasm_std::{entry_point, Uint128};
 use cosmwasm_std::{
-    from_binary, to_binary, Addr, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdError,
-    StdResult, SubMsg,
+    from_binary, to_binary, Addr, Binary, Decimal, Deps, DepsMut, Env, MessageInfo, Response,
+    StdError, StdResult, SubMsg,
 };
 use cw0::nonpayable;
 use cw2::{get_contract_version, set_contract_version};
 use cw20::Cw20ReceiveMsg;
-use nexus_prism_protocol::common::instantiate_token;
+use nexus_prism_protocol::common::{instantiate_token, optional_addr_validate};
 use nexus_prism_protocol::vault::{
     Cw20HookMsg, ExecuteMsg, GovernanceMsg, InstantiateMsg, MigrateMsg, MyselfMsg, OwnerMsg,
     QueryMsg,
@@ -39,11 +40,18 @@ pub fn instantiate(
 ) -> Result<Response, ContractError> {
     nonpayable(&info)?;
 
+    if msg.autocompounder_reward_swap_max_spread.is_zero()
+        || msg.autocompounder_reward_swap_max_spread >= Decimal::one()
+    {
+        return Err(ContractError::InvalidConfig {});
+    }
+
     let inst_config = InstantiationConfig {
         admin: info.sender.clone(),
         cw20_token_code_id: msg.cw20_token_code_id,
         staking_code_id: msg.staking_code_id,
         autocompounder_code_id: msg.autocompounder_code_id,
+        autocompounder_reward_swap_max_spread: msg.autocompounder_reward_swap_max_spread,
         astroport_factory: deps.api.addr_validate(&msg.astroport_factory)?,
         nexprism_xprism_amp_coef: msg.nexprism_xprism_amp_coef,
         psi_token: deps.api.addr_validate(&msg.psi_token)?,
@@ -61,14 +69,20 @@ pub fn instantiate(
         yluna_token: deps.api.addr_validate(&msg.yluna_token)?,
         nyluna_token: Addr::unchecked(""),
         prism_token: deps.api.addr_validate(&msg.prism_token)?,
+        cluna_token: optional_addr_validate(deps.as_ref(), msg.cluna_token)?,
+        pluna_token: optional_addr_validate(deps.as_ref(), msg.pluna_token)?,
 
         prism_launch_pool: deps.api.addr_validate(&msg.prism_launch_pool)?,
         prism_xprism_boost: deps.api.addr_validate(&msg.prism_xprism_boost)?,
+        prism_vault: optional_addr_validate(deps.as_ref(), msg.prism_vault)?,
 
         nexprism_staking: Addr::unchecked(""),
         psi_staking: Addr::unchecked(""),
         nyluna_staking: Addr::unchecked(""),
 
+        nexprism_autocompounder: None,
+        nyluna_autocompounder: None,
+
         prism_xprism_pair: deps.api.addr_validate(&msg.prism_xprism_pair)?,
         prism_yluna_pair: deps.api.addr_validate(&msg.prism_yluna_pair)?,
 
@@ -148,7 +162,7 @@ pub fn execute(
             if info.sender != config.owner {
                 return Err(ContractError::Unauthorized {});
             }
-            match msg {
+            match *msg {
                 OwnerMsg::UpdateRewardsDistribution {} => {
                     update_rewards_distribution_by_owner(deps, env, config)
                 }
@@ -169,6 +183,11 @@ pub fn execute(
                     owner,
                     prism_launch_pool,
                     prism_xprism_boost,
+                    prism_vault,
+                    cluna_token,
+                    pluna_token,
+                    nexprism_autocompounder,
+                    nyluna_autocompounder,
                     prism_xprism_pair,
                     prism_yluna_pair,
                     rewards_distribution_update_period_secs,
@@ -183,6 +202,11 @@ pub fn execute(
                     owner,
                     prism_launch_pool,
                     prism_xprism_boost,
+                    prism_vault,
+                    cluna_token,
+                    pluna_token,
+                    nexprism_autocompounder,
+                    nyluna_autocompounder,
                     prism_xprism_pair,
                     prism_yluna_pair,
                     rewards_distribution_update_period_secs,
@@ -253,18 +277,43 @@ fn receive_cw20(
     let token = info.sender.clone();
 
     match from_binary(&cw20_msg.msg) {
-        Ok(Cw20HookMsg::Deposit {}) if token == config.xprism_token => {
-            deposit_xprism(deps, env, info, config, cw20_msg.sender, cw20_msg.amount)
-        }
-        Ok(Cw20HookMsg::Deposit {}) if token == config.yluna_token => {
-            deposit_yluna(deps, env, info, config, cw20_msg.sender, cw20_msg.amount)
+        Ok(Cw20HookMsg::Deposit {
+            recipient,
+            then,
+            best_route,
+        }) => {
+            let recipient = deps
+                .api
+                .addr_validate(&recipient.unwrap_or(cw20_msg.sender))?;
+
+            if token == config.xprism_token {
+                deposit_xprism(deps, env, config, recipient, cw20_msg.amount, then)
+            } else if token == config.yluna_token {
+                deposit_yluna(deps, env, config, recipient, cw20_msg.amount, then)
+            } else if token == config.prism_token {
+                deposit_prism(
+                    deps,
+                    env,
+                    config,
+                    recipient,
+                    cw20_msg.amount,
+                    then,
+                    best_route,
+                )
+            } else if Some(&token) == config.cluna_token.as_ref() {
+                deposit_cluna(deps, env, config, recipient, cw20_msg.amount, then)
+            } else {
+                Err(ContractError::Unauthorized {})
+            }
         }
-        Ok(Cw20HookMsg::Deposit {}) => Err(ContractError::Unauthorized {}),
 
-        Ok(Cw20HookMsg::Withdraw {}) if token == config.nyluna_token => {
-            withdraw_yluna(deps, env, info, config, cw20_msg.sender, cw20_msg.amount)
+        Ok(Cw20HookMsg::Withdraw { recipient }) if token == config.nyluna_token => {
+            let recipient = deps
+                .api
+                .addr_validate(&recipient.unwrap_or(cw20_msg.sender))?;
+            withdraw_yluna(deps, env, config, recipient, cw20_msg.amount)
         }
-        Ok(Cw20HookMsg::Withdraw {}) => Err(ContractError::Unauthorized {}),
+        Ok(Cw20HookMsg::Withdraw { .. }) => Err(ContractError::Unauthorized {}),
 
         Err(err) => Err(ContractError::Std(err)),
     }
@@ -287,6 +336,7 @@ pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
             staking_contract,
             user_addr,
         )?),
+        QueryMsg::PrismVestingSchedules {} => to_binary(&query_prism_vesting_schedules(deps, env)?),
     }
 }
 
@@ -1,4 +1,4 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{StdError, Uint128};
 use cw0::PaymentError;
 use thiserror::Error;
 
@@ -19,6 +19,9 @@ pub enum ContractError {
     #[error("invalid state")]
     InvalidState {},
 
+    #[error("swap returned {received}, less than minimum {minimum}")]
+    MinimumReceiveNotReached { minimum: Uint128, received: Uint128 },
+
     #[error("unknown reply id={id}")]
     UnknownReplyId { id: u64 },
 }
@@ -1,6 +1,7 @@
 use cosmwasm_std::{Addr, Deps, Env, StdResult, Uint128};
 use nexus_prism_protocol::vault::{
-    ConfigResponse, PotentialRewardsResponse, StakingContract, StateResponse,
+    ConfigResponse, PotentialRewardsResponse, PrismVestingScheduleResponse,
+    PrismVestingSchedulesResponse, StakingContract, StateResponse,
     UpdateRewardsDistributionResponse,
 };
 
@@ -25,12 +26,17 @@ pub fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
         nyluna_token: config.nyluna_token.to_string(),
         prism_token: config.prism_token.to_string(),
 
+        cluna_token: config.cluna_token.map(|addr| addr.to_string()),
+        pluna_token: config.pluna_token.map(|addr| addr.to_string()),
         prism_launch_pool: config.prism_launch_pool.to_string(),
         prism_xprism_boost: config.prism_xprism_boost.to_string(),
+        prism_vault: config.prism_vault.map(|addr| addr.to_string()),
 
         nexprism_staking: config.nexprism_staking.to_string(),
         psi_staking: config.psi_staking.to_string(),
         nyluna_staking: config.nyluna_staking.to_string(),
+        nexprism_autocompounder: config.nexprism_autocompounder.map(|addr| addr.to_string()),
+        nyluna_autocompounder: config.nyluna_autocompounder.map(|addr| addr.to_string()),
 
         prism_xprism_pair: config.prism_xprism_pair.to_string(),
         prism_yluna_pair: config.prism_yluna_pair.to_string(),
@@ -112,6 +118,24 @@ pub fn query_potential_rewards(
     Ok(PotentialRewardsResponse { rewards })
 }
 
+pub fn query_prism_vesting_schedules(
+    deps: Deps,
+    env: Env,
+) -> StdResult<PrismVestingSchedulesResponse> {
+    let config = load_config(deps.storage)?;
+    let schedules = prism_vesting_schedules(deps, &env, &config.prism_launch_pool)?;
+
+    Ok(PrismVestingSchedulesResponse {
+        schedules: schedules
+            .into_iter()
+            .map(|schedule| PrismVestingScheduleResponse {
+                end_time: schedule.end_time,
+                amount: schedule.amount,
+            })
+            .collect(),
+    })
+}
+
 fn query_staking_potential_rewards(
     deps: Deps,
     staking_addr: &Addr,
@@ -14,4 +14,6 @@ pub enum ReplyId {
     XPrismBoostActivated,
     VirtualRewardsClaimed,
     RealRewardsClaimed,
+    XPrismMinted,
+    NexPrismBought,
 }
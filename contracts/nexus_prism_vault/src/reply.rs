@@ -5,11 +5,14 @@ use cosmwasm_std::{
     to_binary, Addr, CosmosMsg, DepsMut, Env, Reply, Response, StdError, StdResult, SubMsg, WasmMsg,
 };
 use nexus_prism_protocol::common::{query_token_balance, transfer};
+use nexus_prism_protocol::events::{RealRewardsDistributed, RewardsDistributed, TypedEvent};
+use nexus_prism_protocol::staking::PayoutMode;
 use protobuf::Message;
 
 use crate::commands::{
-    calc_stakers_rewards, distribute_virtual_rewards, get_locked_vested_prism_amount,
-    prism_vesting_schedules, update_staking_global_index,
+    calc_stakers_rewards, deposit_bought_nexprism, deposit_minted_xprism,
+    distribute_virtual_rewards, get_locked_vested_prism_amount, prism_vesting_schedules,
+    update_staking_global_index,
 };
 use crate::state::{load_locked_vested_prism_amount, save_prism_vesting_schedules, State};
 use crate::{
@@ -50,8 +53,7 @@ fn instantiate_staking(
     config: &Config,
     stake_operator: Option<&Addr>,
     staking_token: &Addr,
-    prism_governance: Option<&Addr>,
-    is_nexprism_xprism: bool,
+    payout_mode: PayoutMode,
     reply_id: ReplyId,
 ) -> StdResult<SubMsg> {
     Ok(SubMsg::reply_on_success(
@@ -59,22 +61,15 @@ fn instantiate_staking(
             admin: Some(inst_config.admin.to_string()),
             code_id: inst_config.staking_code_id,
             msg: to_binary(&nexus_prism_protocol::staking::InstantiateMsg {
-                stake_operator: stake_operator.map(|x| x.to_string()),
+                stake_operators: stake_operator
+                    .map(|x| vec![x.to_string()])
+                    .unwrap_or_default(),
+                direct_bonding: stake_operator.is_none(),
                 staking_token: staking_token.to_string(),
                 reward_operator: env.contract.address.to_string(),
                 reward_token: config.prism_token.to_string(),
-                prism_governance: prism_governance.map(|x| x.to_string()),
                 governance: config.governance.to_string(),
-                xprism_token: if is_nexprism_xprism {
-                    Some(config.xprism_token.to_string())
-                } else {
-                    None
-                },
-                nexprism_xprism_pair: if is_nexprism_xprism {
-                    Some(inst_config.nexprism_xprism_pair.to_string())
-                } else {
-                    None
-                },
+                payout_mode,
             })?,
             funds: vec![],
             label: "".to_owned(),
@@ -94,8 +89,7 @@ fn instantiate_nyluna_staking(
         config,
         None,
         &config.nyluna_token,
-        None,
-        false,
+        PayoutMode::Prism {},
         ReplyId::NYLunaStakingCreated,
     )
 }
@@ -111,8 +105,10 @@ fn instantiate_nexprism_staking(
         config,
         None,
         &config.nexprism_token,
-        Some(&inst_config.prism_governance),
-        false,
+        PayoutMode::Xprism {
+            prism_governance: inst_config.prism_governance.to_string(),
+            xprism_token: config.xprism_token.to_string(),
+        },
         ReplyId::NexPrismStakingCreated,
     )
 }
@@ -128,8 +124,11 @@ fn instantiate_psi_staking(
         config,
         Some(&config.governance),
         &inst_config.psi_token,
-        Some(&inst_config.prism_governance),
-        true,
+        PayoutMode::Nexprism {
+            prism_governance: inst_config.prism_governance.to_string(),
+            xprism_token: config.xprism_token.to_string(),
+            nexprism_xprism_pair: inst_config.nexprism_xprism_pair.to_string(),
+        },
         ReplyId::PsiStakingCreated,
     )
 }
@@ -154,6 +153,7 @@ fn instantiate_autocompounder(
                 governance: config.governance.to_string(),
                 staking_contract: staking_contract.to_string(),
                 cw20_token_code_id: inst_config.cw20_token_code_id,
+                reward_swap_max_spread: inst_config.autocompounder_reward_swap_max_spread,
             })?,
             funds: vec![],
             label: "".to_owned(),
@@ -289,13 +289,25 @@ pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> Result<Response, ContractEr
                 .add_attribute("nyluna_staking", config.nyluna_staking))
         }
 
-        ReplyId::NexPrismAutocompounderCreated => Ok(Response::new()
-            .add_attribute("action", "nexprism_autocompounder_instantiated")
-            .add_attribute("nexprism_autocompounder", get_addr(msg)?)),
+        ReplyId::NexPrismAutocompounderCreated => {
+            let nexprism_autocompounder = get_addr(msg)?;
+            config.nexprism_autocompounder = Some(nexprism_autocompounder.clone());
+            save_config(deps.storage, &config)?;
 
-        ReplyId::NYLunaAutocompounderCreated => Ok(Response::new()
-            .add_attribute("action", "nyluna_autocompounder_instantiated")
-            .add_attribute("nyluna_autocompounder", get_addr(msg)?)),
+            Ok(Response::new()
+                .add_attribute("action", "nexprism_autocompounder_instantiated")
+                .add_attribute("nexprism_autocompounder", nexprism_autocompounder))
+        }
+
+        ReplyId::NYLunaAutocompounderCreated => {
+            let nyluna_autocompounder = get_addr(msg)?;
+            config.nyluna_autocompounder = Some(nyluna_autocompounder.clone());
+            save_config(deps.storage, &config)?;
+
+            Ok(Response::new()
+                .add_attribute("action", "nyluna_autocompounder_instantiated")
+                .add_attribute("nyluna_autocompounder", nyluna_autocompounder))
+        }
 
         ReplyId::XPrismBoostActivated => match msg.result {
             cosmwasm_std::ContractResult::Err(err_msg) => {
@@ -347,6 +359,10 @@ pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> Result<Response, ContractEr
                 Ok(real_rewards_claimed_logic(deps, &env, &config, &state)?)
             }
         },
+
+        ReplyId::XPrismMinted => deposit_minted_xprism(deps, env, config),
+
+        ReplyId::NexPrismBought => deposit_bought_nexprism(deps, env, config),
     }
 }
 
@@ -403,8 +419,79 @@ fn real_rewards_claimed_logic(
 
     Ok(resp
         .add_attribute("action", "real_rewards_claimed")
-        .add_attribute("real_rewards_total", claimed_rewards)
-        .add_attribute("nexprism_stakers_real_rewards", nexprism_stakers_rewards)
-        .add_attribute("nyluna_stakers_real_rewards", nyluna_stakers_rewards)
-        .add_attribute("psi_stakers_real_rewards", psi_stakers_rewards))
+        .add_event(
+            RealRewardsDistributed(RewardsDistributed {
+                total: claimed_rewards,
+                nexprism_stakers: nexprism_stakers_rewards,
+                nyluna_stakers: nyluna_stakers_rewards,
+                psi_stakers: psi_stakers_rewards,
+            })
+            .into_event(),
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use cosmwasm_std::{from_binary, testing::mock_env, Addr, CosmosMsg, Decimal, SubMsg, WasmMsg};
+    use nexus_prism_protocol::staking::{InstantiateMsg, PayoutMode};
+
+    use super::{
+        instantiate_nexprism_staking, instantiate_nyluna_staking, instantiate_psi_staking,
+    };
+    use crate::{commands::tests::config, state::InstantiationConfig};
+
+    fn inst_config() -> InstantiationConfig {
+        InstantiationConfig {
+            admin: Addr::unchecked("admin"),
+            cw20_token_code_id: 1,
+            staking_code_id: 2,
+            autocompounder_code_id: 3,
+            autocompounder_reward_swap_max_spread: Decimal::percent(1),
+            astroport_factory: Addr::unchecked("factory"),
+            nexprism_xprism_pair: Addr::unchecked("nexprism_xprism_pair"),
+            nexprism_xprism_amp_coef: 10,
+            psi_token: Addr::unchecked("psi"),
+            prism_governance: Addr::unchecked("prism_gov"),
+        }
+    }
+
+    fn staking_msg(sub_msg: SubMsg) -> InstantiateMsg {
+        match sub_msg.msg {
+            CosmosMsg::Wasm(WasmMsg::Instantiate { msg, .. }) => from_binary(&msg).unwrap(),
+            msg => panic!("unexpected message {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn staking_payout_modes() {
+        let env = mock_env();
+        let (inst_config, config) = (inst_config(), config());
+
+        let nyluna = staking_msg(instantiate_nyluna_staking(&env, &inst_config, &config).unwrap());
+        assert_eq!(nyluna.payout_mode, PayoutMode::Prism {});
+        assert!(nyluna.direct_bonding);
+
+        // only psi stakers buy nexPRISM, nexPRISM stakers get xPRISM
+        let nexprism =
+            staking_msg(instantiate_nexprism_staking(&env, &inst_config, &config).unwrap());
+        assert_eq!(
+            nexprism.payout_mode,
+            PayoutMode::Xprism {
+                prism_governance: "prism_gov".to_string(),
+                xprism_token: config.xprism_token.to_string(),
+            }
+        );
+
+        let psi = staking_msg(instantiate_psi_staking(&env, &inst_config, &config).unwrap());
+        assert_eq!(
+            psi.payout_mode,
+            PayoutMode::Nexprism {
+                prism_governance: "prism_gov".to_string(),
+                xprism_token: config.xprism_token.to_string(),
+                nexprism_xprism_pair: "nexprism_xprism_pair".to_string(),
+            }
+        );
+        assert_eq!(psi.stake_operators, vec![config.governance.to_string()]);
+        assert!(!psi.direct_bonding);
+    }
 }
@@ -4,6 +4,7 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::error::ContractError;
+use nexus_prism_protocol::vault::PostDepositAction;
 
 const CONFIG: Item<Config> = Item::new("config");
 pub const INST_CONFIG: Item<InstantiationConfig> = Item::new("inst_config");
@@ -11,6 +12,7 @@ const STATE: Item<State> = Item::new("state");
 const CLAIM_VIRTUAL_REWARDS_REPLY_CONTEXT: Item<ClaimVirtualRewardsReplyContext> =
     Item::new("claim_virt_rewards_reply_ctx");
 const PRISM_VESTING_STATE: Item<PrismVestingState> = Item::new("prism_vesting_state");
+const DEPOSIT_REPLY_CONTEXT: Item<DepositReplyContext> = Item::new("deposit_reply_ctx");
 pub const GOVERNANCE_UPDATE: Item<GovernanceUpdateState> = Item::new("gov_update");
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -23,14 +25,25 @@ pub struct Config {
     pub yluna_token: Addr,
     pub nyluna_token: Addr,
     pub prism_token: Addr,
+    #[serde(default)]
+    pub cluna_token: Option<Addr>,
+    #[serde(default)]
+    pub pluna_token: Option<Addr>,
 
     pub prism_launch_pool: Addr,
     pub prism_xprism_boost: Addr,
+    #[serde(default)]
+    pub prism_vault: Option<Addr>,
 
     pub nexprism_staking: Addr,
     pub psi_staking: Addr,
     pub nyluna_staking: Addr,
 
+    #[serde(default)]
+    pub nexprism_autocompounder: Option<Addr>,
+    #[serde(default)]
+    pub nyluna_autocompounder: Option<Addr>,
+
     pub prism_xprism_pair: Addr,
     pub prism_yluna_pair: Addr,
 
@@ -70,6 +83,8 @@ pub struct InstantiationConfig {
     pub cw20_token_code_id: u64,
     pub staking_code_id: u64,
     pub autocompounder_code_id: u64,
+    #[serde(default)]
+    pub autocompounder_reward_swap_max_spread: Decimal,
     pub astroport_factory: Addr,
     pub nexprism_xprism_pair: Addr,
     pub nexprism_xprism_amp_coef: u64,
@@ -177,3 +192,30 @@ pub fn save_locked_vested_prism_amount(store: &mut dyn Storage, amount: Uint128)
         },
     )
 }
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DepositReplyContext {
+    pub recipient: Addr,
+    pub xprism_balance_before: Uint128,
+    pub nexprism_balance_before: Uint128,
+    // nexPRISM bought with minted xPRISM must not be less than its 1:1 deposit
+    #[serde(default)]
+    pub minted_xprism: Uint128,
+    pub best_route: bool,
+    pub then: Option<PostDepositAction>,
+}
+
+pub fn load_deposit_reply_context(store: &dyn Storage) -> StdResult<DepositReplyContext> {
+    DEPOSIT_REPLY_CONTEXT.load(store)
+}
+
+pub fn save_deposit_reply_context(
+    store: &mut dyn Storage,
+    context: &DepositReplyContext,
+) -> StdResult<()> {
+    DEPOSIT_REPLY_CONTEXT.save(store, context)
+}
+
+pub fn remove_deposit_reply_context(store: &mut dyn Storage) {
+    DEPOSIT_REPLY_CONTEXT.remove(store)
+}
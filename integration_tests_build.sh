@@ -9,6 +9,7 @@ fi
 # You can add more contracts that are built with integration tests to this array
 contracts=(
     "nexus_prism_autocompounder"
+    "nexus_prism_router"
     "nexus_prism_staking"
     "nexus_prism_vault"
 )
@@ -1,7 +1,8 @@
+use astroport::router::SwapOperation;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::Uint128;
+use cosmwasm_std::{Decimal, Uint128};
 use cw20::Cw20ReceiveMsg;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -12,6 +13,8 @@ pub struct InstantiateMsg {
     pub governance: String,
     pub staking_contract: String,
     pub cw20_token_code_id: u64,
+    // must be greater than 0 and less than 1
+    pub reward_swap_max_spread: Decimal,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -29,6 +32,26 @@ pub enum GovernanceMsg {
     UpdateConfig {
         reward_compound_pair: Option<String>,
         staking_contract: Option<String>,
+        // null removes the router
+        #[serde(
+            default,
+            deserialize_with = "crate::common::deserialize_optional",
+            skip_serializing_if = "Option::is_none"
+        )]
+        astroport_router: Option<Option<String>>,
+        // route from reward token to compounding token through astroport router,
+        // empty route means selling through `reward_compound_pair`
+        reward_swap_operations: Option<Vec<SwapOperation>>,
+        reward_swap_max_spread: Option<Decimal>,
+        // rewards are held until their amount reaches this threshold
+        min_harvest_amount: Option<Uint128>,
+        // rewards above this amount are carried to the next compound, null removes the limit
+        #[serde(
+            default,
+            deserialize_with = "crate::common::deserialize_optional",
+            skip_serializing_if = "Option::is_none"
+        )]
+        max_swap_amount: Option<Option<Uint128>>,
     },
     UpdateGovernanceContract {
         gov_addr: String,
@@ -40,16 +63,33 @@ pub enum GovernanceMsg {
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum Cw20HookMsg {
-    Deposit {},
-    Withdraw {},
+    Deposit {
+        // sender by default
+        #[serde(default)]
+        recipient: Option<String>,
+    },
+    Withdraw {
+        // sender by default
+        #[serde(default)]
+        recipient: Option<String>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
     Config {},
-    AutoCompoundingTokenValue { amount: Uint128 },
-    CompoundingTokenValue { amount: Uint128 },
+    AutoCompoundingTokenValue {
+        amount: Uint128,
+    },
+    CompoundingTokenValue {
+        amount: Uint128,
+    },
+    State {},
+    Harvests {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -60,6 +100,11 @@ pub struct ConfigResponse {
     pub reward_compound_pair: String,
     pub governance: String,
     pub staking_contract: String,
+    pub astroport_router: Option<String>,
+    pub reward_swap_operations: Vec<SwapOperation>,
+    pub reward_swap_max_spread: Decimal,
+    pub min_harvest_amount: Uint128,
+    pub max_swap_amount: Option<Uint128>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -73,4 +118,35 @@ pub struct CompoundingTokenValueResponse {
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub struct MigrateMsg {}
+pub struct StateResponse {
+    pub last_harvest_time: u64,
+    pub harvests_count: u64,
+    pub total_rewards_claimed: Uint128,
+    pub total_rewards_sold: Uint128,
+    pub total_compounding_token_received: Uint128,
+    pub rewards_carried_over: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct HarvestResponse {
+    pub id: u64,
+    pub time: u64,
+    pub rewards_claimed: Uint128,
+    pub rewards_sold: Uint128,
+    pub compounding_token_received: Uint128,
+    // compounding token amount per one auto compounding token
+    pub share_price_before: Decimal,
+    pub share_price_after: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct HarvestsResponse {
+    pub harvests: Vec<HarvestResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MigrateMsg {
+    // required to migrate config stored without reward swap max spread
+    #[serde(default)]
+    pub reward_swap_max_spread: Option<Decimal>,
+}
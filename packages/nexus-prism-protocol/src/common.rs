@@ -3,7 +3,7 @@ use cosmwasm_storage::to_length_prefixed;
 use cw20::{Cw20ExecuteMsg, MinterResponse};
 use cw20_base::state::TokenInfo;
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 use cosmwasm_std::{
     to_binary, Addr, Binary, BlockInfo, CosmosMsg, Decimal, Deps, Order, QueryRequest, StdError,
@@ -38,6 +38,15 @@ macro_rules! cfg_addr {
     };
 }
 
+// For `Option<Option<T>>` config fields: missing field keeps the value, `null` clears it.
+pub fn deserialize_optional<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum OrderBy {
@@ -0,0 +1,191 @@
+use cosmwasm_bignumber::{Decimal256, Uint256};
+use cosmwasm_std::{Decimal, StdError, StdResult, Uint128};
+use integer_sqrt::IntegerSquareRoot;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::common::{div, mul};
+
+// Pure part of the vault rewards distribution logic, shared with off-chain tooling.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Value {
+    Zero,
+    Positive,
+    Negative,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RewardRatios {
+    pub nexprism_stakers: Decimal,
+    pub nyluna_stakers: Decimal,
+    pub psi_stakers: Decimal,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RatioLimits {
+    pub update_step: Decimal,
+    pub min_nexprism_stakers: Decimal,
+    pub max_nexprism_stakers: Decimal,
+    pub min_nyluna_stakers: Decimal,
+    pub max_nyluna_stakers: Decimal,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn calculate_inner(
+    base_ratio: Decimal256,
+    xprism: Uint256,
+    yluna_total: Uint256,
+    yluna: Uint256,
+    weight_total: Uint256,
+    weight: Uint256,
+    ampl: Uint256,
+    yluna_price: Decimal256,
+    xprism_price: Decimal256,
+) -> StdResult<Value> {
+    let ampl_amount = to_uint128(ampl, "boost")?;
+    let yluna_amount = to_uint128(yluna, "yLUNA")?;
+    to_uint128(yluna_total, "total yLUNA")?;
+    to_uint128(weight_total, "total boost weight")?;
+    to_uint128(xprism, "xPRISM")?;
+    if yluna_amount.is_zero() {
+        return Err(StdError::generic_err("no yLUNA bonded"));
+    }
+    if yluna > yluna_total {
+        return Err(StdError::generic_err("yLUNA bonded is greater than total"));
+    }
+    if weight > weight_total {
+        return Err(StdError::generic_err("boost weight is greater than total"));
+    }
+    // Decimal::from_ratio panics on ratios it can't hold
+    if Decimal256::from_ratio(ampl, yluna) > Decimal256::from(Decimal::MAX) {
+        return Err(StdError::generic_err("boost to yLUNA ratio is too big"));
+    }
+
+    // amounts fit into Uint128, so integer ratios can't overflow
+    let a = checked_mul(
+        base_ratio,
+        Decimal256::from_ratio(yluna_total - yluna, yluna_total * yluna_total),
+    )?;
+    let b = Decimal256::from_uint256(weight_total - weight);
+    let big_sqrt = ampl_amount.checked_mul(yluna_amount)?.u128().integer_sqrt();
+    let c = checked_add(b, Decimal256::from_uint256(big_sqrt))?;
+    let d = checked_div(
+        checked_mul(
+            checked_mul(checked_sub(Decimal256::one(), base_ratio)?, b)?,
+            Decimal256::from(Decimal::from_ratio(ampl_amount, yluna_amount).sqrt()),
+        )?,
+        checked_mul(checked_mul(Decimal256::from_uint256(2u64), c)?, c)?,
+    )?;
+    let e = checked_div(
+        checked_mul(
+            checked_mul(
+                d,
+                checked_div(
+                    Decimal256::from_uint256(yluna),
+                    Decimal256::from_uint256(xprism),
+                )?,
+            )?,
+            yluna_price,
+        )?,
+        xprism_price,
+    )?;
+
+    Ok(match checked_add(a, d)?.cmp(&e) {
+        std::cmp::Ordering::Greater => Value::Positive,
+        std::cmp::Ordering::Less => Value::Negative,
+        std::cmp::Ordering::Equal => Value::Zero,
+    })
+}
+
+// Decimal256 operators panic on overflow and division by zero.
+fn checked_add(a: Decimal256, b: Decimal256) -> StdResult<Decimal256> {
+    match a.0.overflowing_add(b.0) {
+        (sum, false) => Ok(Decimal256(sum)),
+        _ => Err(StdError::generic_err("distribution calculation overflow")),
+    }
+}
+
+fn checked_sub(a: Decimal256, b: Decimal256) -> StdResult<Decimal256> {
+    if b > a {
+        return Err(StdError::generic_err("distribution calculation underflow"));
+    }
+    Ok(a - b)
+}
+
+fn checked_mul(a: Decimal256, b: Decimal256) -> StdResult<Decimal256> {
+    match a.0.overflowing_mul(b.0) {
+        (product, false) => Ok(Decimal256(product / Decimal256::one().0)),
+        _ => Err(StdError::generic_err("distribution calculation overflow")),
+    }
+}
+
+fn checked_div(a: Decimal256, b: Decimal256) -> StdResult<Decimal256> {
+    if b.is_zero() {
+        return Err(StdError::generic_err(
+            "distribution calculation division by zero",
+        ));
+    }
+    match a.0.overflowing_mul(Decimal256::one().0) {
+        (numerator, false) => Ok(Decimal256(numerator / b.0)),
+        _ => Err(StdError::generic_err("distribution calculation overflow")),
+    }
+}
+
+fn to_uint128(value: Uint256, name: &str) -> StdResult<Uint128> {
+    if value > Uint256::from(u128::MAX) {
+        return Err(StdError::generic_err(format!(
+            "{} amount {} doesn't fit into Uint128",
+            name, value
+        )));
+    }
+    Ok(value.into())
+}
+
+// Moves nexPRISM stakers ratio one step, ratios are left as is if any limit is crossed.
+pub fn next_ratios(limits: &RatioLimits, ratios: &RewardRatios, value: Value) -> RewardRatios {
+    let mut new_ratios = ratios.clone();
+    match value {
+        Value::Negative => {
+            new_ratios.nexprism_stakers = mul(new_ratios.nexprism_stakers, limits.update_step);
+            if new_ratios.nexprism_stakers > limits.max_nexprism_stakers {
+                return ratios.clone();
+            }
+            new_ratios.nyluna_stakers =
+                Decimal::one() - new_ratios.nexprism_stakers - new_ratios.psi_stakers;
+            if new_ratios.nyluna_stakers < limits.min_nyluna_stakers {
+                return ratios.clone();
+            }
+        }
+        Value::Positive => {
+            new_ratios.nexprism_stakers = div(new_ratios.nexprism_stakers, limits.update_step);
+            if new_ratios.nexprism_stakers < limits.min_nexprism_stakers {
+                return ratios.clone();
+            }
+            new_ratios.nyluna_stakers =
+                Decimal::one() - new_ratios.nexprism_stakers - new_ratios.psi_stakers;
+            if new_ratios.nyluna_stakers > limits.max_nyluna_stakers {
+                return ratios.clone();
+            }
+        }
+        Value::Zero => {
+            return ratios.clone();
+        }
+    }
+
+    new_ratios
+}
+
+// psi stakers get the remainder, so nothing is lost on rounding.
+pub fn split_rewards(ratios: &RewardRatios, total_rewards: Uint128) -> (Uint128, Uint128, Uint128) {
+    let nexprism_stakers_rewards = total_rewards * ratios.nexprism_stakers;
+    let nyluna_stakers_rewards = total_rewards * ratios.nyluna_stakers;
+    let psi_stakers_rewards = total_rewards - nexprism_stakers_rewards - nyluna_stakers_rewards;
+
+    (
+        nexprism_stakers_rewards,
+        nyluna_stakers_rewards,
+        psi_stakers_rewards,
+    )
+}
@@ -0,0 +1,413 @@
+use std::str::FromStr;
+
+use cosmwasm_std::{Decimal, Event, StdError, StdResult, Uint128};
+
+use crate::staking::PayoutPreference;
+
+// Contracts emit them with `Response::add_event`, chain prefixes custom event types with "wasm-".
+pub trait TypedEvent: Sized {
+    const TYPE: &'static str;
+
+    fn into_event(self) -> Event;
+
+    fn from_event(event: &Event) -> StdResult<Self>;
+
+    fn matches(event: &Event) -> bool {
+        event.ty == Self::TYPE || event.ty.strip_prefix("wasm-") == Some(Self::TYPE)
+    }
+}
+
+// Decodes all events of type `T`, other events are skipped.
+pub fn parse_events<T: TypedEvent>(events: &[Event]) -> StdResult<Vec<T>> {
+    events
+        .iter()
+        .filter(|event| T::matches(event))
+        .map(T::from_event)
+        .collect()
+}
+
+fn attr<'a>(event: &'a Event, key: &str) -> StdResult<&'a str> {
+    event
+        .attributes
+        .iter()
+        .find(|attr| attr.key == key)
+        .map(|attr| attr.value.as_str())
+        .ok_or_else(|| StdError::parse_err(&event.ty, format!("missing attribute \"{}\"", key)))
+}
+
+fn string_attr(event: &Event, key: &str) -> StdResult<String> {
+    attr(event, key).map(String::from)
+}
+
+fn uint_attr(event: &Event, key: &str) -> StdResult<Uint128> {
+    Uint128::from_str(attr(event, key)?)
+}
+
+fn u64_attr(event: &Event, key: &str) -> StdResult<u64> {
+    attr(event, key)?
+        .parse()
+        .map_err(|_| StdError::parse_err(&event.ty, format!("invalid attribute \"{}\"", key)))
+}
+
+fn decimal_attr(event: &Event, key: &str) -> StdResult<Decimal> {
+    Decimal::from_str(attr(event, key)?)
+}
+
+// No preference is "default", rewards are paid in payout mode token then.
+fn preference_value(preference: Option<PayoutPreference>) -> &'static str {
+    match preference {
+        None => "default",
+        Some(PayoutPreference::Prism) => "prism",
+        Some(PayoutPreference::Xprism) => "xprism",
+        Some(PayoutPreference::Nexprism) => "nexprism",
+        Some(PayoutPreference::Restake) => "restake",
+    }
+}
+
+fn preference_attr(event: &Event, key: &str) -> StdResult<Option<PayoutPreference>> {
+    Ok(match attr(event, key)? {
+        "default" => None,
+        "prism" => Some(PayoutPreference::Prism),
+        "xprism" => Some(PayoutPreference::Xprism),
+        "nexprism" => Some(PayoutPreference::Nexprism),
+        "restake" => Some(PayoutPreference::Restake),
+        _ => {
+            return Err(StdError::parse_err(
+                &event.ty,
+                format!("invalid attribute \"{}\"", key),
+            ))
+        }
+    })
+}
+
+fn check_type<T: TypedEvent>(event: &Event) -> StdResult<()> {
+    if !T::matches(event) {
+        return Err(StdError::parse_err(
+            T::TYPE,
+            format!("unexpected event type \"{}\"", event.ty),
+        ));
+    }
+    Ok(())
+}
+
+// Defines an event struct with `TypedEvent` impl, attributes are named after fields
+// and encoded by their kind: string, uint, u64, decimal or preference.
+macro_rules! typed_event {
+    (@encode string, $value:expr) => {
+        $value
+    };
+    (@encode uint, $value:expr) => {
+        $value
+    };
+    (@encode u64, $value:expr) => {
+        $value.to_string()
+    };
+    (@encode decimal, $value:expr) => {
+        $value.to_string()
+    };
+    (@encode preference, $value:expr) => {
+        preference_value($value)
+    };
+    (@decode string, $event:expr, $key:expr) => {
+        string_attr($event, $key)
+    };
+    (@decode uint, $event:expr, $key:expr) => {
+        uint_attr($event, $key)
+    };
+    (@decode u64, $event:expr, $key:expr) => {
+        u64_attr($event, $key)
+    };
+    (@decode decimal, $event:expr, $key:expr) => {
+        decimal_attr($event, $key)
+    };
+    (@decode preference, $event:expr, $key:expr) => {
+        preference_attr($event, $key)
+    };
+    ($name:ident, $ty:literal, { $($field:ident: $field_ty:ty => $kind:ident),* $(,)? }) => {
+        #[derive(Clone, Debug, PartialEq)]
+        pub struct $name {
+            $(pub $field: $field_ty,)*
+        }
+
+        impl TypedEvent for $name {
+            const TYPE: &'static str = $ty;
+
+            fn into_event(self) -> Event {
+                Event::new(Self::TYPE)
+                    $(.add_attribute(stringify!($field), typed_event!(@encode $kind, self.$field)))*
+            }
+
+            fn from_event(event: &Event) -> StdResult<Self> {
+                check_type::<Self>(event)?;
+                Ok($name {
+                    $($field: typed_event!(@decode $kind, event, stringify!($field))?,)*
+                })
+            }
+        }
+    };
+    // same attributes under another event type
+    ($name:ident($inner:ident), $ty:literal) => {
+        #[derive(Clone, Debug, PartialEq)]
+        pub struct $name(pub $inner);
+
+        impl TypedEvent for $name {
+            const TYPE: &'static str = $ty;
+
+            fn into_event(self) -> Event {
+                let mut event = self.0.into_event();
+                event.ty = Self::TYPE.to_string();
+                event
+            }
+
+            fn from_event(event: &Event) -> StdResult<Self> {
+                check_type::<Self>(event)?;
+                let mut inner = event.clone();
+                inner.ty = $inner::TYPE.to_string();
+                Ok($name($inner::from_event(&inner)?))
+            }
+        }
+    };
+}
+
+// Vault rewards split between staking contracts, emitted as virtual or real ones.
+typed_event!(RewardsDistributed, "nexus_rewards_distributed", {
+    total: Uint128 => uint,
+    nexprism_stakers: Uint128 => uint,
+    nyluna_stakers: Uint128 => uint,
+    psi_stakers: Uint128 => uint,
+});
+
+typed_event!(
+    VirtualRewardsDistributed(RewardsDistributed),
+    "nexus_virtual_rewards_distributed"
+);
+
+typed_event!(
+    RealRewardsDistributed(RewardsDistributed),
+    "nexus_real_rewards_distributed"
+);
+
+// Virtual rewards registered by reward operator.
+typed_event!(StakingRewarded, "nexus_staking_rewarded", {
+    amount: Uint128 => uint,
+});
+
+typed_event!(StakingDonated, "nexus_staking_donated", {
+    donor: String => string,
+    amount: Uint128 => uint,
+});
+
+typed_event!(StakingBalanceChanged, "nexus_staking_balance_changed", {
+    staker: String => string,
+    old_balance: Uint128 => uint,
+    new_balance: Uint128 => uint,
+});
+
+// Stake operator update of many balances, per staker changes are left to hooks.
+typed_event!(StakingBalancesBatchUpdated, "nexus_staking_balances_batch_updated", {
+    stakers: u64 => u64,
+    total_increase: Uint128 => uint,
+    total_decrease: Uint128 => uint,
+    staking_total_balance: Uint128 => uint,
+});
+
+// Claimed rewards in reward token, before conversion to the payout token.
+typed_event!(StakingRewardsClaimed, "nexus_staking_rewards_claimed", {
+    staker: String => string,
+    recipient: String => string,
+    rewards: Uint128 => uint,
+});
+
+typed_event!(StakingSurplusCollected, "nexus_staking_surplus_collected", {
+    staker: String => string,
+    surplus: Uint128 => uint,
+});
+
+typed_event!(StakingLocked, "nexus_staking_locked", {
+    staker: String => string,
+    amount: Uint128 => uint,
+    unlock_time: u64 => u64,
+    multiplier: Decimal => decimal,
+});
+
+typed_event!(StakingLocksReleased, "nexus_staking_locks_released", {
+    staker: String => string,
+    released_boost: Uint128 => uint,
+});
+
+// Escrowed rewards in reward token, penalty is left to stakers on early exit.
+typed_event!(StakingVestedRewardsWithdrawn, "nexus_staking_vested_rewards_withdrawn", {
+    staker: String => string,
+    recipient: String => string,
+    rewards: Uint128 => uint,
+    penalty: Uint128 => uint,
+});
+
+typed_event!(StakingStakeTransferred, "nexus_staking_stake_transferred", {
+    staker: String => string,
+    recipient: String => string,
+    amount: Uint128 => uint,
+});
+
+typed_event!(StakingPayoutPreferenceSet, "nexus_staking_payout_preference_set", {
+    staker: String => string,
+    preference: Option<PayoutPreference> => preference,
+});
+
+// Vault token is minted 1:1 to the deposited one.
+typed_event!(VaultDeposited, "nexus_vault_deposited", {
+    recipient: String => string,
+    deposit_token: String => string,
+    amount: Uint128 => uint,
+});
+
+typed_event!(VaultWithdrawn, "nexus_vault_withdrawn", {
+    recipient: String => string,
+    withdraw_token: String => string,
+    amount: Uint128 => uint,
+});
+
+typed_event!(AutocompounderDeposited, "nexus_autocompounder_deposited", {
+    farmer: String => string,
+    compounding_token_amount: Uint128 => uint,
+});
+
+typed_event!(AutocompounderWithdrawn, "nexus_autocompounder_withdrawn", {
+    farmer: String => string,
+    compounding_token_amount: Uint128 => uint,
+    auto_compounding_token_burned: Uint128 => uint,
+});
+
+// Share prices are in compounding token per one auto compounding token.
+typed_event!(AutocompounderHarvested, "nexus_autocompounder_harvested", {
+    rewards_claimed: Uint128 => uint,
+    rewards_sold: Uint128 => uint,
+    compounding_token_received: Uint128 => uint,
+    share_price_before: Decimal => decimal,
+    share_price_after: Decimal => decimal,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_emitted_events() {
+        let claimed = StakingRewardsClaimed {
+            staker: "staker".to_string(),
+            recipient: "recipient".to_string(),
+            rewards: Uint128::new(100),
+        };
+        let mut on_chain = claimed.clone().into_event();
+        on_chain.ty = format!("wasm-{}", on_chain.ty);
+        let events = vec![
+            StakingRewarded {
+                amount: Uint128::new(5),
+            }
+            .into_event(),
+            on_chain,
+        ];
+
+        assert_eq!(
+            parse_events::<StakingRewardsClaimed>(&events).unwrap(),
+            vec![claimed]
+        );
+        assert_eq!(
+            parse_events::<StakingRewarded>(&events).unwrap(),
+            vec![StakingRewarded {
+                amount: Uint128::new(5)
+            }]
+        );
+        assert!(parse_events::<StakingDonated>(&events).unwrap().is_empty());
+    }
+
+    #[test]
+    fn string_attributes_round_trip() {
+        let deposited = VaultDeposited {
+            recipient: "recipient".to_string(),
+            deposit_token: "xprism".to_string(),
+            amount: Uint128::new(100),
+        };
+        assert_eq!(
+            VaultDeposited::from_event(&deposited.clone().into_event()).unwrap(),
+            deposited
+        );
+    }
+
+    #[test]
+    fn uint_attributes_round_trip() {
+        let distributed = RealRewardsDistributed(RewardsDistributed {
+            total: Uint128::new(100),
+            nexprism_stakers: Uint128::new(50),
+            nyluna_stakers: Uint128::new(30),
+            psi_stakers: Uint128::new(20),
+        });
+        let event = distributed.clone().into_event();
+        assert_eq!(event.ty, RealRewardsDistributed::TYPE);
+        assert_eq!(
+            RealRewardsDistributed::from_event(&event).unwrap(),
+            distributed
+        );
+        assert!(VirtualRewardsDistributed::from_event(&event).is_err());
+    }
+
+    #[test]
+    fn u64_attributes_round_trip() {
+        let updated = StakingBalancesBatchUpdated {
+            stakers: 3,
+            total_increase: Uint128::new(100),
+            total_decrease: Uint128::new(20),
+            staking_total_balance: Uint128::new(1080),
+        };
+        assert_eq!(
+            StakingBalancesBatchUpdated::from_event(&updated.clone().into_event()).unwrap(),
+            updated
+        );
+    }
+
+    #[test]
+    fn decimal_attributes_round_trip() {
+        let harvested = AutocompounderHarvested {
+            rewards_claimed: Uint128::new(100),
+            rewards_sold: Uint128::new(80),
+            compounding_token_received: Uint128::new(40),
+            share_price_before: Decimal::one(),
+            share_price_after: Decimal::percent(104),
+        };
+        assert_eq!(
+            AutocompounderHarvested::from_event(&harvested.clone().into_event()).unwrap(),
+            harvested
+        );
+    }
+
+    #[test]
+    fn preference_attributes_round_trip() {
+        for preference in [None, Some(PayoutPreference::Restake)] {
+            let set = StakingPayoutPreferenceSet {
+                staker: "staker".to_string(),
+                preference,
+            };
+            assert_eq!(
+                StakingPayoutPreferenceSet::from_event(&set.clone().into_event()).unwrap(),
+                set
+            );
+        }
+
+        let event = Event::new(StakingPayoutPreferenceSet::TYPE)
+            .add_attribute("staker", "staker")
+            .add_attribute("preference", "luna");
+        assert!(StakingPayoutPreferenceSet::from_event(&event).is_err());
+    }
+
+    #[test]
+    fn fail_to_parse_wrong_event() {
+        let event = StakingRewarded {
+            amount: Uint128::new(5),
+        }
+        .into_event();
+        assert!(StakingDonated::from_event(&event).is_err());
+
+        let event = Event::new(StakingDonated::TYPE).add_attribute("donor", "donor");
+        assert!(StakingDonated::from_event(&event).is_err());
+    }
+}
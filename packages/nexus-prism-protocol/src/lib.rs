@@ -1,4 +1,9 @@
 pub mod autocompounder;
 pub mod common;
+pub mod distribution;
+pub mod events;
+pub mod router;
 pub mod staking;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod vault;
@@ -0,0 +1,77 @@
+use cosmwasm_std::{Decimal, Uint128};
+use cw20::Cw20ReceiveMsg;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct InstantiateMsg {
+    pub vault: String,
+    pub prism_governance: String,
+    pub nexprism_xprism_pair: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecuteMsg {
+    Receive(Cw20ReceiveMsg),
+    Callback(CallbackMsg),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Cw20HookMsg {
+    // Operations are applied one by one to the amount received from the previous one,
+    // other router balances are not touched. Received tokens are sent to `to`
+    // (sender by default), it is required when tokens come from a staking contract.
+    ExecuteOperations {
+        operations: Vec<Operation>,
+        minimum_receive: Option<Uint128>,
+        to: Option<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    // PRISM -> xPRISM
+    MintXprism {},
+    // xPRISM -> nexPRISM, yLUNA -> nyLUNA
+    VaultDeposit {},
+    // xPRISM <-> nexPRISM
+    SwapNexprismXprism { max_spread: Option<Decimal> },
+    // nexPRISM/nyLUNA -> autocompounder share token
+    Autocompound {},
+    // nexPRISM/nyLUNA are bonded on behalf of `to`, has to be the last operation
+    Stake {},
+    // PRISM/xPRISM/nexPRISM rewards are converted to nexPRISM and bonded on behalf of `to`,
+    // has to be the last operation. Rewards are claimed into the router with nexPRISM
+    // staking `ClaimRewards { recipient: router, msg }` carrying `ExecuteOperations`.
+    ClaimAndRestake {},
+}
+
+// Router calls itself with these messages, so each of them
+// sees balances left by the previous one. Amount received by the last
+// operation is tracked by the router between them.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CallbackMsg {
+    ExecuteOperation { operation: Operation, to: String },
+    AssertMinimumReceive { minimum_receive: Uint128 },
+    SendReceived { to: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryMsg {
+    Config {},
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ConfigResponse {
+    pub vault: String,
+    pub prism_governance: String,
+    pub nexprism_xprism_pair: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MigrateMsg {}
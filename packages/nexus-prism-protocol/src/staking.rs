@@ -1,4 +1,4 @@
-use cosmwasm_std::{Decimal, Uint128};
+use cosmwasm_std::{Binary, Decimal, Uint128};
 use cw20::Cw20ReceiveMsg;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -7,16 +7,36 @@ use serde::{Deserialize, Serialize};
 pub struct InstantiateMsg {
     pub governance: String,
     pub staking_token: String,
-    pub stake_operator: Option<String>,
+    // staker balance is a sum of balances reported by operators and bonded directly
+    pub stake_operators: Vec<String>,
+    // allows bonding staking token to this contract
+    pub direct_bonding: bool,
     pub reward_token: String,
     pub reward_operator: String,
-    pub xprism_token: Option<String>,
-    pub prism_governance: Option<String>,
-    pub nexprism_xprism_pair: Option<String>,
+    pub payout_mode: PayoutMode,
+}
+
+// Currency rewards are paid in by default.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PayoutMode {
+    Prism {},
+    // reward token is exchanged for xPRISM in Prism governance
+    Xprism {
+        prism_governance: String,
+        xprism_token: String,
+    },
+    // minted xPRISM is sold for nexPRISM in the pair, or deposited to the vault below 1:1
+    Nexprism {
+        prism_governance: String,
+        xprism_token: String,
+        nexprism_xprism_pair: String,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
+#[allow(clippy::large_enum_variant)]
 pub enum ExecuteMsg {
     Receive(Cw20ReceiveMsg),
     Anyone { anyone_msg: AnyoneMsg },
@@ -28,45 +48,207 @@ pub enum ExecuteMsg {
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum Cw20HookMsg {
-    Bond {},
+    Bond {
+        #[serde(default)]
+        recipient: Option<String>,
+        // has to match one of `lockup_tiers`, locked tokens can't be unbonded until unlock,
+        // can't be set with `recipient` other than sender
+        #[serde(default)]
+        lock_duration: Option<u64>,
+    },
+    // Reward token top-up from anyone, registered as both real and virtual rewards,
+    // the way for partners not listed as reward operators to add rewards
+    Donate {},
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum AnyoneMsg {
-    Unbond { amount: Uint128 },
+    Unbond {
+        // all unlocked directly bonded tokens by default
+        #[serde(default)]
+        amount: Option<Uint128>,
+        // sender by default
+        #[serde(default)]
+        recipient: Option<String>,
+        // rewards are paid to `recipient` in the same transaction
+        #[serde(default)]
+        claim_rewards: bool,
+    },
+    // Moves unlocked stake with its future rewards, pending rewards stay with sender.
+    // Only directly bonded tokens can be moved, balances reported by stake operators can't.
+    TransferStake {
+        recipient: String,
+        amount: Uint128,
+    },
     UpdateGlobalIndex {},
-    ClaimRewards { recipient: Option<String> },
+    // With `msg` rewards are delivered to `recipient` contract with cw20 Send.
+    ClaimRewards {
+        recipient: Option<String>,
+        #[serde(default)]
+        msg: Option<Binary>,
+    },
+    // Removes expired locks of a staker, anyone can call it for any staker.
+    // Boost stops earning at unlock time without it, only older locks wait for the release.
+    ReleaseExpiredLocks {
+        address: String,
+    },
+    // Collects real rewards exceeding virtual ones of exited staker after grace period
+    CollectSurplus {
+        address: String,
+    },
     //Claim rewards for some address, rewards will be sent to it, not to sender!
-    ClaimRewardsForSomeone { address: String },
+    ClaimRewardsForSomeone {
+        address: String,
+    },
+    // None resets sender preference to the contract default
+    SetPayoutPreference {
+        preference: Option<PayoutPreference>,
+    },
+    // Pays vested part of escrowed rewards
+    WithdrawVestedRewards {
+        recipient: Option<String>,
+        msg: Option<Binary>,
+    },
+    // Pays all escrowed rewards, unvested ones with early exit penalty
+    ExitVesting {
+        recipient: Option<String>,
+        msg: Option<Binary>,
+    },
     AcceptGovernance {},
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PayoutPreference {
+    Prism,
+    Xprism,
+    // bought for xPRISM through the pair of `PayoutMode::Nexprism`, or minted 1:1 by the vault
+    Nexprism,
+    // rewards are converted to staking token and bonded for the recipient
+    Restake,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum RewardOperatorMsg {
     Reward { amount: Uint128 },
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum GovernanceMsg {
     UpdateConfig {
-        stake_operator: Option<String>,
+        // stakers balances are refreshed on their next balance change or claim,
+        // so operators should be changed before they hold any balances
+        stake_operators: Option<Vec<String>>,
+        direct_bonding: Option<bool>,
         reward_operator: Option<String>,
-        nexprism_xprism_pair: Option<String>,
+        // partners allowed to register rewards along with `reward_operator`, replaces the list
+        reward_operators: Option<Vec<String>>,
+        payout_mode: Option<PayoutMode>,
+        lockup_tiers: Option<Vec<LockupTier>>,
+        // null stops surplus collection
+        #[serde(
+            default,
+            deserialize_with = "crate::common::deserialize_optional",
+            skip_serializing_if = "Option::is_none"
+        )]
+        surplus_policy: Option<Option<SurplusPolicy>>,
+        // zero duration disables escrow, existing entries keep vesting
+        reward_vesting: Option<RewardVesting>,
+        // stakers paid without escrow, like autocompounder passing rewards to its users
+        escrow_exempt: Option<Vec<String>>,
     },
     UpdateGovernance {
         gov_addr: String,
         seconds_to_wait_for_accept_gov_tx: u64,
     },
+    // Hooks are called with `StakeChangedHookMsg` on every staker balance change
+    AddHook {
+        addr: String,
+    },
+    RemoveHook {
+        addr: String,
+    },
+    // Removes entries of stakers without balance, claimable rewards and payout preference,
+    // `last_staker` attribute is `start_after` of the next page
+    PruneStakers {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    // Updates the next page of staker entries after contract migration,
+    // entries used before the sweep reaches them are updated on use
+    MigrateStakers {
+        limit: Option<u32>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StakeChangedHookMsg {
+    StakeChangedHook {
+        staker: String,
+        old_balance: Uint128,
+        new_balance: Uint128,
+    },
+}
+
+// Real rewards of exited stakers above their virtual rewards can't be claimed,
+// they are given to `recipient` when `grace_period` (non-zero) seconds passed since exit.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SurplusPolicy {
+    pub grace_period: u64,
+    pub recipient: SurplusRecipient,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SurplusRecipient {
+    // rolled back into real rewards of current stakers
+    Stakers,
+    Address { address: String },
+}
+
+// Claimed rewards vest linearly for `duration` seconds when set, escrow belongs to staker
+// and is sent to a recipient only on withdrawal. Stake operators are not escrowed.
+// Penalty of early exit is redistributed to stakers as real rewards.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RewardVesting {
+    pub duration: u64,
+    pub early_exit_penalty: Decimal,
+}
+
+// Locked tokens are weighted with `multiplier` in rewards distribution.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LockupTier {
+    pub duration: u64,
+    pub multiplier: Decimal,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum StakeOperatorMsg {
-    IncreaseBalance { staker: String, amount: Uint128 },
-    DecreaseBalance { staker: String, amount: Uint128 },
+    IncreaseBalance {
+        staker: String,
+        amount: Uint128,
+    },
+    DecreaseBalance {
+        staker: String,
+        amount: Uint128,
+    },
+    // balances have to be already updated on stake operator side, each staker at most once
+    BatchUpdateBalances {
+        updates: Vec<(String, BalanceDelta)>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BalanceDelta {
+    Increase { amount: Uint128 },
+    Decrease { amount: Uint128 },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -84,26 +266,65 @@ pub enum QueryMsg {
         potential_rewards_total: Uint128,
         address: String,
     },
+    Locks {
+        address: String,
+    },
+    ClaimableRewards {
+        address: String,
+    },
+    Surplus {
+        address: String,
+    },
+    VestingEntries {
+        address: String,
+    },
+    Donations {
+        donor: String,
+    },
+    // Balances before any changes at `height`, recorded since snapshots were introduced
+    BalanceAt {
+        address: String,
+        height: u64,
+    },
+    TotalStakedAt {
+        height: u64,
+    },
+    Hooks {},
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct ConfigResponse {
     pub governance: String,
     pub staking_token: String,
-    pub stake_operator: Option<String>,
+    pub stake_operators: Vec<String>,
+    pub direct_bonding: bool,
     pub reward_token: String,
     pub reward_operator: String,
-    pub xprism_token: Option<String>,
-    pub prism_governance: Option<String>,
-    pub nexprism_xprism_pair: Option<String>,
+    pub reward_operators: Vec<String>,
+    pub payout_mode: PayoutMode,
+    pub lockup_tiers: Vec<LockupTier>,
+    pub surplus_policy: Option<SurplusPolicy>,
+    pub reward_vesting: Option<RewardVesting>,
+    pub escrow_exempt: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct StateResponse {
     pub staking_total_balance: Uint128,
+    // staking total balance with lockup boosts
+    pub total_weight: Uint128,
     pub virtual_reward_balance: Uint128,
     pub virtual_rewards: RewardStateResponse,
     pub real_rewards: RewardStateResponse,
+    // surplus of exited stakers not collected yet
+    pub stranded_surplus: Uint128,
+    pub total_donated: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DonationsResponse {
+    pub donor: String,
+    pub amount: Uint128,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -118,12 +339,92 @@ pub struct RewardsResponse {
     pub real_rewards: Uint128,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ClaimableRewardsResponse {
+    // min of real and virtual rewards
+    pub claimable: Uint128,
+    pub real_rewards: Uint128,
+    pub virtual_rewards: Uint128,
+    // none when real and virtual rewards are equal
+    pub limited_by: Option<RewardsLimit>,
+    // end of the nearest reward operator PRISM vesting, none if nothing is vesting
+    pub next_claimable_time: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RewardsLimit {
+    // virtual rewards wait for PRISM vesting to end
+    Real,
+    // real rewards are not registered by reward operator yet
+    Virtual,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct VestingEntryResponse {
+    pub amount: Uint128,
+    pub withdrawn: Uint128,
+    pub start_time: u64,
+    pub end_time: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct VestingEntriesResponse {
+    pub entries: Vec<VestingEntryResponse>,
+    // vested and not withdrawn yet
+    pub claimable: Uint128,
+    // not vested yet
+    pub locked: Uint128,
+    // part of `locked` kept on exit now
+    pub early_exit_penalty: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SurplusResponse {
+    pub surplus: Uint128,
+    // none while staker has balance
+    pub exited_at: Option<u64>,
+    pub collectable_at: Option<u64>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct StakerResponse {
     pub address: String,
     pub balance: Uint128,
     pub virtual_pending_rewards: Decimal,
     pub real_pending_rewards: Decimal,
+    pub payout_preference: Option<PayoutPreference>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LockResponse {
+    pub amount: Uint128,
+    pub unlock_time: u64,
+    pub multiplier: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LocksResponse {
+    pub locks: Vec<LockResponse>,
+    // amount that can't be unbonded now
+    pub locked_amount: Uint128,
+    // balance with boosts of not released locks
+    pub weight: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BalanceAtResponse {
+    pub balance: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TotalStakedAtResponse {
+    pub total_staked: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct HooksResponse {
+    pub hooks: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -132,7 +433,11 @@ pub struct PotentialRewardsResponse {
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub struct MigrateMsg {}
+pub struct MigrateMsg {
+    // required to migrate xPRISM payout configured without xPRISM token
+    #[serde(default)]
+    pub xprism_token: Option<String>,
+}
 
 // ================================
 
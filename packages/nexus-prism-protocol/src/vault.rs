@@ -11,6 +11,8 @@ pub struct InstantiateMsg {
     pub cw20_token_code_id: u64,
     pub staking_code_id: u64,
     pub autocompounder_code_id: u64,
+    // max spread of autocompounders reward swaps
+    pub autocompounder_reward_swap_max_spread: Decimal,
 
     pub astroport_factory: String,
     pub nexprism_xprism_amp_coef: u64,
@@ -19,8 +21,11 @@ pub struct InstantiateMsg {
     pub prism_token: String,
     pub xprism_token: String,
     pub yluna_token: String,
+    pub cluna_token: Option<String>,
+    pub pluna_token: Option<String>,
 
     pub prism_governance: String,
+    pub prism_vault: Option<String>,
     pub prism_launch_pool: String,
     pub prism_xprism_boost: String,
 
@@ -47,7 +52,7 @@ pub enum ExecuteMsg {
     Receive(Cw20ReceiveMsg),
     ClaimAllRewards {},
     Myself { msg: MyselfMsg },
-    Owner { msg: OwnerMsg },
+    Owner { msg: Box<OwnerMsg> },
     Governance { msg: GovernanceMsg },
     AcceptGovernance {},
 }
@@ -57,8 +62,31 @@ pub enum ExecuteMsg {
 pub enum Cw20HookMsg {
     // You have to deposit at least 1 yLUNA before depositing any xPRISM.
     // yLUNA is required for boost activating.
-    Deposit {},
-    Withdraw {},
+    // PRISM is minted to xPRISM in Prism governance first. With `best_route`
+    // it is swapped on nexPRISM/xPRISM pair instead, if the pair gives more nexPRISM.
+    // cLUNA is split in Prism vault, pLUNA is returned to recipient and yLUNA is deposited.
+    Deposit {
+        // sender by default
+        #[serde(default)]
+        recipient: Option<String>,
+        #[serde(default)]
+        then: Option<PostDepositAction>,
+        #[serde(default)]
+        best_route: bool,
+    },
+    Withdraw {
+        // sender by default
+        #[serde(default)]
+        recipient: Option<String>,
+    },
+}
+
+// What to do with minted nexPRISM/nyLUNA on behalf of recipient.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PostDepositAction {
+    Stake {},
+    Autocompound {},
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -85,6 +113,13 @@ pub enum OwnerMsg {
 
         prism_launch_pool: Option<String>,
         prism_xprism_boost: Option<String>,
+        prism_vault: Option<String>,
+
+        cluna_token: Option<String>,
+        pluna_token: Option<String>,
+
+        nexprism_autocompounder: Option<String>,
+        nyluna_autocompounder: Option<String>,
 
         prism_xprism_pair: Option<String>,
         prism_yluna_pair: Option<String>,
@@ -137,6 +172,7 @@ pub enum QueryMsg {
         staking_contract: StakingContract,
         user_addr: String,
     },
+    PrismVestingSchedules {},
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -157,14 +193,20 @@ pub struct ConfigResponse {
     pub yluna_token: String,
     pub nyluna_token: String,
     pub prism_token: String,
+    pub cluna_token: Option<String>,
+    pub pluna_token: Option<String>,
 
     pub prism_launch_pool: String,
     pub prism_xprism_boost: String,
+    pub prism_vault: Option<String>,
 
     pub nexprism_staking: String,
     pub psi_staking: String,
     pub nyluna_staking: String,
 
+    pub nexprism_autocompounder: Option<String>,
+    pub nyluna_autocompounder: Option<String>,
+
     pub prism_xprism_pair: String,
     pub prism_yluna_pair: String,
 
@@ -199,5 +241,16 @@ pub struct PotentialRewardsResponse {
     pub rewards: Uint128,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PrismVestingScheduleResponse {
+    pub end_time: u64,
+    pub amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PrismVestingSchedulesResponse {
+    pub schedules: Vec<PrismVestingScheduleResponse>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct MigrateMsg {}
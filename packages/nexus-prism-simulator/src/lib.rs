@@ -0,0 +1,318 @@
+use cosmwasm_std::{Decimal, Uint128};
+use nexus_prism_protocol::distribution::{
+    calculate_inner, next_ratios, split_rewards, RatioLimits, RewardRatios, Value,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SimulationError {
+    #[error("Initial reward ratios must sum up to one")]
+    InvalidInitialRatios {},
+
+    #[error("Snapshots must be ordered by time, snapshot {index} is out of order")]
+    UnorderedSnapshots { index: usize },
+
+    #[error("Snapshot {index} can't be evaluated: {reason}")]
+    InvalidSnapshot { index: usize, reason: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SimulationInput {
+    pub limits: RatioLimits,
+    pub initial_ratios: RewardRatios,
+    // Same as vault `rewards_distribution_update_period_secs`, every snapshot triggers an update if unset.
+    pub update_period_secs: Option<u64>,
+    pub snapshots: Vec<Snapshot>,
+}
+
+// Vault position in Prism launch pool and xPRISM boost, as returned by their queries.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub time: u64,
+    pub base_pool_ratio: Decimal,
+    pub xprism_bonded: Uint128,
+    pub yluna_total_bonded: Uint128,
+    pub yluna_bonded: Uint128,
+    pub boost_total_weight: Uint128,
+    pub boost_weight: Uint128,
+    pub active_boost: Uint128,
+    // Prices in PRISM.
+    pub yluna_price: Decimal,
+    pub xprism_price: Decimal,
+    // Rewards distributed with ratios in effect at this snapshot.
+    #[serde(default)]
+    pub rewards: Uint128,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SimulationStep {
+    pub time: u64,
+    pub value: Option<Value>,
+    pub ratios: RewardRatios,
+    pub rewards: StakersRewards,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StakersRewards {
+    pub nexprism_stakers: Uint128,
+    pub nyluna_stakers: Uint128,
+    pub psi_stakers: Uint128,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SimulationOutput {
+    pub trajectory: Vec<SimulationStep>,
+    pub total_rewards: StakersRewards,
+    // Part of all simulated rewards received by each staker group, zero if there were no rewards.
+    pub reward_shares: RewardRatios,
+}
+
+impl Snapshot {
+    fn validate(&self, index: usize) -> Result<(), SimulationError> {
+        let invalid = |reason: &str| {
+            Err(SimulationError::InvalidSnapshot {
+                index,
+                reason: reason.to_string(),
+            })
+        };
+
+        if self.base_pool_ratio > Decimal::one() {
+            return invalid("base pool ratio is greater than one");
+        }
+        if self.xprism_bonded.is_zero() {
+            return invalid("no xPRISM bonded");
+        }
+        if self.yluna_bonded.is_zero() {
+            return invalid("no yLUNA bonded");
+        }
+        if self.yluna_bonded > self.yluna_total_bonded {
+            return invalid("yLUNA bonded is greater than total");
+        }
+        if self.boost_weight > self.boost_total_weight {
+            return invalid("boost weight is greater than total");
+        }
+        if self.boost_weight == self.boost_total_weight && self.active_boost.is_zero() {
+            return invalid("no boost weight outside of vault and no active boost");
+        }
+        if self.yluna_price.is_zero() || self.xprism_price.is_zero() {
+            return invalid("zero price");
+        }
+        Ok(())
+    }
+
+    fn value(&self, index: usize) -> Result<Value, SimulationError> {
+        calculate_inner(
+            self.base_pool_ratio.into(),
+            self.xprism_bonded.into(),
+            self.yluna_total_bonded.into(),
+            self.yluna_bonded.into(),
+            self.boost_total_weight.into(),
+            self.boost_weight.into(),
+            self.active_boost.into(),
+            self.yluna_price.into(),
+            self.xprism_price.into(),
+        )
+        .map_err(|e| SimulationError::InvalidSnapshot {
+            index,
+            reason: e.to_string(),
+        })
+    }
+}
+
+// Replays snapshots the way vault updates rewards distribution on-chain.
+pub fn simulate(input: &SimulationInput) -> Result<SimulationOutput, SimulationError> {
+    let initial = &input.initial_ratios;
+    if initial.nexprism_stakers + initial.nyluna_stakers + initial.psi_stakers != Decimal::one() {
+        return Err(SimulationError::InvalidInitialRatios {});
+    }
+
+    let mut ratios = input.initial_ratios.clone();
+    let mut last_calculation_time: Option<u64> = None;
+    let mut trajectory = Vec::with_capacity(input.snapshots.len());
+    let mut total_rewards = StakersRewards::default();
+
+    for (index, snapshot) in input.snapshots.iter().enumerate() {
+        if index > 0 && snapshot.time < input.snapshots[index - 1].time {
+            return Err(SimulationError::UnorderedSnapshots { index });
+        }
+
+        let update_due = match (input.update_period_secs, last_calculation_time) {
+            (Some(period), Some(last_time)) => last_time + period <= snapshot.time,
+            _ => true,
+        };
+
+        let mut value = None;
+        if update_due {
+            snapshot.validate(index)?;
+            let cur_value = snapshot.value(index)?;
+            ratios = next_ratios(&input.limits, &ratios, cur_value);
+            last_calculation_time = Some(snapshot.time);
+            value = Some(cur_value);
+        }
+
+        let (nexprism_stakers, nyluna_stakers, psi_stakers) =
+            split_rewards(&ratios, snapshot.rewards);
+        total_rewards.nexprism_stakers += nexprism_stakers;
+        total_rewards.nyluna_stakers += nyluna_stakers;
+        total_rewards.psi_stakers += psi_stakers;
+
+        trajectory.push(SimulationStep {
+            time: snapshot.time,
+            value,
+            ratios: ratios.clone(),
+            rewards: StakersRewards {
+                nexprism_stakers,
+                nyluna_stakers,
+                psi_stakers,
+            },
+        });
+    }
+
+    let total =
+        total_rewards.nexprism_stakers + total_rewards.nyluna_stakers + total_rewards.psi_stakers;
+    let share = |amount: Uint128| {
+        if total.is_zero() {
+            Decimal::zero()
+        } else {
+            Decimal::from_ratio(amount, total)
+        }
+    };
+    let reward_shares = RewardRatios {
+        nexprism_stakers: share(total_rewards.nexprism_stakers),
+        nyluna_stakers: share(total_rewards.nyluna_stakers),
+        psi_stakers: share(total_rewards.psi_stakers),
+    };
+
+    Ok(SimulationOutput {
+        trajectory,
+        total_rewards,
+        reward_shares,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn dec(value: &str) -> Decimal {
+        Decimal::from_str(value).unwrap()
+    }
+
+    fn input(snapshots: Vec<Snapshot>) -> SimulationInput {
+        SimulationInput {
+            limits: RatioLimits {
+                update_step: dec("1.1"),
+                min_nexprism_stakers: dec("0.1"),
+                max_nexprism_stakers: dec("0.6"),
+                min_nyluna_stakers: dec("0.1"),
+                max_nyluna_stakers: dec("0.8"),
+            },
+            initial_ratios: RewardRatios {
+                nexprism_stakers: dec("0.5"),
+                nyluna_stakers: dec("0.3"),
+                psi_stakers: dec("0.2"),
+            },
+            update_period_secs: Some(10),
+            snapshots,
+        }
+    }
+
+    fn snapshot(time: u64, xprism_price: &str) -> Snapshot {
+        Snapshot {
+            time,
+            base_pool_ratio: dec("0.8"),
+            xprism_bonded: Uint128::new(1_000_000),
+            yluna_total_bonded: Uint128::new(10_000_000),
+            yluna_bonded: Uint128::new(1_000_000),
+            boost_total_weight: Uint128::new(10_000_000),
+            boost_weight: Uint128::new(1_000_000),
+            active_boost: Uint128::new(1_000_000),
+            yluna_price: Decimal::one(),
+            xprism_price: dec(xprism_price),
+            rewards: Uint128::new(1_000),
+        }
+    }
+
+    #[test]
+    fn ratios_follow_vault_rules() {
+        let input = input(vec![
+            snapshot(0, "1"),
+            snapshot(5, "1"),
+            snapshot(10, "1"),
+            snapshot(20, "1"),
+        ]);
+        let output = simulate(&input).unwrap();
+
+        let first = &output.trajectory[0];
+        let expected = next_ratios(
+            &input.limits,
+            &input.initial_ratios,
+            input.snapshots[0].value(0).unwrap(),
+        );
+        assert_eq!(first.value, Some(input.snapshots[0].value(0).unwrap()));
+        assert_eq!(first.ratios, expected);
+
+        // update period isn't passed yet
+        assert_eq!(output.trajectory[1].value, None);
+        assert_eq!(output.trajectory[1].ratios, expected);
+        assert!(output.trajectory[2].value.is_some());
+
+        for step in output.trajectory.iter() {
+            let ratios = &step.ratios;
+            assert_eq!(
+                ratios.nexprism_stakers + ratios.nyluna_stakers + ratios.psi_stakers,
+                Decimal::one()
+            );
+            assert!(ratios.nexprism_stakers <= input.limits.max_nexprism_stakers);
+            assert!(ratios.nexprism_stakers >= input.limits.min_nexprism_stakers);
+        }
+
+        let total = output.total_rewards.nexprism_stakers
+            + output.total_rewards.nyluna_stakers
+            + output.total_rewards.psi_stakers;
+        assert_eq!(total, Uint128::new(4_000));
+        // psi stakers get rounding remainders
+        assert!(output.reward_shares.psi_stakers >= dec("0.2"));
+    }
+
+    #[test]
+    fn reject_invalid_snapshots() {
+        let mut bad = snapshot(10, "1");
+        bad.yluna_bonded = Uint128::new(20_000_000);
+        assert_eq!(
+            simulate(&input(vec![snapshot(0, "1"), bad])),
+            Err(SimulationError::InvalidSnapshot {
+                index: 1,
+                reason: "yLUNA bonded is greater than total".to_string(),
+            })
+        );
+
+        let mut bad = snapshot(10, "1");
+        bad.yluna_total_bonded = Uint128::MAX;
+        bad.yluna_bonded = Uint128::MAX;
+        bad.active_boost = Uint128::MAX;
+        assert!(matches!(
+            simulate(&input(vec![snapshot(0, "1"), bad])),
+            Err(SimulationError::InvalidSnapshot { index: 1, .. })
+        ));
+
+        // squared boost weights don't fit into Decimal256
+        let mut bad = snapshot(10, "1");
+        bad.boost_total_weight = Uint128::new(1_000_000_000_000_000_000_000_000);
+        assert_eq!(
+            simulate(&input(vec![snapshot(0, "1"), bad])),
+            Err(SimulationError::InvalidSnapshot {
+                index: 1,
+                reason: "Generic error: distribution calculation overflow".to_string(),
+            })
+        );
+
+        assert_eq!(
+            simulate(&input(vec![snapshot(10, "1"), snapshot(0, "1")])),
+            Err(SimulationError::UnorderedSnapshots { index: 1 })
+        );
+    }
+}
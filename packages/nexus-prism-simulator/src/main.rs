@@ -0,0 +1,28 @@
+use std::{env, fs, io::Read, process};
+
+use nexus_prism_simulator::{simulate, SimulationInput};
+
+// Usage: nexus-prism-simulator [input.json], input is read from stdin if no file given.
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let input = match env::args().nth(1) {
+        Some(path) => fs::read_to_string(path)?,
+        None => {
+            let mut input = String::new();
+            std::io::stdin().read_to_string(&mut input)?;
+            input
+        }
+    };
+
+    let input: SimulationInput = serde_json::from_str(&input)?;
+    let output = simulate(&input)?;
+    println!("{}", serde_json::to_string_pretty(&output)?);
+
+    Ok(())
+}